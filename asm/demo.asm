@@ -0,0 +1,67 @@
+; Demo program
+;
+; Moves values between registers and memory and does some arithmetic, step
+; through it with `16-bit-vm run demo.bin --debug` to see the registers
+; change. `16-bit-vm asm asm/demo.asm -l demo.lst` lists the address and
+; bytes of every instruction.
+
+; Memory the demo reads and writes
+.define VALUES $0F00
+
+start:
+    ; Move values in registers and memory
+    mov $FFFF, r1
+    mov $EEEE, r2
+    mov $DDDD, &[VALUES]
+    mov $CCCC, &[VALUES + 2]
+
+    ; Read memory with an offset from register 3
+    mov $0002, r3
+    mov VALUES, &r3, r4
+    mov $0000, &r3, r7
+
+    ; Copy registers
+    mov r1, r5
+    mov r2, r6
+
+    ; Write registers to memory
+    mov r1, &[VALUES + 4]
+    mov r2, &[VALUES + 6]
+
+    ; Read memory registers point at
+    mov &r4, r6
+    mov &r3, r5
+
+    ; Read memory, also at an odd address
+    mov &[VALUES], r8
+    mov &[VALUES + 1], r7
+
+reset:
+    mov $0000, r1
+    mov $0000, r2
+    mov $0000, r3
+    mov $0000, r4
+    mov $0000, r5
+    mov $0000, r6
+    mov $0000, r7
+    mov $0000, r8
+
+arithmetic:
+    mov $0008, r1
+    mov $0008, r2
+    add r1, r2
+    add $00FF, r2
+    sub $0001, r2
+    sub r1, r2
+    sub $0005, r2
+    mul $0002, r2
+    mul r2, r1
+    inc r2
+    dec r2
+
+shift:
+    lsh r2, $0001
+    lsh r2, $0001
+    mov $0002, r3
+    rsh r2, r3
+    hlt
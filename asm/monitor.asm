@@ -0,0 +1,419 @@
+; Monitor, the ROM booted when no image is given
+;
+; Reads commands from the keyboard and shows memory on the screen, after the
+; Apple II's Wozmon. A line holds hex numbers and commands, one after the
+; other:
+;
+;     0300            show the byte at $0300
+;     0300.030F       show the bytes from $0300 up to $030F
+;     0300: 01 02     write bytes from $0300 on
+;     0300 R          call the program at $0300, it returns with `rts $0000`
+;     L               load Intel HEX from the serial port, prints OK or ERR
+;
+; Anything else prints "?". The monitor halts when the keyboard input ends.
+;
+; It runs on machines with the devices below at the same addresses as the
+; default machine, writable memory in the variables page and the stack page.
+
+; Devices
+.define SCREEN $3000
+.define KEYBOARD $3100
+.define SERIAL $3102
+
+; Status bits of the keyboard and serial port, data is at the next address
+.define READY $0000
+.define CLOSED $0001
+
+; Screen size, row 0 shares its terminal line with row 1 and isn't used
+.define COLUMNS 16
+.define ROWS 16
+
+; Variables, in the page below the screen
+.define ROW $2F00
+.define COLUMN $2F02
+.define EXAMINE $2F04
+.define STORE $2F06
+.define MODE $2F08
+.define LINE $2F10
+.define LINE_SIZE 48
+
+; What a number on a line does
+.define MODE_EXAMINE 0
+.define MODE_RANGE 1
+.define MODE_STORE 2
+
+; Stack, the top of the stack device
+.define STACK $FFFE
+.define STACK_LIMIT $FF00
+
+.org $F000
+
+reset:
+    mov STACK, sb
+    mov STACK_LIMIT, sl
+    mov8 $FF, &[SCREEN]
+    mov $0001, &[ROW]
+    mov $0000, &[COLUMN]
+    mov $0000, &[EXAMINE]
+    mov $0000, &[STORE]
+    mov !welcome, r6
+    jsr !puts
+
+; Read and run a line, errors jump back here with anything on the stack
+prompt:
+    mov STACK, sp
+    mov STACK, fp
+    mov '\\', r1
+    jsr !putc
+    jsr !readline
+    mov LINE, r4
+    mov MODE_EXAMINE, &[MODE]
+
+command:
+    jsr !skip_spaces
+    mov $0000, acc
+    jeq r1, &[!prompt]
+    mov '.', acc
+    jeq r1, &[!command_range]
+    mov ':', acc
+    jeq r1, &[!command_store]
+    and r1, $00DF
+    mov acc, r2
+    mov 'R', acc
+    jeq r2, &[!command_run]
+    mov 'L', acc
+    jeq r2, &[!load]
+
+    ; Number, what it does depends on the mode
+    jsr !parse_hex
+    mov $0000, acc
+    jeq r6, &[!error]
+    mov &[MODE], r7
+    mov MODE_STORE, acc
+    jeq r7, &[!command_deposit]
+    mov MODE_RANGE, acc
+    jeq r7, &[!command_dump]
+
+    ; Show the byte at an address, unless a range or store follows
+    mov r5, &[EXAMINE]
+    mov r5, &[STORE]
+    jsr !skip_spaces
+    mov '.', acc
+    jeq r1, &[!command]
+    mov ':', acc
+    jeq r1, &[!command]
+    mov r5, r7
+    jsr !dump
+    jeq acc, &[!command]
+
+; Show the bytes from the examined address up to the number
+command_dump:
+    mov r5, r7
+    mov &[EXAMINE], r5
+    jsr !dump
+    mov MODE_EXAMINE, &[MODE]
+    jeq acc, &[!command]
+
+; Write the number to the next store address
+command_deposit:
+    mov &[STORE], r8
+    mov8 r5, &[r8+]
+    mov r8, &[STORE]
+    jeq acc, &[!command]
+
+command_range:
+    mov MODE_RANGE, &[MODE]
+    inc r4
+    jeq acc, &[!command]
+
+command_store:
+    mov MODE_STORE, &[MODE]
+    inc r4
+    jeq acc, &[!command]
+
+; Call the program at the examined address
+command_run:
+    mov &[EXAMINE], r1
+    jsr r1
+    jeq acc, &[!prompt]
+
+; Load Intel HEX records from the serial port until the end record, r7 sums
+; the bytes of a record for the checksum, r5 is the address and r4 the type
+load:
+    jsr !serial_getc
+    mov ':', acc
+    jne r1, &[!load]
+    mov $0000, r7
+    jsr !serial_byte
+    mov r1, r6
+    jsr !serial_byte
+    lsh r1, $0008
+    mov r1, r5
+    jsr !serial_byte
+    or r5, r1
+    mov acc, r5
+    jsr !serial_byte
+    mov r1, r4
+
+    ; Data, only written by data records
+load_data:
+    mov $0000, acc
+    jeq r6, &[!load_checksum]
+    jsr !serial_byte
+    mov $0000, acc
+    jne r4, &[!load_next]
+    mov8 r1, &[r5+]
+load_next:
+    dec r6
+    jeq acc, &[!load_data]
+
+    ; The bytes of a record sum to 0
+load_checksum:
+    jsr !serial_byte
+    and r7, $00FF
+    jne $0000, &[!load_error]
+    mov $0001, acc
+    jne r4, &[!load]
+    mov !ok, r6
+    jsr !puts
+    jeq acc, &[!prompt]
+
+load_error:
+    mov !failed, r6
+    jsr !puts
+    jeq acc, &[!prompt]
+
+error:
+    mov !unknown, r6
+    jsr !puts
+    jeq acc, &[!prompt]
+
+; Print the bytes from r5 up to r7, four to a line
+dump:
+    mov r5, acc
+    jge r7, &[!dump_start]
+    mov r5, r7
+dump_start:
+    mov r5, r8
+    jsr !print_address
+dump_byte:
+    mov8 &[r8], r1
+    jsr !print_byte
+    mov r7, acc
+    jeq r8, &[!dump_end]
+    inc r8
+    and r8, $0003
+    jeq $0000, &[!dump_line]
+    mov ' ', r1
+    jsr !putc
+    jeq acc, &[!dump_byte]
+dump_line:
+    mov $000A, r1
+    jsr !putc
+    jsr !print_address
+    jeq acc, &[!dump_byte]
+dump_end:
+    mov $000A, r1
+    jeq acc, &[!putc]
+
+; Print r8 as an address, "0300:"
+print_address:
+    mov r8, r1
+    rsh r1, $0008
+    jsr !print_byte
+    and r8, $00FF
+    mov acc, r1
+    jsr !print_byte
+    mov ':', r1
+    jeq acc, &[!putc]
+
+; Print r1 as two hex digits
+print_byte:
+    psh r1
+    rsh r1, $0004
+    jsr !print_digit
+    pop r1
+    jeq acc, &[!print_digit]
+
+; Print the low four bits of r1 as a hex digit
+print_digit:
+    and r1, $000F
+    mov acc, r1
+    mov $0009, acc
+    jgt r1, &[!print_letter]
+    add $0030, r1
+    mov acc, r1
+    jeq acc, &[!putc]
+print_letter:
+    add $0037, r1
+    mov acc, r1
+    jeq acc, &[!putc]
+
+; Print the string r6 points at
+puts:
+    mov8 &[r6+], r1
+    mov $0000, acc
+    jeq r1, &[!puts_end]
+    jsr !putc
+    jeq acc, &[!puts]
+puts_end:
+    rts $0000
+
+; Print r1 at the cursor, a newline moves to the next row, uses r2 and r3
+putc:
+    mov $000A, acc
+    jeq r1, &[!newline]
+
+    ; Wrap when the row is full
+    mov &[COLUMN], r3
+    mov COLUMNS, acc
+    jne r3, &[!putc_write]
+    jsr !newline
+    mov $0000, r3
+putc_write:
+    mov &[ROW], r2
+    lsh r2, $0004
+    add r2, r3
+    add SCREEN, acc
+    mov acc, r2
+    mov8 r1, &[r2]
+    inc r3
+    mov r3, &[COLUMN]
+    rts $0000
+
+; Move the cursor to the next row, clear the screen after the last one
+newline:
+    mov $0000, &[COLUMN]
+    mov &[ROW], r2
+    inc r2
+    mov r2, &[ROW]
+    mov ROWS, acc
+    jeq r2, &[!clear]
+    rts $0000
+clear:
+    mov8 $FF, &[SCREEN]
+    mov $0001, &[ROW]
+    rts $0000
+
+; Read a line to LINE and end it with a 0, echoing it on the screen
+readline:
+    mov LINE, r4
+readline_next:
+    jsr !getc
+    mov $000A, acc
+    jeq r1, &[!readline_end]
+    mov $000D, acc
+    jeq r1, &[!readline_next]
+    mov LINE + LINE_SIZE - 1, acc
+    jeq r4, &[!readline_next]
+    mov8 r1, &[r4+]
+    jsr !putc
+    jeq acc, &[!readline_next]
+readline_end:
+    mov $0000, r5
+    mov8 r5, &[r4]
+    jeq acc, &[!putc]
+
+; Move r4 past spaces, r1 is the character it stops at
+skip_spaces:
+    mov8 &[r4], r1
+    mov ' ', acc
+    jne r1, &[!skip_spaces_end]
+    inc r4
+    jeq acc, &[!skip_spaces]
+skip_spaces_end:
+    rts $0000
+
+; Read hex digits at r4 to r5, r6 is the number of digits
+parse_hex:
+    mov $0000, r5
+    mov $0000, r6
+parse_hex_next:
+    mov8 &[r4], r1
+    jsr !hex_digit
+    mov $FFFF, acc
+    jeq r1, &[!parse_hex_end]
+    lsh r5, $0004
+    or r5, r1
+    mov acc, r5
+    inc r4
+    inc r6
+    jeq acc, &[!parse_hex_next]
+parse_hex_end:
+    rts $0000
+
+; Turn the hex digit in r1 into its value, $FFFF if it isn't one
+hex_digit:
+    mov '0', acc
+    jlt r1, &[!hex_digit_bad]
+    mov '9', acc
+    jle r1, &[!hex_digit_number]
+    and r1, $00DF
+    mov acc, r1
+    mov 'A', acc
+    jlt r1, &[!hex_digit_bad]
+    mov 'F', acc
+    jgt r1, &[!hex_digit_bad]
+    sub r1, $0037
+    mov acc, r1
+    rts $0000
+hex_digit_number:
+    sub r1, $0030
+    mov acc, r1
+    rts $0000
+hex_digit_bad:
+    mov $FFFF, r1
+    rts $0000
+
+; Wait for a key and read it to r1, halt when the input has ended
+getc:
+    mov8 &[KEYBOARD], r1
+    tst r1, READY
+    jeq $0001, &[!getc_read]
+    tst r1, CLOSED
+    jeq $0000, &[!getc]
+    hlt
+getc_read:
+    mov8 &[KEYBOARD + 1], r1
+    rts $0000
+
+; Wait for a byte of the serial port and read it to r1, a load fails when the
+; input has ended
+serial_getc:
+    mov8 &[SERIAL], r1
+    tst r1, READY
+    jeq $0001, &[!serial_getc_read]
+    tst r1, CLOSED
+    jeq $0000, &[!serial_getc]
+    jeq acc, &[!load_error]
+serial_getc_read:
+    mov8 &[SERIAL + 1], r1
+    rts $0000
+
+; Read two hex digits of the serial port to r1 and add it to r7, a load fails
+; when they aren't hex
+serial_byte:
+    jsr !serial_getc
+    jsr !hex_digit
+    mov $FFFF, acc
+    jeq r1, &[!load_error]
+    lsh r1, $0004
+    mov r1, r2
+    jsr !serial_getc
+    jsr !hex_digit
+    mov $FFFF, acc
+    jeq r1, &[!load_error]
+    or r1, r2
+    mov acc, r1
+    add r7, r1
+    mov acc, r7
+    rts $0000
+
+welcome:
+    .asciz "16-bit monitor\n"
+ok:
+    .asciz "OK\n"
+failed:
+    .asciz "ERR\n"
+unknown:
+    .asciz "?\n"
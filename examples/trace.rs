@@ -0,0 +1,38 @@
+// Trace a program, printing every instruction and memory write
+//
+//     cargo run --example trace asm/demo.asm
+
+use six_teen_bit_vm::assembler::Assembler;
+use six_teen_bit_vm::linker::{self, MemoryMap};
+use six_teen_bit_vm::{Access, MachineBuilder, MachineConfig, StopReason};
+use std::env;
+
+fn main() -> Result<(), String> {
+    let path = env::args().nth(1).unwrap_or(String::from("asm/demo.asm"));
+
+    // Assemble and link the program
+    let object = Assembler::assemble_file(&path)?;
+    let image = linker::link(&[object], &MemoryMap::default_machine())?;
+
+    // Print the instructions and writes
+    let mut cpu = MachineBuilder::new(MachineConfig::default_machine())
+        .with_program(image.bytes)
+        .build()?;
+    cpu.load_symbols(image.symbols);
+    cpu.on_step(|cpu| {
+        let ip = cpu.get_register("ip");
+        let address = cpu.symbols().unwrap().format_address(ip);
+        println!("{}", address);
+    });
+    cpu.on_memory_access(|access| {
+        if access.access == Access::Write {
+            println!("    ${:04X} = ${:04X}", access.address, access.value);
+        }
+    });
+    cpu.on_halt(|cpu| println!("halted, acc ${:04X}", cpu.get_register("acc")));
+
+    match cpu.run() {
+        StopReason::Halted => Ok(()),
+        reason => Err(reason.to_string()),
+    }
+}
@@ -0,0 +1,1785 @@
+use crate::device::Endian;
+use crate::listing::{Listing, ListingLine, Reference};
+use crate::object::{Data, Line, Object, Relocation, Symbol, Target};
+use crate::opcodes::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Assembler
+//
+// Turns source in the syntax of the LowLevelJavaScript series into an object
+// file. One statement per line, `;` starts a comment:
+//
+//     start:                        label
+//         mov $0A, &0050            literal to memory
+//         mov &[!count], acc        memory at a label
+//         mov [!table + $02], r1    literal expression
+//         mov r1, &[fp - $04]       addressed, also &[r1], &[r1+] and &[-r1]
+//         jne $00, &[!start]        jump to a label
+//
+// Literals are `$` hex, decimal or a 'c'haracter. `name` or `!name` is the
+// value of a label or define. Expressions use `*`, `/`, `%`, `+`, `-`, `<<`,
+// `>>`, `&`, `^`, `|` and comparisons, in C precedence, and group with `[...]`
+// or `(...)`. Only `+`, `-` and multiplying by a constant work on addresses.
+// A memory operand is `&` followed by a hex address, a name or a `[...]`
+// expression. `&reg` is the memory the register points at.
+//
+// Directives:
+//
+//     .section name         put what follows in a section, "text" by default
+//     .global name          export a label to other objects
+//     .extern name          use a label of another object
+//     .define NAME expr     name an expression
+//     .include "file"       assemble a file, relative to this one
+//     .macro name a, b      start a macro, invoked as `name x, y`, `@` in
+//     .endm                 names of the body is unique per invocation
+//     .if expr              assemble up to .else or .endif if expr isn't 0,
+//     .ifdef NAME           if NAME is defined or if it isn't, blocks nest
+//     .ifndef NAME
+//     .else
+//     .endif
+//
+// Data:
+//
+//     .byte 1, -2, "text"   bytes, strings give their UTF-8 bytes
+//     .word $1234, label    words, addresses are relocated, see with_endian
+//     .ascii "text"         string without terminator
+//     .asciz "text"         string followed by a 0 byte
+//     .fill count, byte     count copies of a byte, 0 by default
+//     .align n              pad with 0 up to a multiple of n, a power of two
+//     .org address          pad with 0 up to the address, the first .org of a
+//                           section gives the section a fixed address
+//
+// A structure declares the offsets of its fields, `point.y` below is 2 and
+// `point.size` is 12, ready for `mov point.y, &r1, r2`:
+//
+//     .struct point
+//     x: .word
+//     y: .word
+//     name: .byte 8         fields take .byte n, .word n or .fill n
+//     .ends
+//
+// Addresses of labels are only known after linking, every field using one
+// gets a relocation.
+
+// Registers by operand number
+pub const REGISTERS: [&str; 14] = [
+    "ip", "acc", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "sp", "fp", "sb", "sl",
+];
+
+// Section used before the first .section directive
+pub const DEFAULT_SECTION: &str = "text";
+
+// Kinds of operands an instruction takes
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Literal,
+    Literal8,
+    Register,
+    Memory,
+    Pointer,
+    Address,
+}
+
+use Kind::*;
+
+// Instructions by mnemonic and operands, encoded in operand order
+const INSTRUCTIONS: &[(&str, &[Kind], u8)] = &[
+    // Move instructions
+    ("mov", &[Literal, Register], MOV_LIT_REG),
+    ("mov", &[Register, Register], MOV_REG_REG),
+    ("mov", &[Register, Memory], MOV_REG_MEM),
+    ("mov", &[Memory, Register], MOV_MEM_REG),
+    ("mov", &[Literal, Memory], MOV_LIT_MEM),
+    ("mov", &[Pointer, Register], MOV_REG_PTR_REG),
+    ("mov", &[Literal, Pointer, Register], MOV_LIT_OFF_REG),
+    ("mov", &[Address, Register], MOV_ADR_REG),
+    ("mov", &[Register, Address], MOV_REG_ADR),
+    // Byte move instructions
+    ("mov8", &[Register, Memory], MOV8_REG_MEM),
+    ("mov8", &[Memory, Register], MOV8_MEM_REG),
+    ("mov8", &[Literal8, Memory], MOV8_LIT_MEM),
+    ("mov8", &[Pointer, Register], MOV8_REG_PTR_REG),
+    ("mov8", &[Register, Pointer], MOV8_REG_REG_PTR),
+    ("mov8", &[Address, Register], MOV_ADR_REG),
+    ("mov8", &[Register, Address], MOV_REG_ADR),
+    ("mov8s", &[Memory, Register], MOV8S_MEM_REG),
+    ("mov8s", &[Pointer, Register], MOV8S_REG_PTR_REG),
+    ("mov8s", &[Address, Register], MOV_ADR_REG),
+    // Arithmetic instructions
+    ("add", &[Register, Register], ADD_REG_REG),
+    ("add", &[Literal, Register], ADD_LIT_REG),
+    ("sub", &[Literal, Register], SUB_LIT_REG),
+    ("sub", &[Register, Literal], SUB_REG_LIT),
+    ("sub", &[Register, Register], SUB_REG_REG),
+    ("inc", &[Register], INC_REG),
+    ("dec", &[Register], DEC_REG),
+    ("mul", &[Literal, Register], MUL_LIT_REG),
+    ("mul", &[Register, Register], MUL_REG_REG),
+    // Binary manipulation instructions
+    ("lsh", &[Register, Literal], LSH_REG_LIT),
+    ("lsh", &[Register, Register], LSH_REG_REG),
+    ("rsh", &[Register, Literal], RSH_REG_LIT),
+    ("rsh", &[Register, Register], RSH_REG_REG),
+    ("and", &[Register, Literal], AND_REG_LIT),
+    ("and", &[Register, Register], AND_REG_REG),
+    ("or", &[Register, Literal], OR_REG_LIT),
+    ("or", &[Register, Register], OR_REG_REG),
+    ("xor", &[Register, Literal], XOR_REG_LIT),
+    ("xor", &[Register, Register], XOR_REG_REG),
+    ("not", &[Register], NOT),
+    ("rol", &[Register, Literal], ROL_REG_LIT),
+    ("rol", &[Register, Register], ROL_REG_REG),
+    ("ror", &[Register, Literal], ROR_REG_LIT),
+    ("ror", &[Register, Register], ROR_REG_REG),
+    // Bit instructions
+    ("tst", &[Register, Literal], TST_REG_LIT),
+    ("tst", &[Register, Register], TST_REG_REG),
+    ("set", &[Register, Literal], SET_REG_LIT),
+    ("set", &[Register, Register], SET_REG_REG),
+    ("clr", &[Register, Literal], CLR_REG_LIT),
+    ("clr", &[Register, Register], CLR_REG_REG),
+    ("cnt", &[Register], CNT_REG),
+    ("swp", &[Register], SWP_REG),
+    // Branching instructions, the target is a memory operand or a literal
+    ("jne", &[Register, Memory], JNE_REG),
+    ("jne", &[Register, Literal], JNE_REG),
+    ("jne", &[Literal, Memory], JNE_LIT),
+    ("jne", &[Literal, Literal], JNE_LIT),
+    ("jeq", &[Register, Memory], JEQ_REG),
+    ("jeq", &[Register, Literal], JEQ_REG),
+    ("jeq", &[Literal, Memory], JEQ_LIT),
+    ("jeq", &[Literal, Literal], JEQ_LIT),
+    ("jlt", &[Register, Memory], JLT_REG),
+    ("jlt", &[Register, Literal], JLT_REG),
+    ("jlt", &[Literal, Memory], JLT_LIT),
+    ("jlt", &[Literal, Literal], JLT_LIT),
+    ("jgt", &[Register, Memory], JGT_REG),
+    ("jgt", &[Register, Literal], JGT_REG),
+    ("jgt", &[Literal, Memory], JGT_LIT),
+    ("jgt", &[Literal, Literal], JGT_LIT),
+    ("jle", &[Register, Memory], JLE_REG),
+    ("jle", &[Register, Literal], JLE_REG),
+    ("jle", &[Literal, Memory], JLE_LIT),
+    ("jle", &[Literal, Literal], JLE_LIT),
+    ("jge", &[Register, Memory], JGE_REG),
+    ("jge", &[Register, Literal], JGE_REG),
+    ("jge", &[Literal, Memory], JGE_LIT),
+    ("jge", &[Literal, Literal], JGE_LIT),
+    // Miscellaneous instructions
+    ("psh", &[Literal], PSH_LIT),
+    ("psh", &[Register], PSH_REG),
+    ("pop", &[Register], POP),
+    ("cal", &[Literal], CAL_LIT),
+    ("cal", &[Memory], CAL_LIT),
+    ("cal", &[Register], CAL_REG),
+    ("ret", &[], RET),
+    ("hlt", &[], HLT),
+    ("jsr", &[Literal], JSR_LIT),
+    ("jsr", &[Memory], JSR_LIT),
+    ("jsr", &[Register], JSR_REG),
+    ("rts", &[Literal], RTS_LIT),
+    // System instructions
+    ("lpt", &[Literal], LPT_LIT),
+    ("lpt", &[Memory], LPT_LIT),
+    ("lpt", &[Register], LPT_REG),
+    ("upt", &[], UPT),
+    ("syscall", &[Literal], SYSCALL),
+    ("sysret", &[], SYSRET),
+];
+
+// Punctuation, longest first
+const PUNCTUATION: [&str; 24] = [
+    "<<", ">>", "==", "!=", "<=", ">=", "&", "!", "[", "]", "(", ")", "+", "-", "*", "/", "%", "|",
+    "^", "~", "<", ">", ",", ":",
+];
+
+// Binary operators by precedence, lowest first
+const OPERATORS: [&[&str]; 8] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// Precedence of `+` and `-`
+const ADDITIVE: usize = 6;
+
+// Deepest nesting of includes, macro expansions and defines
+const MAX_DEPTH: usize = 64;
+
+// Token of a source line
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(u16),
+    String(String),
+    Punctuation(&'static str),
+}
+
+// Expression
+#[derive(Clone, Debug)]
+enum Expression {
+    Number(u16),
+    Name(String),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+}
+
+// Operand of an instruction
+#[derive(Clone, Debug)]
+enum Operand {
+    Literal(Expression),
+    Register(u8),
+    Memory(Expression),
+    Pointer(u8),
+    Address {
+        mode: u8,
+        register: u8,
+        offset: Option<Expression>,
+    },
+}
+
+// Operand implementation
+impl Operand {
+    // Check if the operand can be used where kind is expected
+    fn is(&self, kind: Kind) -> bool {
+        matches!(
+            (self, kind),
+            (Operand::Literal(_), Literal | Literal8)
+                | (Operand::Register(_), Register)
+                | (Operand::Memory(_), Memory)
+                | (Operand::Pointer(_), Pointer)
+                | (Operand::Address { .. }, Address)
+        )
+    }
+}
+
+// Value of an expression, a constant plus multiples of addresses that are only
+// known after linking
+#[derive(Clone, Debug, PartialEq)]
+struct Value {
+    constant: u16,
+    terms: Vec<(Target, i32)>,
+}
+
+// Value implementation
+impl Value {
+    fn constant(constant: u16) -> Self {
+        Self {
+            constant,
+            terms: Vec::new(),
+        }
+    }
+
+    fn address(target: Target, offset: u16) -> Self {
+        Self {
+            constant: offset,
+            terms: vec![(target, 1)],
+        }
+    }
+
+    // Multiply by a constant
+    fn scale(mut self, factor: u16) -> Self {
+        self.constant = self.constant.wrapping_mul(factor);
+        for term in self.terms.iter_mut() {
+            term.1 = term.1.wrapping_mul(factor as i16 as i32);
+        }
+        self.terms.retain(|term| term.1 != 0);
+        self
+    }
+
+    // Add another value
+    fn add(mut self, other: Value) -> Self {
+        self.constant = self.constant.wrapping_add(other.constant);
+        for (target, coefficient) in other.terms {
+            match self.terms.iter_mut().find(|term| term.0 == target) {
+                Some(term) => term.1 += coefficient,
+                None => self.terms.push((target, coefficient)),
+            }
+        }
+        self.terms.retain(|term| term.1 != 0);
+        self
+    }
+}
+
+// Field to fill in once all labels are known
+struct Fixup {
+    section: String,
+    offset: u16,
+    expression: Expression,
+    byte: bool,
+    location: (String, usize),
+}
+
+// Source line and the range of its section it assembled to
+struct Listed {
+    location: (String, usize),
+    text: String,
+    section: String,
+    start: usize,
+    end: usize,
+}
+
+// Macro
+#[derive(Clone)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Vec<Token>>,
+}
+
+// Conditional block
+struct Conditional {
+    // Lines are assembled
+    active: bool,
+    // The enclosing block is active
+    parent: bool,
+    // A branch of the block was taken
+    taken: bool,
+    // The .else was seen
+    otherwise: bool,
+}
+
+// Assembler class
+pub struct Assembler {
+    object: Object,
+    section: String,
+    labels: HashMap<String, (String, u16)>,
+    label_order: Vec<String>,
+    globals: Vec<(String, (String, usize))>,
+    externs: Vec<String>,
+    defines: HashMap<String, Expression>,
+    macros: HashMap<String, Macro>,
+    recording: Option<(String, Macro)>,
+    conditionals: Vec<Conditional>,
+    structure: Option<(String, u16)>,
+    expansions: usize,
+    depth: usize,
+    fixups: Vec<Fixup>,
+    location: (String, usize),
+    listed: Vec<Listed>,
+    definitions: HashMap<String, (String, usize)>,
+    uses: Vec<(String, (String, usize))>,
+}
+
+// Default Assembler
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Assembler implementation
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            object: Object::new(),
+            section: DEFAULT_SECTION.to_string(),
+            labels: HashMap::new(),
+            label_order: Vec::new(),
+            globals: Vec::new(),
+            externs: Vec::new(),
+            defines: HashMap::new(),
+            macros: HashMap::new(),
+            recording: None,
+            conditionals: Vec::new(),
+            structure: None,
+            expansions: 0,
+            depth: 0,
+            fixups: Vec::new(),
+            location: (String::new(), 0),
+            listed: Vec::new(),
+            definitions: HashMap::new(),
+            uses: Vec::new(),
+        }
+    }
+
+    // Assemble a source file
+    pub fn assemble_file(path: &str) -> Result<Object, String> {
+        let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        Self::assemble(&source, path)
+    }
+
+    // Assemble source, file is used in errors, line information and to find
+    // included files
+    pub fn assemble(source: &str, file: &str) -> Result<Object, String> {
+        Self::assemble_listing(source, file).map(|(object, _)| object)
+    }
+
+    // Assemble a source file and list it
+    pub fn assemble_file_listing(path: &str) -> Result<(Object, Listing), String> {
+        let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        Self::assemble_listing(&source, path)
+    }
+
+    // Assemble source and list it
+    pub fn assemble_listing(source: &str, file: &str) -> Result<(Object, Listing), String> {
+        Self::new().assemble_source(source, file)
+    }
+
+    // Write words in the byte order of the memory the program runs from,
+    // big-endian by default
+    pub fn with_endian(mut self, endian: Endian) -> Self {
+        self.object.endian = endian;
+        self
+    }
+
+    // Assemble source and list it with this assembler
+    pub fn assemble_source(
+        mut self,
+        source: &str,
+        file: &str,
+    ) -> Result<(Object, Listing), String> {
+        self.source(source, file)?;
+        self.finish()
+    }
+
+    // Assemble the lines of a file
+    fn source(&mut self, source: &str, file: &str) -> Result<(), String> {
+        // Check nesting
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("includes nested too deep"));
+        }
+        self.depth += 1;
+        let location = self.location.clone();
+        let conditionals = self.conditionals.len();
+
+        // Assemble lines
+        for (number, line) in source.lines().enumerate() {
+            self.location = (file.to_string(), number + 1);
+            let index = self.listed.len();
+            let start = self.length();
+            self.listed.push(Listed {
+                location: self.location.clone(),
+                text: line.to_string(),
+                section: self.section.clone(),
+                start,
+                end: start,
+            });
+            tokenize(line)
+                .and_then(|tokens| self.line(tokens))
+                .map_err(|error| self.error(&error))?;
+
+            // Included lines list their own bytes
+            if self.listed.len() == index + 1 && self.listed[index].section == self.section {
+                self.listed[index].end = self.length();
+            }
+        }
+
+        // Blocks may not run past the end of the file
+        if self.recording.is_some() {
+            return Err(self.error("missing .endm"));
+        }
+        if self.structure.is_some() {
+            return Err(self.error("missing .ends"));
+        }
+        if self.conditionals.len() != conditionals {
+            return Err(self.error("missing .endif"));
+        }
+
+        self.depth -= 1;
+        self.location = location;
+        Ok(())
+    }
+
+    // Prefix an error with the current source location
+    fn error(&self, message: &str) -> String {
+        format!("{}:{}: {}", self.location.0, self.location.1, message)
+    }
+
+    // Check if lines are assembled
+    fn active(&self) -> bool {
+        self.conditionals
+            .last()
+            .is_none_or(|conditional| conditional.active)
+    }
+
+    // Size of the current section so far
+    fn length(&self) -> usize {
+        self.object
+            .section(&self.section)
+            .map_or(0, |section| section.bytes.len())
+    }
+
+    // Remember the names used on the current line for the cross reference
+    fn record_uses(&mut self, tokens: &[Token]) {
+        for token in tokens.iter() {
+            if let Token::Identifier(name) = token {
+                self.uses.push((name.clone(), self.location.clone()));
+            }
+        }
+    }
+
+    // Offset of the next byte in the current section
+    fn offset(&mut self) -> Result<u16, String> {
+        let length = self.object.section_mut(&self.section).bytes.len();
+        u16::try_from(length).map_err(|_| format!("section {} is too large", self.section))
+    }
+
+    // Append bytes to the current section
+    fn emit(&mut self, bytes: &[u8]) {
+        let section = self.object.section_mut(&self.section);
+        section.bytes.extend_from_slice(bytes);
+    }
+
+    // Append a field filled in when all labels are known
+    fn emit_expression(&mut self, expression: Expression, byte: bool) -> Result<(), String> {
+        let offset = self.offset()?;
+        self.fixups.push(Fixup {
+            section: self.section.clone(),
+            offset,
+            expression,
+            byte,
+            location: self.location.clone(),
+        });
+        self.emit(if byte { &[0] } else { &[0, 0] });
+        Ok(())
+    }
+
+    // Assemble a line, recording macros and skipping inactive conditional blocks
+    fn line(&mut self, tokens: Vec<Token>) -> Result<(), String> {
+        let directive = match tokens.first() {
+            Some(Token::Identifier(name)) if name.starts_with('.') => name.to_lowercase(),
+            _ => String::new(),
+        };
+
+        // Record macro body
+        if let Some((_, definition)) = self.recording.as_mut() {
+            match directive.as_str() {
+                ".endm" => {
+                    let (name, definition) = self.recording.take().unwrap();
+                    self.macros.insert(name, definition);
+                }
+                ".macro" => return Err("macros can't be defined inside a macro".to_string()),
+                _ => definition.body.push(tokens),
+            }
+            return Ok(());
+        }
+
+        // Conditional assembly
+        let mut parser = Parser { tokens, index: 0 };
+        match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                parser.index += 1;
+                let parent = self.active();
+                if parent {
+                    self.record_uses(&parser.tokens[1..]);
+                }
+                let active = parent
+                    && match directive.as_str() {
+                        ".if" => self.constant(&parser.expression()?)? != 0,
+                        ".ifdef" => self.is_defined(&parser.identifier()?),
+                        _ => !self.is_defined(&parser.identifier()?),
+                    };
+                if parent {
+                    parser.end()?;
+                }
+                self.conditionals.push(Conditional {
+                    active,
+                    parent,
+                    taken: active,
+                    otherwise: false,
+                });
+                Ok(())
+            }
+            ".else" => {
+                let conditional = self.conditionals.last_mut().ok_or(".else without .if")?;
+                if conditional.otherwise {
+                    return Err("second .else in .if".to_string());
+                }
+                conditional.otherwise = true;
+                conditional.active = conditional.parent && !conditional.taken;
+                Ok(())
+            }
+            ".endif" => {
+                self.conditionals.pop().ok_or(".endif without .if")?;
+                Ok(())
+            }
+            _ if !self.active() => Ok(()),
+            _ => self.statement(&mut parser),
+        }
+    }
+
+    // Assemble a statement
+    fn statement(&mut self, parser: &mut Parser) -> Result<(), String> {
+        // Names used, not the labels and the names being defined
+        let mut start = 0;
+        while let (Some(Token::Identifier(_)), Some(Token::Punctuation(":"))) =
+            (parser.peek(start), parser.peek(start + 1))
+        {
+            start += 2;
+        }
+        let skip = match parser.peek(start) {
+            Some(Token::Identifier(name)) => match name.to_lowercase().as_str() {
+                ".define" => 2,
+                ".extern" | ".macro" | ".struct" => parser.tokens.len(),
+                _ => 1,
+            },
+            _ => 0,
+        };
+        let uses = parser.tokens[(start + skip).min(parser.tokens.len())..].to_vec();
+        self.record_uses(&uses);
+
+        // Fields of a structure
+        if self.structure.is_some() {
+            return self.field(parser);
+        }
+
+        // Labels
+        while let (Some(Token::Identifier(name)), Some(Token::Punctuation(":"))) =
+            (parser.peek(0), parser.peek(1))
+        {
+            let name = name.clone();
+            parser.index += 2;
+            self.define_label(&name)?;
+        }
+
+        // Directive, macro or instruction
+        let name = match parser.next() {
+            Some(Token::Identifier(name)) => name,
+            Some(token) => return Err(format!("unexpected {}", describe(&token))),
+            None => return Ok(()),
+        };
+        if name.starts_with('.') {
+            self.directive(&name.to_lowercase(), parser)
+        } else if self.macros.contains_key(&name) {
+            self.expand(&name, parser)
+        } else {
+            self.instruction(&name.to_lowercase(), parser)
+        }
+    }
+
+    // Check if a name is a label or define
+    fn is_defined(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.defines.contains_key(name)
+    }
+
+    // Name an expression
+    fn define(&mut self, name: String, expression: Expression) -> Result<(), String> {
+        if self.is_defined(&name) {
+            return Err(format!("{} is already defined", name));
+        }
+
+        self.definitions.insert(name.clone(), self.location.clone());
+        self.defines.insert(name, expression);
+        Ok(())
+    }
+
+    // Define a label at the current offset
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        if self.is_defined(name) {
+            return Err(format!("{} is already defined", name));
+        }
+
+        let offset = self.offset()?;
+        self.labels
+            .insert(name.to_string(), (self.section.clone(), offset));
+        self.label_order.push(name.to_string());
+        self.definitions
+            .insert(name.to_string(), self.location.clone());
+        Ok(())
+    }
+
+    // Assemble a directive
+    fn directive(&mut self, name: &str, parser: &mut Parser) -> Result<(), String> {
+        match name {
+            ".section" => {
+                self.section = parser.identifier()?;
+                self.object.section_mut(&self.section);
+            }
+            ".global" => loop {
+                let label = parser.identifier()?;
+                self.globals.push((label, self.location.clone()));
+                if !parser.accept(",") {
+                    break;
+                }
+            },
+            ".extern" => loop {
+                let label = parser.identifier()?;
+                self.definitions
+                    .entry(label.clone())
+                    .or_insert_with(|| self.location.clone());
+                self.externs.push(label);
+                if !parser.accept(",") {
+                    break;
+                }
+            },
+            ".define" => {
+                let name = parser.identifier()?;
+                let expression = parser.expression()?;
+                self.define(name, expression)?;
+            }
+            ".byte" | ".word" => {
+                let start = self.offset()?;
+                loop {
+                    match parser.peek(0).cloned() {
+                        Some(Token::String(text)) if name == ".byte" => {
+                            parser.index += 1;
+                            self.emit(text.as_bytes());
+                        }
+                        _ => self.emit_expression(parser.expression()?, name == ".byte")?,
+                    }
+                    if !parser.accept(",") {
+                        break;
+                    }
+                }
+                self.mark_data(start)?;
+            }
+            ".ascii" | ".asciz" => {
+                let start = self.offset()?;
+                let text = parser.string()?;
+                self.emit(text.as_bytes());
+                if name == ".asciz" {
+                    self.emit(&[0]);
+                }
+                self.mark_data(start)?;
+            }
+            ".fill" => {
+                let start = self.offset()?;
+                let count = self.constant(&parser.expression()?)?;
+                let value = match parser.accept(",") {
+                    true => self.byte(&parser.expression()?)?,
+                    false => 0,
+                };
+                self.emit(&vec![value; count as usize]);
+                self.mark_data(start)?;
+            }
+            ".align" => {
+                let alignment = self.constant(&parser.expression()?)?;
+                if !alignment.is_power_of_two() {
+                    return Err(format!("alignment {} isn't a power of two", alignment));
+                }
+                // Sections with an address align the address, others their offset
+                let section = self.object.section_mut(&self.section);
+                let origin = section.address.unwrap_or(0) as usize;
+                let end = (origin + section.bytes.len()).next_multiple_of(alignment as usize);
+                section.bytes.resize(end - origin, 0);
+                section.alignment = section.alignment.max(alignment);
+                self.offset()?;
+            }
+            ".org" => {
+                let address = self.constant(&parser.expression()?)?;
+                let section = self.object.section_mut(&self.section);
+                match section.address {
+                    None if section.bytes.is_empty() => section.address = Some(address),
+                    None => {
+                        return Err(
+                            ".org must come first in a section without an address".to_string()
+                        )
+                    }
+                    Some(origin) => {
+                        let size = address.wrapping_sub(origin) as usize;
+                        if address < origin || size < section.bytes.len() {
+                            return Err(format!(
+                                ".org ${:04X} is behind the current address",
+                                address
+                            ));
+                        }
+                        section.bytes.resize(size, 0);
+                    }
+                }
+            }
+            ".struct" => self.structure = Some((parser.identifier()?, 0)),
+            ".ends" => return Err(".ends without .struct".to_string()),
+            ".macro" => {
+                let name = parser.identifier()?;
+                let mut parameters = Vec::new();
+                while parser.peek(0).is_some() {
+                    parameters.push(parser.identifier()?);
+                    if !parser.accept(",") {
+                        break;
+                    }
+                }
+                let definition = Macro {
+                    parameters,
+                    body: Vec::new(),
+                };
+                self.recording = Some((name, definition));
+            }
+            ".include" => {
+                let path = parser.string()?;
+                parser.end()?;
+
+                // Paths are relative to the including file
+                let directory = Path::new(&self.location.0).parent();
+                let path =
+                    directory.map_or(PathBuf::from(&path), |directory| directory.join(&path));
+                let path = path.to_string_lossy().into_owned();
+                let source =
+                    fs::read_to_string(&path).map_err(|error| format!("{}: {}", path, error))?;
+                return self.source(&source, &path);
+            }
+            ".endm" => return Err(".endm without .macro".to_string()),
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+
+        parser.end()
+    }
+
+    // Assemble a field of a structure
+    fn field(&mut self, parser: &mut Parser) -> Result<(), String> {
+        let (structure, offset) = self.structure.clone().unwrap();
+
+        // Field name
+        if let (Some(Token::Identifier(field)), Some(Token::Punctuation(":"))) =
+            (parser.peek(0), parser.peek(1))
+        {
+            let name = format!("{}.{}", structure, field);
+            parser.index += 2;
+            self.define(name, Expression::Number(offset))?;
+        }
+
+        // Field size
+        let directive = match parser.next() {
+            Some(Token::Identifier(directive)) => directive.to_lowercase(),
+            Some(token) => return Err(format!("unexpected {}", describe(&token))),
+            None => return Ok(()),
+        };
+        let count = match parser.peek(0) {
+            Some(_) => Some(self.constant(&parser.expression()?)?),
+            None => None,
+        };
+        parser.end()?;
+        let size = match (directive.as_str(), count) {
+            (".byte", count) => count.unwrap_or(1),
+            (".word", count) => count
+                .unwrap_or(1)
+                .checked_mul(2)
+                .ok_or("field is too large")?,
+            (".fill", Some(count)) => count,
+            (".fill", None) => return Err("expected a value".to_string()),
+            (".ends", None) => {
+                self.structure = None;
+                return self.define(format!("{}.size", structure), Expression::Number(offset));
+            }
+            _ => return Err("only fields are allowed in a .struct".to_string()),
+        };
+
+        let end = offset
+            .checked_add(size)
+            .ok_or(format!("structure {} is too large", structure))?;
+        self.structure = Some((structure, end));
+        Ok(())
+    }
+
+    // Record the bytes since start as data, named by the label in front of them
+    fn mark_data(&mut self, start: u16) -> Result<(), String> {
+        let end = self.offset()?;
+        let label = self
+            .label_order
+            .iter()
+            .rev()
+            .find(|name| self.labels[*name] == (self.section.clone(), start))
+            .cloned();
+
+        // Grow the data right before when no label starts new data
+        if let (None, Some(data)) = (&label, self.object.data.last_mut()) {
+            if data.section == self.section && data.offset + data.size == start {
+                data.size = end - data.offset;
+                return Ok(());
+            }
+        }
+
+        // New data
+        self.object.data.push(Data {
+            name: label.unwrap_or_else(|| String::from("data")),
+            section: self.section.clone(),
+            offset: start,
+            size: end - start,
+        });
+        Ok(())
+    }
+
+    // Assemble the body of a macro with its parameters replaced by the arguments
+    fn expand(&mut self, name: &str, parser: &mut Parser) -> Result<(), String> {
+        // Read arguments
+        let definition = self.macros[name].clone();
+        let arguments = parser.arguments();
+        if arguments.len() != definition.parameters.len() {
+            return Err(format!(
+                "macro {} takes {} arguments, found {}",
+                name,
+                definition.parameters.len(),
+                arguments.len()
+            ));
+        }
+
+        // Check nesting
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("macro {} nested too deep", name));
+        }
+        self.depth += 1;
+        self.expansions += 1;
+        let conditionals = self.conditionals.len();
+
+        // Assemble body, `@` in names becomes a number unique to the expansion
+        let unique = format!("_{}", self.expansions);
+        for line in definition.body {
+            let mut tokens = Vec::new();
+            for token in line {
+                match token {
+                    Token::Identifier(word) => match definition
+                        .parameters
+                        .iter()
+                        .position(|parameter| *parameter == word)
+                    {
+                        Some(index) => tokens.extend(arguments[index].iter().cloned()),
+                        None => tokens.push(Token::Identifier(word.replace('@', &unique))),
+                    },
+                    token => tokens.push(token),
+                }
+            }
+            self.line(tokens)
+                .map_err(|error| format!("in macro {}: {}", name, error))?;
+        }
+
+        // Blocks may not run past the end of the macro
+        if self.conditionals.len() != conditionals {
+            return Err(format!("missing .endif in macro {}", name));
+        }
+
+        self.depth -= 1;
+        Ok(())
+    }
+
+    // Assemble an instruction
+    fn instruction(&mut self, mnemonic: &str, parser: &mut Parser) -> Result<(), String> {
+        // Read operands
+        let mut operands = Vec::new();
+        if parser.peek(0).is_some() {
+            loop {
+                operands.push(parser.operand()?);
+                if !parser.accept(",") {
+                    break;
+                }
+            }
+        }
+        parser.end()?;
+
+        // Find encoding
+        if !INSTRUCTIONS.iter().any(|entry| entry.0 == mnemonic) {
+            return Err(format!("unknown instruction {}", mnemonic));
+        }
+        let (_, kinds, opcode) = INSTRUCTIONS
+            .iter()
+            .find(|(name, kinds, _)| {
+                *name == mnemonic
+                    && kinds.len() == operands.len()
+                    && operands.iter().zip(kinds.iter()).all(|(o, k)| o.is(*k))
+            })
+            .ok_or_else(|| format!("invalid operands for {}", mnemonic))?;
+
+        // Line information
+        let offset = self.offset()?;
+        self.object.lines.push(Line {
+            section: self.section.clone(),
+            offset,
+            file: self.location.0.clone(),
+            line: self.location.1,
+        });
+
+        // Encode
+        let size_flags = match mnemonic {
+            "mov8" => ADR_BYTE,
+            "mov8s" => ADR_BYTE | ADR_SIGNED,
+            _ => 0,
+        };
+        self.emit(&[*opcode]);
+        for (operand, kind) in operands.into_iter().zip(kinds.iter()) {
+            match operand {
+                Operand::Literal(expression) | Operand::Memory(expression) => {
+                    self.emit_expression(expression, *kind == Literal8)?
+                }
+                Operand::Register(register) | Operand::Pointer(register) => self.emit(&[register]),
+                Operand::Address {
+                    mode,
+                    register,
+                    offset,
+                } => {
+                    self.emit(&[mode | size_flags | register]);
+                    if let Some(offset) = offset {
+                        self.emit_expression(offset, false)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Value of an expression that must be known while assembling
+    fn constant(&self, expression: &Expression) -> Result<u16, String> {
+        let value = self.evaluate(expression, 0)?;
+        if !value.terms.is_empty() {
+            return Err("expression must be a constant".to_string());
+        }
+
+        Ok(value.constant)
+    }
+
+    // Value of an expression that must be a constant byte
+    fn byte(&self, expression: &Expression) -> Result<u8, String> {
+        let value = self.constant(expression)?;
+        if !fits_byte(value) {
+            return Err(format!("value ${:04X} doesn't fit in a byte", value));
+        }
+
+        Ok(value as u8)
+    }
+
+    // Value of an expression
+    fn evaluate(&self, expression: &Expression, depth: usize) -> Result<Value, String> {
+        match expression {
+            Expression::Number(number) => Ok(Value::constant(*number)),
+            Expression::Name(name) => {
+                if let Some(define) = self.defines.get(name) {
+                    if depth >= MAX_DEPTH {
+                        return Err(format!("define {} refers to itself", name));
+                    }
+                    return self.evaluate(define, depth + 1);
+                }
+
+                match self.labels.get(name) {
+                    Some((section, offset)) => {
+                        Ok(Value::address(Target::Section(section.clone()), *offset))
+                    }
+                    None if self.externs.contains(name) => {
+                        Ok(Value::address(Target::Symbol(name.clone()), 0))
+                    }
+                    None => Err(format!("undefined name {}", name)),
+                }
+            }
+            Expression::Unary(operator, value) => {
+                let value = self.evaluate(value, depth)?;
+                match *operator {
+                    "-" => Ok(value.scale(0xFFFF)),
+                    _ if !value.terms.is_empty() => Err(format!("'{}' needs a constant", operator)),
+                    _ => Ok(Value::constant(!value.constant)),
+                }
+            }
+            Expression::Binary(operator, left, right) => {
+                let left = self.evaluate(left, depth)?;
+                let right = self.evaluate(right, depth)?;
+
+                // Operators on addresses
+                match *operator {
+                    "+" => return Ok(left.add(right)),
+                    "-" => return Ok(left.add(right.scale(0xFFFF))),
+                    "*" if right.terms.is_empty() => return Ok(left.scale(right.constant)),
+                    "*" if left.terms.is_empty() => return Ok(right.scale(left.constant)),
+                    _ if !left.terms.is_empty() || !right.terms.is_empty() => {
+                        return Err(format!("'{}' needs constants", operator))
+                    }
+                    _ => {}
+                }
+
+                // Operators on constants
+                let (left, right) = (left.constant, right.constant);
+                let value = match *operator {
+                    "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+                    "/" => left / right,
+                    "%" => left % right,
+                    "<<" => left.checked_shl(right as u32).unwrap_or(0),
+                    ">>" => left.checked_shr(right as u32).unwrap_or(0),
+                    "&" => left & right,
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "==" => (left == right) as u16,
+                    "!=" => (left != right) as u16,
+                    "<" => (left < right) as u16,
+                    ">" => (left > right) as u16,
+                    "<=" => (left <= right) as u16,
+                    ">=" => (left >= right) as u16,
+                    _ => unreachable!(),
+                };
+                Ok(Value::constant(value))
+            }
+        }
+    }
+
+    // Fill in the fields and build the object and its listing
+    fn finish(mut self) -> Result<(Object, Listing), String> {
+        // Fill in fields
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.location = fixup.location.clone();
+            let value = self
+                .evaluate(&fixup.expression, 0)
+                .map_err(|error| self.error(&error))?;
+            let offset = fixup.offset as usize;
+            let endian = self.object.endian;
+            let section = self.object.section_mut(&fixup.section);
+
+            // Constant
+            if value.terms.is_empty() {
+                if fixup.byte {
+                    if !fits_byte(value.constant) {
+                        return Err(self.error(&format!(
+                            "value ${:04X} doesn't fit in a byte",
+                            value.constant
+                        )));
+                    }
+                    section.bytes[offset] = value.constant as u8;
+                } else {
+                    let bytes = endian.split(value.constant);
+                    section.bytes[offset..offset + 2].copy_from_slice(&bytes);
+                }
+                continue;
+            }
+
+            // Address
+            let target = match &value.terms[..] {
+                [(target, 1)] if !fixup.byte => target.clone(),
+                _ if fixup.byte => return Err(self.error("byte operand must be a constant")),
+                _ => return Err(self.error("expression can't be relocated")),
+            };
+            let bytes = endian.split(value.constant);
+            section.bytes[offset..offset + 2].copy_from_slice(&bytes);
+            self.object.relocations.push(Relocation {
+                section: fixup.section,
+                offset: fixup.offset,
+                target,
+                addend: value.constant,
+            });
+        }
+
+        // Symbols, a label runs up to the next label of its section
+        let mut symbols: Vec<Symbol> = Vec::new();
+        for name in self.label_order.iter() {
+            let (section, offset) = &self.labels[name];
+            symbols.push(Symbol {
+                name: name.clone(),
+                section: section.clone(),
+                offset: *offset,
+                size: 0,
+                global: false,
+            });
+        }
+        for index in 0..symbols.len() {
+            let end = symbols
+                .iter()
+                .filter(|other| {
+                    other.section == symbols[index].section && other.offset > symbols[index].offset
+                })
+                .map(|other| other.offset)
+                .min()
+                .unwrap_or_else(|| {
+                    self.object
+                        .section(&symbols[index].section)
+                        .map_or(0, |section| section.bytes.len() as u16)
+                });
+            symbols[index].size = end - symbols[index].offset;
+        }
+
+        // Exports
+        for (name, location) in self.globals.iter() {
+            match symbols.iter_mut().find(|symbol| symbol.name == *name) {
+                Some(symbol) => symbol.global = true,
+                None => {
+                    return Err(format!(
+                        "{}:{}: global label {} is not defined",
+                        location.0, location.1, name
+                    ))
+                }
+            }
+        }
+
+        // Imports
+        for name in self.externs.iter() {
+            if !self.labels.contains_key(name) && !self.object.imports.contains(name) {
+                self.object.imports.push(name.clone());
+            }
+        }
+
+        self.object.symbols = symbols;
+        let listing = self.listing();
+        Ok((self.object, listing))
+    }
+
+    // List the source lines with their bytes and the names with their uses
+    fn listing(&self) -> Listing {
+        let mut listing = Listing::new();
+        for listed in self.listed.iter() {
+            let (address, bytes) = match self.object.section(&listed.section) {
+                Some(section) => (
+                    section
+                        .address
+                        .unwrap_or(0)
+                        .wrapping_add(listed.start as u16),
+                    section.bytes[listed.start..listed.end].to_vec(),
+                ),
+                None => (listed.start as u16, Vec::new()),
+            };
+            let relocated = self
+                .object
+                .relocations
+                .iter()
+                .filter(|relocation| relocation.section == listed.section)
+                .map(|relocation| relocation.offset as usize)
+                .filter(|offset| (listed.start..listed.end).contains(offset))
+                .map(|offset| offset - listed.start)
+                .collect();
+            listing.lines.push(ListingLine {
+                file: listed.location.0.clone(),
+                line: listed.location.1,
+                text: listed.text.clone(),
+                section: listed.section.clone(),
+                address,
+                bytes,
+                relocated,
+            });
+        }
+
+        // Cross reference, sorted by name
+        let mut names: Vec<&String> = self.definitions.keys().collect();
+        names.sort();
+        for name in names {
+            let (kind, value) = if let Some((section, offset)) = self.labels.get(name) {
+                let global = self.globals.iter().any(|(global, _)| global == name);
+                let kind = if global { "global" } else { "label" };
+                (kind, self.describe_address(section, *offset))
+            } else if let Some(define) = self.defines.get(name) {
+                let value = match self.evaluate(define, 0) {
+                    Ok(value) => match &value.terms[..] {
+                        [] => format!("${:04X}", value.constant),
+                        [(Target::Section(section), 1)] => {
+                            self.describe_address(section, value.constant)
+                        }
+                        [(Target::Symbol(symbol), 1)] => {
+                            format!("{}+${:04X}", symbol, value.constant)
+                        }
+                        _ => String::from("expression"),
+                    },
+                    Err(_) => String::from("?"),
+                };
+                ("define", value)
+            } else {
+                ("extern", String::from("?"))
+            };
+
+            let mut uses: Vec<(String, usize)> = self
+                .uses
+                .iter()
+                .filter(|(used, _)| used == name)
+                .map(|(_, location)| location.clone())
+                .collect();
+            uses.dedup();
+            listing.references.push(Reference {
+                name: name.clone(),
+                kind,
+                value,
+                defined: self.definitions[name].clone(),
+                uses,
+            });
+        }
+
+        listing
+    }
+
+    // Describe an offset into a section, the address if the section is fixed
+    fn describe_address(&self, section: &str, offset: u16) -> String {
+        match self
+            .object
+            .section(section)
+            .and_then(|section| section.address)
+        {
+            Some(address) => format!("${:04X}", address.wrapping_add(offset)),
+            None => format!("{}+${:04X}", section, offset),
+        }
+    }
+}
+
+// Parser of the tokens of a line
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+// Parser implementation
+impl Parser {
+    // Look at a token ahead
+    fn peek(&self, ahead: usize) -> Option<&Token> {
+        self.tokens.get(self.index + ahead)
+    }
+
+    // Take the next token
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    // Take the next token if it is the punctuation
+    fn accept(&mut self, punctuation: &str) -> bool {
+        if matches!(self.peek(0), Some(Token::Punctuation(found)) if *found == punctuation) {
+            self.index += 1;
+            return true;
+        }
+
+        false
+    }
+
+    // Take the punctuation
+    fn expect(&mut self, punctuation: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Punctuation(found)) if found == punctuation => Ok(()),
+            Some(token) => Err(format!(
+                "expected '{}', found {}",
+                punctuation,
+                describe(&token)
+            )),
+            None => Err(format!("expected '{}'", punctuation)),
+        }
+    }
+
+    // Take a string
+    fn string(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::String(text)) => Ok(text),
+            Some(token) => Err(format!("expected a string, found {}", describe(&token))),
+            None => Err("expected a string".to_string()),
+        }
+    }
+
+    // Take an identifier
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Identifier(name)) => Ok(name),
+            Some(token) => Err(format!("expected a name, found {}", describe(&token))),
+            None => Err("expected a name".to_string()),
+        }
+    }
+
+    // Check that the line has ended
+    fn end(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(token) => Err(format!("unexpected {}", describe(&token))),
+            None => Ok(()),
+        }
+    }
+
+    // Take the rest of the line as comma separated macro arguments
+    fn arguments(&mut self) -> Vec<Vec<Token>> {
+        let mut arguments = Vec::new();
+        let mut argument = Vec::new();
+        let mut nesting = 0;
+        while let Some(token) = self.next() {
+            match token {
+                Token::Punctuation("[" | "(") => nesting += 1,
+                Token::Punctuation("]" | ")") => nesting -= 1,
+                Token::Punctuation(",") if nesting == 0 => {
+                    arguments.push(std::mem::take(&mut argument));
+                    continue;
+                }
+                _ => {}
+            }
+            argument.push(token);
+        }
+        if !argument.is_empty() || !arguments.is_empty() {
+            arguments.push(argument);
+        }
+
+        arguments
+    }
+
+    // Take a register if the next token is one
+    fn register(&mut self) -> Option<u8> {
+        let register = match self.peek(0) {
+            Some(Token::Identifier(name)) => register_number(name)?,
+            _ => return None,
+        };
+        self.index += 1;
+        Some(register)
+    }
+
+    // Parse an operand
+    fn operand(&mut self) -> Result<Operand, String> {
+        // Register
+        if let Some(register) = self.register() {
+            return Ok(Operand::Register(register));
+        }
+
+        // Literal
+        if !self.accept("&") {
+            return Ok(Operand::Literal(self.expression()?));
+        }
+
+        // Register pointer
+        if let Some(register) = self.register() {
+            return Ok(Operand::Pointer(register));
+        }
+
+        // Addressed memory
+        let addressed = match (self.peek(0), self.peek(1), self.peek(2)) {
+            (Some(Token::Punctuation("[")), Some(Token::Identifier(name)), _) => {
+                register_number(name).is_some()
+            }
+            (
+                Some(Token::Punctuation("[")),
+                Some(Token::Punctuation("-")),
+                Some(Token::Identifier(name)),
+            ) => register_number(name).is_some(),
+            _ => false,
+        };
+        if addressed {
+            return self.address();
+        }
+
+        // Memory, a bare word is a hex address when it can be one
+        match self.peek(0).cloned() {
+            Some(Token::Identifier(word)) => {
+                self.index += 1;
+                match u16::from_str_radix(&word, 16) {
+                    Ok(address) => Ok(Operand::Memory(Expression::Number(address))),
+                    Err(_) if word.starts_with(|char: char| char.is_ascii_digit()) => {
+                        Err(format!("invalid address {}", word))
+                    }
+                    Err(_) => Ok(Operand::Memory(Expression::Name(word))),
+                }
+            }
+            _ => Ok(Operand::Memory(self.unary()?)),
+        }
+    }
+
+    // Parse an addressing mode after the '&'
+    fn address(&mut self) -> Result<Operand, String> {
+        self.expect("[")?;
+
+        // Pre-decrement
+        if self.accept("-") {
+            let register = self.register().unwrap();
+            self.expect("]")?;
+            return Ok(Operand::Address {
+                mode: ADR_PRE_DEC,
+                register,
+                offset: None,
+            });
+        }
+
+        // Indirect
+        let register = self.register().unwrap();
+        if self.accept("]") {
+            return Ok(Operand::Address {
+                mode: ADR_INDIRECT,
+                register,
+                offset: None,
+            });
+        }
+
+        // Post-increment
+        if self.peek(0) == Some(&Token::Punctuation("+"))
+            && self.peek(1) == Some(&Token::Punctuation("]"))
+        {
+            self.index += 2;
+            return Ok(Operand::Address {
+                mode: ADR_POST_INC,
+                register,
+                offset: None,
+            });
+        }
+
+        // Offset, the terms added to or subtracted from the register
+        if !matches!(self.peek(0), Some(Token::Punctuation("+" | "-"))) {
+            return Err("expected ']', '+' or '-'".to_string());
+        }
+        let offset = self.binary_rest(Expression::Number(0), ADDITIVE)?;
+        self.expect("]")?;
+
+        Ok(Operand::Address {
+            mode: ADR_OFFSET,
+            register,
+            offset: Some(offset),
+        })
+    }
+
+    // Parse an expression
+    fn expression(&mut self) -> Result<Expression, String> {
+        self.binary(0)
+    }
+
+    // Parse binary operators of a precedence and higher
+    fn binary(&mut self, precedence: usize) -> Result<Expression, String> {
+        if precedence == OPERATORS.len() {
+            return self.unary();
+        }
+
+        let left = self.binary(precedence + 1)?;
+        self.binary_rest(left, precedence)
+    }
+
+    // Parse the operators of a precedence following the left operand
+    fn binary_rest(&mut self, left: Expression, precedence: usize) -> Result<Expression, String> {
+        let mut left = left;
+        while let Some(Token::Punctuation(operator)) = self.peek(0).cloned() {
+            if !OPERATORS[precedence].contains(&operator) {
+                break;
+            }
+            self.index += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    // Parse a number, name, unary operator or grouped expression
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expression::Number(number)),
+            Some(Token::Identifier(word))
+                if word.starts_with(|char: char| char.is_ascii_digit()) =>
+            {
+                let number = word
+                    .parse()
+                    .map_err(|_| format!("invalid number {}", word))?;
+                Ok(Expression::Number(number_to_u16(number, &word)?))
+            }
+            Some(Token::Identifier(name)) => Ok(Expression::Name(name)),
+            Some(Token::Punctuation("!")) => Ok(Expression::Name(self.identifier()?)),
+            Some(Token::Punctuation(operator @ ("-" | "~"))) => {
+                Ok(Expression::Unary(operator, Box::new(self.unary()?)))
+            }
+            Some(Token::Punctuation(open @ ("[" | "("))) => {
+                let expression = self.expression()?;
+                self.expect(if open == "[" { "]" } else { ")" })?;
+                Ok(expression)
+            }
+            Some(token) => Err(format!("expected a value, found {}", describe(&token))),
+            None => Err("expected a value".to_string()),
+        }
+    }
+}
+
+// Number of a register by name
+fn register_number(name: &str) -> Option<u8> {
+    REGISTERS
+        .iter()
+        .position(|register| register.eq_ignore_ascii_case(name))
+        .map(|index| index as u8)
+}
+
+// Describe a token in errors
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Identifier(name) => format!("\"{}\"", name),
+        Token::Number(number) => format!("number {}", number),
+        Token::String(string) => format!("string \"{}\"", string),
+        Token::Punctuation(punctuation) => format!("'{}'", punctuation),
+    }
+}
+
+// Split a line in tokens
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut index = 0;
+    while index < chars.len() {
+        let char = chars[index];
+
+        // Whitespace and comments
+        if char.is_whitespace() {
+            index += 1;
+            continue;
+        }
+        if char == ';' {
+            break;
+        }
+
+        // String or character
+        if char == '"' || char == '\'' {
+            let mut text = String::new();
+            index += 1;
+            loop {
+                let next = *chars.get(index).ok_or("missing closing quote")?;
+                index += 1;
+                match next {
+                    _ if next == char => break,
+                    '\\' => {
+                        let escaped = *chars.get(index).ok_or("missing closing quote")?;
+                        index += 1;
+                        text.push(match escaped {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            '0' => '\0',
+                            _ => escaped,
+                        });
+                    }
+                    _ => text.push(next),
+                }
+            }
+
+            // A character is its code
+            if char == '\'' {
+                let mut codes = text.encode_utf16();
+                match (codes.next(), codes.next()) {
+                    (Some(code), None) => tokens.push(Token::Number(code)),
+                    _ => return Err(format!("expected one character in '{}'", text)),
+                }
+            } else {
+                tokens.push(Token::String(text));
+            }
+            continue;
+        }
+
+        // Word
+        let start = index;
+        let is_word =
+            |char: char| char.is_ascii_alphanumeric() || char == '_' || char == '.' || char == '@';
+        if char == '$' {
+            index += 1;
+        }
+        while index < chars.len() && is_word(chars[index]) {
+            index += 1;
+        }
+        let word: String = chars[start..index].iter().collect();
+
+        // Hex number
+        if let Some(hex) = word.strip_prefix('$') {
+            let number =
+                u32::from_str_radix(hex, 16).map_err(|_| format!("invalid hex number {}", word))?;
+            tokens.push(Token::Number(number_to_u16(number, &word)?));
+            continue;
+        }
+
+        // Identifier, or a decimal number read by the parser, `&0050` is a hex
+        // address
+        if !word.is_empty() {
+            tokens.push(Token::Identifier(word));
+            continue;
+        }
+
+        // Punctuation
+        let rest: String = chars[index..].iter().take(2).collect();
+        match PUNCTUATION
+            .iter()
+            .find(|punctuation| rest.starts_with(*punctuation))
+        {
+            Some(punctuation) => {
+                tokens.push(Token::Punctuation(punctuation));
+                index += punctuation.len();
+            }
+            None => return Err(format!("unexpected character '{}'", char)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Check a value fits in a byte, unsigned or signed
+fn fits_byte(value: u16) -> bool {
+    value <= 0xFF || value >= 0xFF80
+}
+
+// Check a number fits in 16 bits
+fn number_to_u16(number: u32, text: &str) -> Result<u16, String> {
+    u16::try_from(number).map_err(|_| format!("number {} doesn't fit in 16 bits", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Offset of a symbol in its section
+    fn offset(object: &Object, name: &str) -> u16 {
+        object
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .unwrap()
+            .offset
+    }
+
+    // Bytes of the text section
+    fn bytes(source: &str) -> Vec<u8> {
+        let object = Assembler::assemble(source, "t.asm").unwrap();
+        object.section(DEFAULT_SECTION).unwrap().bytes.clone()
+    }
+
+    #[test]
+    fn expressions_use_c_precedence() {
+        assert_eq!(
+            bytes(".byte 1 | 2 == 2, 6 & 3 < 4, 1 + 2 * 3 << 1, 8 - 2 - 1"),
+            [0x01, 0x00, 0x0E, 0x05]
+        );
+    }
+
+    #[test]
+    fn words_use_the_byte_order_of_the_assembler() {
+        assert_eq!(bytes(".word $1234"), [0x12, 0x34]);
+
+        let (object, _) = Assembler::new()
+            .with_endian(Endian::Little)
+            .assemble_source("start:\n.word $1234, start + 2", "t.asm")
+            .unwrap();
+        assert_eq!(object.endian, Endian::Little);
+        assert_eq!(object.sections[0].bytes, [0x34, 0x12, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn align_uses_the_address_of_sections_with_one() {
+        let source = ".org $0401\n.byte 1\n.byte 2\n.align 4\nnext:\n.byte 3\n";
+        let object = Assembler::assemble(source, "t.asm").unwrap();
+        assert_eq!(offset(&object, "next"), 0x0003);
+
+        let source = ".byte 1\n.align 4\nnext:\n.byte 2\n";
+        let object = Assembler::assemble(source, "t.asm").unwrap();
+        assert_eq!(offset(&object, "next"), 0x0004);
+    }
+
+    #[test]
+    fn macros_expand_their_arguments_and_unique_labels() {
+        let source = "
+            .macro pair first, second
+            here@:
+            .byte first, second
+            .endm
+            pair 1, 2
+            pair $10, 2 * 3
+        ";
+        assert_eq!(bytes(source), [0x01, 0x02, 0x10, 0x06]);
+    }
+
+    #[test]
+    fn conditionals_nest_and_skip_their_blocks() {
+        let source = "
+            .define BIG 1
+            .if BIG
+            .byte 1
+            .ifdef SMALL
+            .byte 2
+            .else
+            .byte 3
+            .endif
+            .else
+            .if 1
+            .byte 4
+            .endif
+            .endif
+            .ifndef SMALL
+            .byte 5
+            .endif
+            .if BIG == 0
+            .byte 6
+            .endif
+        ";
+        assert_eq!(bytes(source), [0x01, 0x03, 0x05]);
+    }
+
+    #[test]
+    fn structures_declare_the_offsets_of_their_fields() {
+        let source = "
+            .struct point
+            x: .word
+            y: .word
+            name: .byte 8
+            .ends
+            .byte point.x, point.y, point.name, point.size
+        ";
+        assert_eq!(bytes(source), [0x00, 0x02, 0x04, 0x0C]);
+    }
+}
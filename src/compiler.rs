@@ -0,0 +1,1823 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+
+// Compiler
+//
+// Turns a small C-like language into assembler source:
+//
+//     u16 counter = 0;
+//     u8 message[] = "Hello";
+//
+//     u16 length(u8 *text) {
+//         u16 count = 0;
+//         while (text[count]) {
+//             count = count + 1;
+//         }
+//         return count;
+//     }
+//
+//     u16 main() {
+//         return length(message);
+//     }
+//
+// Types are u8, u16, i16, void, pointers `T *` and arrays `T name[n]`.
+// Statements are blocks, declarations, expressions, if/else, while, break,
+// continue, return and asm("line") for an assembler line. Expressions use
+// `=`, `||`, `&&`, `|`, `^`, `&`, comparisons, shifts, `+`, `-`, `*`, `/`,
+// `%`, the unary `-`, `~`, `!`, `*`, `&`, casts `(T)`, calls and indexing, in
+// C precedence. Numbers are decimal, 0x hex or a 'c'haracter, a negated
+// number is i16. Comparisons, `/`, `%` and `>>` are signed when an operand is
+// i16, pointer arithmetic scales by the size of the element.
+//
+// Functions use the CAL/RET calling convention. Expressions are evaluated in
+// r1 with r2 holding the left operand, locals live in the frame below fp and
+// are zeroed on entry. A program with a main function gets startup code that
+// moves the stack to high memory, calls main and halts with its result in acc.
+
+// Stack of compiled programs, the top of the high memory of the default machine
+const STACK_BASE: u16 = 0xEFFE;
+const STACK_LIMIT: u16 = 0xE000;
+
+// Offset from fp of the argument count pushed by the caller, see CPU::execute
+const ARGUMENTS_OFFSET: i32 = 22;
+
+// Frames of more words than this are zeroed with a loop
+const UNROLLED_WORDS: u16 = 4;
+
+// Punctuation, longest first
+const PUNCTUATION: [&str; 29] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+    "!", "<", ">", "=", "(", ")", "[", "]", "{", "}", ",", ";",
+];
+
+// Binary operators by precedence, lowest first
+const OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// Words that can't be names
+const KEYWORDS: [&str; 11] = [
+    "u8", "u16", "i16", "void", "if", "else", "while", "return", "break", "continue", "asm",
+];
+
+// Type
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Void,
+    U8,
+    U16,
+    I16,
+    Pointer(Box<Type>),
+    Array(Box<Type>, u16),
+}
+
+// Type implementation
+impl Type {
+    // Size in bytes
+    fn size(&self) -> u16 {
+        match self {
+            Type::Void => 0,
+            Type::U8 => 1,
+            Type::U16 | Type::I16 | Type::Pointer(_) => 2,
+            Type::Array(element, count) => element.size().saturating_mul(*count),
+        }
+    }
+
+    // Array of count elements, which must fit in memory
+    fn array(element: Type, count: u16) -> Result<Type, String> {
+        if element.size().checked_mul(count).is_none() {
+            return Err(String::from("array is too large"));
+        }
+        Ok(Type::Array(Box::new(element), count))
+    }
+
+    // Type pointed at by a pointer or the element of an array
+    fn element(&self) -> Option<&Type> {
+        match self {
+            Type::Pointer(element) | Type::Array(element, _) => Some(element),
+            _ => None,
+        }
+    }
+
+    // Type of the value, arrays are used as a pointer to their first element
+    fn decay(self) -> Type {
+        match self {
+            Type::Array(element, _) => Type::Pointer(element),
+            other => other,
+        }
+    }
+}
+
+// Write a type the way it's declared
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::U8 => write!(f, "u8"),
+            Type::U16 => write!(f, "u16"),
+            Type::I16 => write!(f, "i16"),
+            Type::Pointer(element) => write!(f, "{}*", element),
+            Type::Array(element, count) => write!(f, "{}[{}]", element, count),
+        }
+    }
+}
+
+// Token of the source
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(u16),
+    String(Vec<u8>),
+    Punctuation(&'static str),
+}
+
+// Expression
+#[derive(Clone, Debug)]
+enum Expression {
+    Number(u16),
+    String(Vec<u8>),
+    Name(String),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Assign(Box<Expression>, Box<Expression>),
+    Call(String, Vec<Expression>),
+    Index(Box<Expression>, Box<Expression>),
+    Cast(Type, Box<Expression>),
+}
+
+// Statement and the line it starts on
+#[derive(Clone, Debug)]
+struct Statement {
+    line: usize,
+    kind: StatementKind,
+}
+
+// Kinds of statements
+#[derive(Clone, Debug)]
+enum StatementKind {
+    Local(String, Type, Option<Expression>),
+    Expression(Expression),
+    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    While(Expression, Box<Statement>),
+    Return(Option<Expression>),
+    Break,
+    Continue,
+    Block(Vec<Statement>),
+    Asm(String),
+}
+
+// Initial value of a global
+#[derive(Clone, Debug)]
+enum Initializer {
+    Values(Vec<u16>),
+    String(Vec<u8>),
+}
+
+// Function, without a body when it's only declared
+struct Function {
+    name: String,
+    result: Type,
+    parameters: Vec<(String, Type)>,
+    body: Option<Vec<Statement>>,
+    line: usize,
+}
+
+// Global variable
+struct Global {
+    name: String,
+    kind: Type,
+    initializer: Option<Initializer>,
+    line: usize,
+}
+
+// Compile a source file to assembler source
+pub fn compile_file(path: &str) -> Result<String, String> {
+    let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    compile(&source, path)
+}
+
+// Compile source to assembler source, file is used in errors
+pub fn compile(source: &str, file: &str) -> Result<String, String> {
+    let tokens =
+        tokenize(source).map_err(|(line, error)| format!("{}:{}: {}", file, line, error))?;
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        functions: Vec::new(),
+        globals: Vec::new(),
+    };
+    parser
+        .program()
+        .map_err(|error| format!("{}:{}: {}", file, parser.line(), error))?;
+
+    let mut generator = Generator::new(source, &parser.functions, &parser.globals)
+        .map_err(|(line, error)| format!("{}:{}: {}", file, line, error))?;
+    generator
+        .program(&parser.functions, &parser.globals)
+        .map_err(|error| format!("{}:{}: {}", file, generator.line, error))?;
+    Ok(generator.output)
+}
+
+// Parser of the tokens of a source file
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    functions: Vec<Function>,
+    globals: Vec<Global>,
+}
+
+// Parser implementation
+impl Parser {
+    // Line of the current token
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .or(self.tokens.last())
+            .map_or(1, |token| token.1)
+    }
+
+    // Look at a token ahead
+    fn peek(&self, ahead: usize) -> Option<&Token> {
+        self.tokens.get(self.index + ahead).map(|token| &token.0)
+    }
+
+    // Take the next token
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek(0).cloned();
+        self.index += 1;
+        token
+    }
+
+    // Take a punctuation if it's next
+    fn accept(&mut self, punctuation: &str) -> bool {
+        match self.peek(0) {
+            Some(Token::Punctuation(next)) if *next == punctuation => {
+                self.index += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Take a keyword if it's next
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek(0) {
+            Some(Token::Identifier(next)) if next == keyword => {
+                self.index += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Take a punctuation that must be next
+    fn expect(&mut self, punctuation: &str) -> Result<(), String> {
+        match self.accept(punctuation) {
+            true => Ok(()),
+            false => Err(format!(
+                "expected '{}', found {}",
+                punctuation,
+                self.found()
+            )),
+        }
+    }
+
+    // Describe the next token in errors
+    fn found(&self) -> String {
+        match self.peek(0) {
+            Some(Token::Identifier(name)) => format!("\"{}\"", name),
+            Some(Token::Number(number)) => format!("number {}", number),
+            Some(Token::String(_)) => String::from("a string"),
+            Some(Token::Punctuation(punctuation)) => format!("'{}'", punctuation),
+            None => String::from("the end of the file"),
+        }
+    }
+
+    // Read a name
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek(0) {
+            Some(Token::Identifier(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.index += 1;
+                Ok(name)
+            }
+            _ => Err(format!("expected a name, found {}", self.found())),
+        }
+    }
+
+    // Check if a type comes next
+    fn is_type(&self, ahead: usize) -> bool {
+        matches!(self.peek(ahead), Some(Token::Identifier(name)) if ["u8", "u16", "i16", "void"].contains(&name.as_str()))
+    }
+
+    // Read a type and its pointer stars
+    fn kind(&mut self) -> Result<Type, String> {
+        let mut kind = match self.next() {
+            Some(Token::Identifier(name)) => match name.as_str() {
+                "u8" => Type::U8,
+                "u16" => Type::U16,
+                "i16" => Type::I16,
+                "void" => Type::Void,
+                _ => return Err(format!("expected a type, found \"{}\"", name)),
+            },
+            _ => {
+                self.index -= 1;
+                return Err(format!("expected a type, found {}", self.found()));
+            }
+        };
+        while self.accept("*") {
+            kind = Type::Pointer(Box::new(kind));
+        }
+
+        Ok(kind)
+    }
+
+    // Read the size of an array, none for `[]`
+    fn array_size(&mut self) -> Result<Option<u16>, String> {
+        if self.accept("]") {
+            return Ok(None);
+        }
+        let size = constant(&self.expression()?).ok_or("array size must be a constant")?;
+        self.expect("]")?;
+        Ok(Some(size))
+    }
+
+    // Read the whole program
+    fn program(&mut self) -> Result<(), String> {
+        while self.peek(0).is_some() {
+            let line = self.line();
+            let kind = self.kind()?;
+            let name = self.name()?;
+            if self.accept("(") {
+                self.function(name, kind, line)?;
+            } else {
+                self.global(name, kind, line)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Read a function after its name
+    fn function(&mut self, name: String, result: Type, line: usize) -> Result<(), String> {
+        // Parameters
+        let mut parameters = Vec::new();
+        if self.peek(0) == Some(&Token::Identifier(String::from("void")))
+            && self.peek(1) == Some(&Token::Punctuation(")"))
+        {
+            self.index += 1;
+        } else {
+            while self.peek(0) != Some(&Token::Punctuation(")")) {
+                let kind = self.kind()?;
+                let name = self.name()?;
+                if kind == Type::Void {
+                    return Err(format!("parameter {} can't be void", name));
+                }
+                parameters.push((name, kind));
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+
+        // Body, or only a declaration
+        let body = match self.accept(";") {
+            true => None,
+            false => {
+                self.expect("{")?;
+                Some(self.block()?)
+            }
+        };
+
+        self.functions.push(Function {
+            name,
+            result,
+            parameters,
+            body,
+            line,
+        });
+        Ok(())
+    }
+
+    // Read a global variable after its name
+    fn global(&mut self, name: String, mut kind: Type, line: usize) -> Result<(), String> {
+        let size = match self.accept("[") {
+            true => Some(self.array_size()?),
+            false => None,
+        };
+
+        // Initial value
+        let initializer = match self.accept("=") {
+            true => Some(self.initializer()?),
+            false => None,
+        };
+
+        // Arrays take their size from the initial value when it isn't given
+        if let Some(size) = size {
+            let count = match (size, &initializer) {
+                (Some(size), _) => size,
+                (None, Some(Initializer::Values(values))) => values.len() as u16,
+                (None, Some(Initializer::String(text))) => text.len() as u16 + 1,
+                (None, None) => return Err(format!("array {} needs a size", name)),
+            };
+            kind = Type::array(kind, count)?;
+        }
+        self.expect(";")?;
+
+        self.globals.push(Global {
+            name,
+            kind,
+            initializer,
+            line,
+        });
+        Ok(())
+    }
+
+    // Read the initial value of a global, constants in braces or a string
+    fn initializer(&mut self) -> Result<Initializer, String> {
+        if let Some(Token::String(text)) = self.peek(0) {
+            let text = text.clone();
+            self.index += 1;
+            return Ok(Initializer::String(text));
+        }
+
+        let mut values = Vec::new();
+        let braces = self.accept("{");
+        loop {
+            if braces && self.peek(0) == Some(&Token::Punctuation("}")) {
+                break;
+            }
+            let value = constant(&self.expression()?).ok_or("initial value must be a constant")?;
+            values.push(value);
+            if !braces || !self.accept(",") {
+                break;
+            }
+        }
+        if braces {
+            self.expect("}")?;
+        }
+
+        Ok(Initializer::Values(values))
+    }
+
+    // Read the statements of a block after its {
+    fn block(&mut self) -> Result<Vec<Statement>, String> {
+        let mut statements = Vec::new();
+        while !self.accept("}") {
+            if self.peek(0).is_none() {
+                return Err("expected '}', found the end of the file".to_string());
+            }
+            statements.push(self.statement()?);
+        }
+
+        Ok(statements)
+    }
+
+    // Read a statement
+    fn statement(&mut self) -> Result<Statement, String> {
+        let line = self.line();
+        let kind = if self.accept("{") {
+            StatementKind::Block(self.block()?)
+        } else if self.accept(";") {
+            StatementKind::Block(Vec::new())
+        } else if self.accept_keyword("if") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            let then = Box::new(self.statement()?);
+            let otherwise = match self.accept_keyword("else") {
+                true => Some(Box::new(self.statement()?)),
+                false => None,
+            };
+            StatementKind::If(condition, then, otherwise)
+        } else if self.accept_keyword("while") {
+            self.expect("(")?;
+            let condition = self.expression()?;
+            self.expect(")")?;
+            StatementKind::While(condition, Box::new(self.statement()?))
+        } else if self.accept_keyword("return") {
+            let value = match self.accept(";") {
+                true => None,
+                false => {
+                    let value = self.expression()?;
+                    self.expect(";")?;
+                    Some(value)
+                }
+            };
+            StatementKind::Return(value)
+        } else if self.accept_keyword("break") {
+            self.expect(";")?;
+            StatementKind::Break
+        } else if self.accept_keyword("continue") {
+            self.expect(";")?;
+            StatementKind::Continue
+        } else if self.accept_keyword("asm") {
+            self.expect("(")?;
+            let line = match self.next() {
+                Some(Token::String(text)) => String::from_utf8_lossy(&text).into_owned(),
+                _ => {
+                    self.index -= 1;
+                    return Err(format!("expected a string, found {}", self.found()));
+                }
+            };
+            self.expect(")")?;
+            self.expect(";")?;
+            StatementKind::Asm(line)
+        } else if self.is_type(0) {
+            // Local variable
+            let mut kind = self.kind()?;
+            let name = self.name()?;
+            if self.accept("[") {
+                let size = self.array_size()?.ok_or("local arrays need a size")?;
+                kind = Type::array(kind, size)?;
+            }
+            let value = match self.accept("=") {
+                true => Some(self.expression()?),
+                false => None,
+            };
+            self.expect(";")?;
+            StatementKind::Local(name, kind, value)
+        } else {
+            let expression = self.expression()?;
+            self.expect(";")?;
+            StatementKind::Expression(expression)
+        };
+
+        Ok(Statement { line, kind })
+    }
+
+    // Read an expression
+    fn expression(&mut self) -> Result<Expression, String> {
+        let target = self.binary(0)?;
+        if self.accept("=") {
+            let value = self.expression()?;
+            return Ok(Expression::Assign(Box::new(target), Box::new(value)));
+        }
+
+        Ok(target)
+    }
+
+    // Read binary operators of a precedence and higher
+    fn binary(&mut self, precedence: usize) -> Result<Expression, String> {
+        if precedence == OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(precedence + 1)?;
+        while let Some(Token::Punctuation(operator)) = self.peek(0) {
+            let operator = *operator;
+            if !OPERATORS[precedence].contains(&operator) {
+                break;
+            }
+            self.index += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    // Read unary operators, casts and what they apply to
+    fn unary(&mut self) -> Result<Expression, String> {
+        // Cast
+        if self.peek(0) == Some(&Token::Punctuation("(")) && self.is_type(1) {
+            self.index += 1;
+            let kind = self.kind()?;
+            self.expect(")")?;
+            return Ok(Expression::Cast(kind, Box::new(self.unary()?)));
+        }
+
+        // Unary operator
+        for operator in ["-", "~", "!", "*", "&"] {
+            if self.accept(operator) {
+                return Ok(Expression::Unary(operator, Box::new(self.unary()?)));
+            }
+        }
+
+        self.postfix()
+    }
+
+    // Read a value and its calls and indexes
+    fn postfix(&mut self) -> Result<Expression, String> {
+        let mut value = match self.next() {
+            Some(Token::Number(number)) => Expression::Number(number),
+            Some(Token::String(text)) => Expression::String(text),
+            Some(Token::Punctuation("(")) => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            Some(Token::Identifier(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                Expression::Name(name)
+            }
+            _ => {
+                self.index -= 1;
+                return Err(format!("expected a value, found {}", self.found()));
+            }
+        };
+
+        loop {
+            if self.accept("(") {
+                let Expression::Name(name) = value else {
+                    return Err("only functions can be called".to_string());
+                };
+                let mut arguments = Vec::new();
+                while self.peek(0) != Some(&Token::Punctuation(")")) {
+                    arguments.push(self.expression()?);
+                    if !self.accept(",") {
+                        break;
+                    }
+                }
+                self.expect(")")?;
+                value = Expression::Call(name, arguments);
+            } else if self.accept("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                value = Expression::Index(Box::new(value), Box::new(index));
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+// Value of an expression made of numbers
+fn constant(expression: &Expression) -> Option<u16> {
+    match expression {
+        Expression::Number(number) => Some(*number),
+        Expression::Cast(Type::U8, value) => Some(constant(value)? & 0xFF),
+        Expression::Cast(_, value) => constant(value),
+        Expression::Unary("-", value) => Some(constant(value)?.wrapping_neg()),
+        Expression::Unary("~", value) => Some(!constant(value)?),
+        Expression::Binary(operator, left, right) => {
+            let (left, right) = (constant(left)?, constant(right)?);
+            match *operator {
+                "+" => Some(left.wrapping_add(right)),
+                "-" => Some(left.wrapping_sub(right)),
+                "*" => Some(left.wrapping_mul(right)),
+                "/" => left.checked_div(right),
+                "%" => left.checked_rem(right),
+                "<<" => Some(left.checked_shl(right as u32).unwrap_or(0)),
+                ">>" => Some(left.checked_shr(right as u32).unwrap_or(0)),
+                "&" => Some(left & right),
+                "|" => Some(left | right),
+                "^" => Some(left ^ right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Local variable or parameter, at fp plus offset
+#[derive(Clone)]
+struct Local {
+    kind: Type,
+    offset: i32,
+}
+
+// Signature of a function
+struct Signature {
+    result: Type,
+    parameters: Vec<Type>,
+}
+
+// Generator of assembler source
+struct Generator<'a> {
+    output: String,
+    lines: Vec<&'a str>,
+    line: usize,
+    signatures: HashMap<String, Signature>,
+    globals: HashMap<String, Type>,
+    strings: Vec<Vec<u8>>,
+    labels: usize,
+    scopes: Vec<HashMap<String, Local>>,
+    frame: u16,
+    loops: Vec<(String, String)>,
+    result: Type,
+    division: bool,
+}
+
+// Generator implementation
+impl<'a> Generator<'a> {
+    // Collect the signatures of the functions and the types of the globals
+    fn new(
+        source: &'a str,
+        functions: &[Function],
+        globals: &[Global],
+    ) -> Result<Self, (usize, String)> {
+        let mut signatures: HashMap<String, Signature> = HashMap::new();
+        for function in functions.iter() {
+            let signature = Signature {
+                result: function.result.clone(),
+                parameters: function.parameters.iter().map(|p| p.1.clone()).collect(),
+            };
+            if let Some(other) = signatures.get(&function.name) {
+                if other.result != signature.result || other.parameters != signature.parameters {
+                    let error =
+                        format!("function {} is declared differently before", function.name);
+                    return Err((function.line, error));
+                }
+            }
+            signatures.insert(function.name.clone(), signature);
+        }
+        for (index, function) in functions.iter().enumerate() {
+            let defined_before = functions[..index]
+                .iter()
+                .any(|other| other.name == function.name && other.body.is_some());
+            if function.body.is_some() && defined_before {
+                let error = format!("function {} is defined more than once", function.name);
+                return Err((function.line, error));
+            }
+        }
+
+        let mut types = HashMap::new();
+        for global in globals.iter() {
+            if global.kind == Type::Void {
+                return Err((
+                    global.line,
+                    format!("variable {} can't be void", global.name),
+                ));
+            }
+            if signatures.contains_key(&global.name)
+                || types
+                    .insert(global.name.clone(), global.kind.clone())
+                    .is_some()
+            {
+                return Err((
+                    global.line,
+                    format!("{} is defined more than once", global.name),
+                ));
+            }
+        }
+
+        Ok(Self {
+            output: String::new(),
+            lines: source.lines().collect(),
+            line: 0,
+            signatures,
+            globals: types,
+            strings: Vec::new(),
+            labels: 0,
+            scopes: Vec::new(),
+            frame: 0,
+            loops: Vec::new(),
+            result: Type::Void,
+            division: false,
+        })
+    }
+
+    // Append an instruction
+    fn emit(&mut self, instruction: &str) {
+        self.output.push_str("    ");
+        self.output.push_str(instruction);
+        self.output.push('\n');
+    }
+
+    // Append a label
+    fn label(&mut self, label: &str) {
+        self.output.push_str(label);
+        self.output.push_str(":\n");
+    }
+
+    // Make a label unique in the program
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("_L{}", self.labels)
+    }
+
+    // Jump to a label, acc always equals itself
+    fn jump(&mut self, label: &str) {
+        self.emit(&format!("jeq acc, &[!{}]", label));
+    }
+
+    // Jump to a label if r1 is zero
+    fn jump_if_zero(&mut self, label: &str) {
+        self.emit("mov $0000, acc");
+        self.emit(&format!("jeq r1, &[!{}]", label));
+    }
+
+    // Generate the whole program
+    fn program(&mut self, functions: &[Function], globals: &[Global]) -> Result<(), String> {
+        // Startup
+        let main = functions
+            .iter()
+            .any(|function| function.name == "main" && function.body.is_some());
+        if main {
+            if !self.signatures["main"].parameters.is_empty() {
+                self.line = functions
+                    .iter()
+                    .find(|function| function.name == "main")
+                    .unwrap()
+                    .line;
+                return Err("main can't take parameters".to_string());
+            }
+            self.output
+                .push_str("; Startup, run main and halt with its result in acc\n");
+            self.label("start");
+            self.emit(&format!("mov ${:04X}, sb", STACK_BASE));
+            self.emit(&format!("mov ${:04X}, sl", STACK_LIMIT));
+            self.emit(&format!("mov ${:04X}, sp", STACK_BASE));
+            self.emit(&format!("mov ${:04X}, fp", STACK_BASE));
+            self.emit("psh $0000");
+            self.emit("cal !main");
+            self.emit("hlt");
+        }
+
+        // Functions
+        for function in functions.iter() {
+            if let Some(body) = &function.body {
+                self.line = function.line;
+                self.function(function, body)?;
+            }
+        }
+
+        // Functions only declared come from other objects
+        let mut externs: Vec<&str> = Vec::new();
+        for function in functions.iter() {
+            let defined = functions
+                .iter()
+                .any(|other| other.name == function.name && other.body.is_some());
+            if !defined && !externs.contains(&function.name.as_str()) {
+                externs.push(&function.name);
+            }
+        }
+        if !externs.is_empty() {
+            self.output.push('\n');
+            for name in externs {
+                self.output.push_str(&format!(".extern {}\n", name));
+            }
+        }
+
+        // Runtime
+        if self.division {
+            self.output.push('\n');
+            self.output.push_str(RUNTIME_DIVISION);
+        }
+
+        // Globals and strings
+        if !globals.is_empty() || !self.strings.is_empty() {
+            self.output.push_str("\n.section data\n");
+        }
+        for global in globals.iter() {
+            self.line = global.line;
+            self.global(global)?;
+        }
+        for (index, text) in std::mem::take(&mut self.strings).iter().enumerate() {
+            self.output
+                .push_str(&format!("_S{}: {}\n", index, string(text)));
+        }
+
+        Ok(())
+    }
+
+    // Generate a function
+    fn function(&mut self, function: &Function, body: &[Statement]) -> Result<(), String> {
+        // Parameters, the first is the farthest from fp
+        let count = function.parameters.len() as i32;
+        let mut parameters = HashMap::new();
+        for (index, (name, kind)) in function.parameters.iter().enumerate() {
+            let mut offset = ARGUMENTS_OFFSET + 2 * (count - index as i32);
+
+            // Bytes are the low byte of their word
+            if *kind == Type::U8 {
+                offset += 1;
+            }
+            let kind = kind.clone().decay();
+            if parameters
+                .insert(name.clone(), Local { kind, offset })
+                .is_some()
+            {
+                return Err(format!("parameter {} is defined more than once", name));
+            }
+        }
+
+        // Body, generated first to know the size of the frame
+        let output = std::mem::take(&mut self.output);
+        self.scopes = vec![parameters];
+        self.frame = 0;
+        self.result = function.result.clone();
+        for statement in body.iter() {
+            self.statement(statement)?;
+        }
+        if !self.output.ends_with("    ret\n") {
+            self.emit("ret");
+        }
+        let body = std::mem::replace(&mut self.output, output);
+
+        // Entry, zeroing the frame
+        let parameters: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|(name, kind)| format!("{} {}", kind, name))
+            .collect();
+        self.output.push_str(&format!(
+            "\n; {} {}({})\n.global {}\n",
+            function.result,
+            function.name,
+            parameters.join(", "),
+            function.name
+        ));
+        self.label(&function.name);
+        let words = self.frame / 2;
+        if words > UNROLLED_WORDS {
+            let label = self.new_label();
+            self.emit(&format!("mov ${:04X}, r1", words));
+            self.label(&label);
+            self.emit("psh $0000");
+            self.emit("dec r1");
+            self.emit("mov $0000, acc");
+            self.emit(&format!("jne r1, &[!{}]", label));
+        } else {
+            for _ in 0..words {
+                self.emit("psh $0000");
+            }
+        }
+        self.output.push_str(&body);
+        Ok(())
+    }
+
+    // Generate a global
+    fn global(&mut self, global: &Global) -> Result<(), String> {
+        let (element, count) = match &global.kind {
+            Type::Array(element, count) => (element.as_ref().clone(), *count),
+            kind => (kind.clone(), 1),
+        };
+        let data = match &global.initializer {
+            None => format!(".fill ${:04X}", global.kind.size()),
+
+            // Text of a byte array
+            Some(Initializer::String(text))
+                if element == Type::U8 && matches!(global.kind, Type::Array(..)) =>
+            {
+                if text.len() >= count as usize {
+                    return Err(format!("string doesn't fit in {}", global.name));
+                }
+                let mut data = string(text);
+                let padding = count as usize - text.len() - 1;
+                if padding > 0 {
+                    data.push_str(&format!("\n    .fill ${:04X}", padding));
+                }
+                data
+            }
+
+            // Pointer to a string
+            Some(Initializer::String(text)) if global.kind == Type::Pointer(Box::new(Type::U8)) => {
+                self.strings.push(text.clone());
+                format!(".word _S{}", self.strings.len() - 1)
+            }
+            Some(Initializer::String(_)) => {
+                return Err(format!("{} can't hold a string", global.name))
+            }
+
+            // Values
+            Some(Initializer::Values(values)) => {
+                if values.len() > count as usize
+                    || (values.len() > 1 && !matches!(global.kind, Type::Array(..)))
+                {
+                    return Err(format!("too many values for {}", global.name));
+                }
+                let directive = if element == Type::U8 {
+                    ".byte"
+                } else {
+                    ".word"
+                };
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|value| match element {
+                        Type::U8 => format!("${:02X}", value & 0xFF),
+                        _ => format!("${:04X}", value),
+                    })
+                    .collect();
+                let mut data = format!("{} {}", directive, values.join(", "));
+                let padding = (count as usize - values.len()) * element.size() as usize;
+                if padding > 0 {
+                    data.push_str(&format!("\n    .fill ${:04X}", padding));
+                }
+                data
+            }
+        };
+
+        self.output.push_str(&format!(
+            ".global {}\n{}: {}\n",
+            global.name, global.name, data
+        ));
+        Ok(())
+    }
+
+    // Generate a statement
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        // Source line as a comment
+        if statement.line != self.line && !matches!(statement.kind, StatementKind::Block(_)) {
+            let text = self
+                .lines
+                .get(statement.line - 1)
+                .map_or("", |line| line.trim());
+            self.output
+                .push_str(&format!("    ; {}: {}\n", statement.line, text));
+        }
+        self.line = statement.line;
+
+        match &statement.kind {
+            StatementKind::Local(name, kind, value) => {
+                if *kind == Type::Void {
+                    return Err(format!("variable {} can't be void", name));
+                }
+
+                // Allocate below the other locals, in whole words
+                let size = kind.size().next_multiple_of(2);
+                self.frame = self
+                    .frame
+                    .checked_add(size)
+                    .filter(|frame| *frame < 0x8000)
+                    .ok_or("locals don't fit in the frame")?;
+                let local = Local {
+                    kind: kind.clone(),
+                    offset: 2 - self.frame as i32,
+                };
+                let scope = self.scopes.last_mut().unwrap();
+                if scope.insert(name.clone(), local).is_some() {
+                    return Err(format!("{} is defined more than once", name));
+                }
+
+                // Initial value
+                if let Some(value) = value {
+                    let target = Expression::Name(name.clone());
+                    self.assign(&target, value)?;
+                }
+            }
+            StatementKind::Expression(expression) => {
+                self.expression(expression)?;
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                let (else_label, end_label) = (self.new_label(), self.new_label());
+                self.value(condition)?;
+                self.jump_if_zero(&else_label);
+                self.statement(then)?;
+                if let Some(otherwise) = otherwise {
+                    self.jump(&end_label);
+                    self.label(&else_label);
+                    self.statement(otherwise)?;
+                    self.label(&end_label);
+                } else {
+                    self.label(&else_label);
+                }
+            }
+            StatementKind::While(condition, body) => {
+                let (start_label, end_label) = (self.new_label(), self.new_label());
+                self.label(&start_label);
+                self.value(condition)?;
+                self.jump_if_zero(&end_label);
+                self.loops.push((start_label.clone(), end_label.clone()));
+                self.statement(body)?;
+                self.loops.pop();
+                self.jump(&start_label);
+                self.label(&end_label);
+            }
+            StatementKind::Return(value) => {
+                match (value, &self.result) {
+                    (Some(_), Type::Void) => return Err("void function returns a value".into()),
+                    (Some(value), _) => {
+                        self.value(value)?;
+                        self.emit("mov r1, acc");
+                    }
+                    (None, _) => {}
+                }
+                self.emit("ret");
+            }
+            StatementKind::Break => {
+                let (_, end) = self.loops.last().ok_or("break outside of a loop")?.clone();
+                self.jump(&end);
+            }
+            StatementKind::Continue => {
+                let (start, _) = self
+                    .loops
+                    .last()
+                    .ok_or("continue outside of a loop")?
+                    .clone();
+                self.jump(&start);
+            }
+            StatementKind::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements.iter() {
+                    self.statement(statement)?;
+                }
+                self.scopes.pop();
+            }
+            StatementKind::Asm(line) => self.emit(line),
+        }
+
+        Ok(())
+    }
+
+    // Find a local variable or parameter
+    fn local(&self, name: &str) -> Option<&Local> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // Generate an expression that must have a value
+    fn value(&mut self, expression: &Expression) -> Result<Type, String> {
+        match self.expression(expression)? {
+            Type::Void => Err("void value used".to_string()),
+            kind => Ok(kind),
+        }
+    }
+
+    // Generate an expression, leaving its value in r1
+    fn expression(&mut self, expression: &Expression) -> Result<Type, String> {
+        match expression {
+            Expression::Number(number) => {
+                self.emit(&format!("mov ${:04X}, r1", number));
+                Ok(Type::U16)
+            }
+            Expression::String(text) => {
+                self.strings.push(text.clone());
+                self.emit(&format!("mov !_S{}, r1", self.strings.len() - 1));
+                Ok(Type::Pointer(Box::new(Type::U8)))
+            }
+            Expression::Name(name) => {
+                // Arrays are their address
+                let kind = match self.local(name) {
+                    Some(local) => local.kind.clone(),
+                    None => self.global_type(name)?,
+                };
+                if let Type::Array(..) = kind {
+                    self.address(expression)?;
+                    return Ok(kind.decay());
+                }
+
+                let operand = self.operand(name)?;
+                self.load(&kind, &operand);
+                Ok(kind)
+            }
+            Expression::Unary(operator, value) => {
+                // Addresses
+                match *operator {
+                    "&" => {
+                        let kind = self.address(value)?;
+                        return Ok(Type::Pointer(Box::new(kind)));
+                    }
+                    "*" => {
+                        let kind = self.address(expression)?;
+                        if let Type::Array(..) = kind {
+                            return Ok(kind.decay());
+                        }
+                        self.load(&kind, "&[r1]");
+                        return Ok(kind);
+                    }
+                    _ => {}
+                }
+
+                let kind = self.value(value)?;
+                match *operator {
+                    "-" => {
+                        self.emit("not r1");
+                        self.emit("inc acc");
+                        self.emit("mov acc, r1");
+
+                        // A negative number is signed
+                        if let Expression::Number(_) = **value {
+                            return Ok(Type::I16);
+                        }
+                    }
+                    "~" => {
+                        self.emit("not r1");
+                        self.emit("mov acc, r1");
+                    }
+                    _ => {
+                        self.emit("mov r1, r2");
+                        self.emit("mov $0000, r1");
+                        self.compare("jeq");
+                        return Ok(Type::U16);
+                    }
+                }
+                Ok(kind)
+            }
+            Expression::Binary(operator, left, right) => self.binary(operator, left, right),
+            Expression::Assign(target, value) => self.assign(target, value),
+            Expression::Call(name, arguments) => {
+                let signature = self
+                    .signatures
+                    .get(name)
+                    .ok_or_else(|| format!("undefined function {}", name))?;
+                if signature.parameters.len() != arguments.len() {
+                    return Err(format!(
+                        "function {} takes {} arguments, found {}",
+                        name,
+                        signature.parameters.len(),
+                        arguments.len()
+                    ));
+                }
+                let result = signature.result.clone();
+
+                // Push arguments and their count, CAL saves r1 to r8 and RET
+                // drops the arguments
+                for argument in arguments.iter() {
+                    self.value(argument)?;
+                    self.emit("psh r1");
+                }
+                self.emit(&format!("psh ${:04X}", arguments.len()));
+                self.emit(&format!("cal !{}", name));
+                if result != Type::Void {
+                    self.emit("mov acc, r1");
+                }
+                Ok(result)
+            }
+            Expression::Index(..) => {
+                let kind = self.address(expression)?;
+                if let Type::Array(..) = kind {
+                    return Ok(kind.decay());
+                }
+                self.load(&kind, "&[r1]");
+                Ok(kind)
+            }
+            Expression::Cast(kind, value) => {
+                self.value(value)?;
+                match kind {
+                    Type::Void | Type::Array(..) => return Err(format!("can't cast to {}", kind)),
+                    Type::U8 => {
+                        self.emit("and r1, $00FF");
+                        self.emit("mov acc, r1");
+                    }
+                    _ => {}
+                }
+                Ok(kind.clone())
+            }
+        }
+    }
+
+    // Type of a global
+    fn global_type(&self, name: &str) -> Result<Type, String> {
+        match self.globals.get(name) {
+            Some(kind) => Ok(kind.clone()),
+            None if self.signatures.contains_key(name) => {
+                Err(format!("function {} is used as a value", name))
+            }
+            None => Err(format!("undefined name {}", name)),
+        }
+    }
+
+    // Memory operand of a variable
+    fn operand(&self, name: &str) -> Result<String, String> {
+        match self.local(name) {
+            Some(local) if local.offset < 0 => Ok(format!("&[fp - ${:04X}]", -local.offset)),
+            Some(local) => Ok(format!("&[fp + ${:04X}]", local.offset)),
+            None => {
+                self.global_type(name)?;
+                Ok(format!("&[!{}]", name))
+            }
+        }
+    }
+
+    // Load r1 from memory
+    fn load(&mut self, kind: &Type, operand: &str) {
+        match kind {
+            Type::U8 => self.emit(&format!("mov8 {}, r1", operand)),
+            _ => self.emit(&format!("mov {}, r1", operand)),
+        }
+    }
+
+    // Store r1 to memory
+    fn store(&mut self, kind: &Type, operand: &str) {
+        match kind {
+            Type::U8 => self.emit(&format!("mov8 r1, {}", operand)),
+            _ => self.emit(&format!("mov r1, {}", operand)),
+        }
+    }
+
+    // Generate the address of a variable or memory in r1, returns its type
+    fn address(&mut self, expression: &Expression) -> Result<Type, String> {
+        match expression {
+            Expression::Name(name) => match self.local(name).cloned() {
+                Some(local) => {
+                    if local.offset < 0 {
+                        self.emit(&format!("sub ${:04X}, fp", -local.offset));
+                    } else {
+                        self.emit(&format!("add ${:04X}, fp", local.offset));
+                    }
+                    self.emit("mov acc, r1");
+                    Ok(local.kind)
+                }
+                None => {
+                    let kind = self.global_type(name)?;
+                    self.emit(&format!("mov !{}, r1", name));
+                    Ok(kind)
+                }
+            },
+            Expression::Unary("*", pointer) => {
+                let kind = self.value(pointer)?;
+                match kind.element() {
+                    Some(Type::Void) | None => Err(format!("can't dereference {}", kind)),
+                    Some(element) => Ok(element.clone()),
+                }
+            }
+            Expression::Index(array, index) => {
+                let pointer = Expression::Binary("+", array.clone(), index.clone());
+                let kind = self.value(&pointer)?;
+                match kind.element() {
+                    Some(Type::Void) | None => Err(format!("can't index {}", kind)),
+                    Some(element) => Ok(element.clone()),
+                }
+            }
+            _ => Err("expression has no address".to_string()),
+        }
+    }
+
+    // Generate an assignment
+    fn assign(&mut self, target: &Expression, value: &Expression) -> Result<Type, String> {
+        let kind = self.value(value)?;
+
+        // Variables are stored directly
+        if let Expression::Name(name) = target {
+            let target_kind = match self.local(name) {
+                Some(local) => local.kind.clone(),
+                None => self.global_type(name)?,
+            };
+            if let Type::Array(..) = target_kind {
+                return Err(format!("can't assign to array {}", name));
+            }
+            let operand = self.operand(name)?;
+            self.store(&target_kind, &operand);
+            return Ok(kind);
+        }
+
+        // Memory through its address
+        self.emit("psh r1");
+        let target_kind = self.address(target)?;
+        if let Type::Array(..) = target_kind {
+            return Err("can't assign to an array".to_string());
+        }
+        self.emit("mov r1, r2");
+        self.emit("pop r1");
+        self.store(&target_kind, "&[r2]");
+        Ok(kind)
+    }
+
+    // Generate a binary operator
+    fn binary(
+        &mut self,
+        operator: &str,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<Type, String> {
+        // Logical operators skip the right side when the left decides
+        if operator == "&&" || operator == "||" {
+            let (short, end) = (self.new_label(), self.new_label());
+            let jump = if operator == "&&" { "jeq" } else { "jne" };
+            for side in [left, right] {
+                self.value(side)?;
+                self.emit("mov $0000, acc");
+                self.emit(&format!("{} r1, &[!{}]", jump, short));
+            }
+            self.emit(&format!("mov ${:04X}, r1", (operator == "&&") as u16));
+            self.jump(&end);
+            self.label(&short);
+            self.emit(&format!("mov ${:04X}, r1", (operator == "||") as u16));
+            self.label(&end);
+            return Ok(Type::U16);
+        }
+
+        // Left side in r2, right side in r1
+        let left_kind = self.value(left)?;
+        self.emit("psh r1");
+        let right_kind = self.value(right)?;
+        self.emit("pop r2");
+        let pointers = (left_kind.element().cloned(), right_kind.element().cloned());
+        let signed = left_kind == Type::I16 || right_kind == Type::I16;
+        let kind = if signed { Type::I16 } else { Type::U16 };
+
+        match operator {
+            // Pointer arithmetic
+            "+" | "-" => {
+                let kind = match (operator, pointers) {
+                    (_, (Some(element), None)) => {
+                        self.scale("r1", &element);
+                        left_kind
+                    }
+                    ("+", (None, Some(element))) => {
+                        self.scale("r2", &element);
+                        right_kind
+                    }
+                    ("-", (Some(element), Some(_))) => {
+                        self.emit("sub r2, r1");
+                        self.emit("mov acc, r1");
+                        self.unscale(&element);
+                        return Ok(Type::I16);
+                    }
+                    (_, (None, None)) => kind,
+                    _ => {
+                        return Err(format!(
+                            "can't use {} on {} and {}",
+                            operator, left_kind, right_kind
+                        ))
+                    }
+                };
+                let mnemonic = if operator == "+" { "add" } else { "sub" };
+                self.emit(&format!("{} r2, r1", mnemonic));
+                self.emit("mov acc, r1");
+                return Ok(kind);
+            }
+
+            // Comparisons, signed ones compare with the sign bits flipped
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                if signed && pointers == (None, None) && !matches!(operator, "==" | "!=") {
+                    self.emit("xor r2, $8000");
+                    self.emit("mov acc, r2");
+                    self.emit("xor r1, $8000");
+                    self.emit("mov acc, r1");
+                }
+                let jump = match operator {
+                    "==" => "jeq",
+                    "!=" => "jne",
+                    "<" => "jlt",
+                    ">" => "jgt",
+                    "<=" => "jle",
+                    _ => "jge",
+                };
+                self.compare(jump);
+                return Ok(Type::U16);
+            }
+            _ => {}
+        }
+
+        // Integer operators
+        if pointers != (None, None) {
+            return Err(format!(
+                "can't use {} on {} and {}",
+                operator, left_kind, right_kind
+            ));
+        }
+        match operator {
+            "*" | "&" | "|" | "^" => {
+                let mnemonic = match operator {
+                    "*" => "mul",
+                    "&" => "and",
+                    "|" => "or",
+                    _ => "xor",
+                };
+                self.emit(&format!("{} r2, r1", mnemonic));
+                self.emit("mov acc, r1");
+            }
+            "/" | "%" => {
+                self.division = true;
+                self.emit(&format!("jsr !{}", if signed { "_divs" } else { "_divu" }));
+                self.emit(if operator == "/" {
+                    "mov r3, r1"
+                } else {
+                    "mov r4, r1"
+                });
+            }
+            "<<" => {
+                self.emit("lsh r2, r1");
+                self.emit("mov r2, r1");
+            }
+            _ if signed => {
+                // Shift the complement of negative numbers to shift in ones
+                let (positive, end) = (self.new_label(), self.new_label());
+                self.emit("tst r2, $000F");
+                self.emit("mov acc, r3");
+                self.emit("mov $0000, acc");
+                self.emit(&format!("jeq r3, &[!{}]", positive));
+                self.emit("not r2");
+                self.emit("mov acc, r2");
+                self.emit("rsh r2, r1");
+                self.emit("not r2");
+                self.emit("mov acc, r1");
+                self.jump(&end);
+                self.label(&positive);
+                self.emit("rsh r2, r1");
+                self.emit("mov r2, r1");
+                self.label(&end);
+            }
+            _ => {
+                self.emit("rsh r2, r1");
+                self.emit("mov r2, r1");
+            }
+        }
+
+        Ok(kind)
+    }
+
+    // Set r1 to 1 if the jump is taken for r2 and r1, else to 0
+    fn compare(&mut self, jump: &str) {
+        let label = self.new_label();
+        self.emit("mov r1, acc");
+        self.emit("mov $0001, r1");
+        self.emit(&format!("{} r2, &[!{}]", jump, label));
+        self.emit("mov $0000, r1");
+        self.label(&label);
+    }
+
+    // Multiply an index in a register by the size of an element
+    fn scale(&mut self, register: &str, element: &Type) {
+        match element.size() {
+            0 | 1 => {}
+            size if size.is_power_of_two() => {
+                self.emit(&format!("lsh {}, ${:04X}", register, size.trailing_zeros()))
+            }
+            size => {
+                self.emit(&format!("mul ${:04X}, {}", size, register));
+                self.emit(&format!("mov acc, {}", register));
+            }
+        }
+    }
+
+    // Divide a difference of addresses in r1 by the size of an element
+    fn unscale(&mut self, element: &Type) {
+        match element.size() {
+            0 | 1 => {}
+            size if size.is_power_of_two() => {
+                self.emit(&format!("rsh r1, ${:04X}", size.trailing_zeros()))
+            }
+            size => {
+                self.division = true;
+                self.emit("mov r1, r2");
+                self.emit(&format!("mov ${:04X}, r1", size));
+                self.emit("jsr !_divu");
+                self.emit("mov r3, r1");
+            }
+        }
+    }
+}
+
+// Division, r2 divided by r1 leaves the quotient in r3 and the remainder in r4
+const RUNTIME_DIVISION: &str = "\
+; Unsigned division, r2 / r1, shifting the dividend into the remainder
+_divu:
+    mov $0000, r3
+    mov $0000, r4
+    mov $0010, r5
+_divu_loop:
+    lsh r4, $0001
+    tst r2, $000F
+    or r4, acc
+    mov acc, r4
+    lsh r2, $0001
+    lsh r3, $0001
+    mov r1, acc
+    jlt r4, &[!_divu_next]
+    sub r4, r1
+    mov acc, r4
+    or r3, $0001
+    mov acc, r3
+_divu_next:
+    dec r5
+    mov $0000, acc
+    jne r5, &[!_divu_loop]
+    rts $0000
+
+; Signed division, the remainder takes the sign of the dividend
+_divs:
+    tst r2, $000F
+    mov acc, r7
+    tst r1, $000F
+    xor r7, acc
+    mov acc, r8
+    mov $0000, acc
+    jeq r7, &[!_divs_divisor]
+    not r2
+    inc acc
+    mov acc, r2
+_divs_divisor:
+    tst r1, $000F
+    mov acc, r6
+    mov $0000, acc
+    jeq r6, &[!_divs_divide]
+    not r1
+    inc acc
+    mov acc, r1
+_divs_divide:
+    jsr !_divu
+    mov $0000, acc
+    jeq r8, &[!_divs_remainder]
+    not r3
+    inc acc
+    mov acc, r3
+_divs_remainder:
+    mov $0000, acc
+    jeq r7, &[!_divs_end]
+    not r4
+    inc acc
+    mov acc, r4
+_divs_end:
+    rts $0000
+";
+
+// String as a .byte directive, followed by a 0
+fn string(text: &[u8]) -> String {
+    let mut bytes: Vec<String> = text.iter().map(|byte| format!("${:02X}", byte)).collect();
+    bytes.push(String::from("$00"));
+    format!(".byte {}", bytes.join(", "))
+}
+
+// Split source in tokens with their line numbers
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut index = 0;
+    let mut line = 1;
+    while index < chars.len() {
+        let char = chars[index];
+
+        // Whitespace and comments
+        if char == '\n' {
+            line += 1;
+            index += 1;
+            continue;
+        }
+        if char.is_whitespace() {
+            index += 1;
+            continue;
+        }
+        if chars[index..].starts_with(&['/', '/']) {
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+            continue;
+        }
+        if chars[index..].starts_with(&['/', '*']) {
+            let start = line;
+            index += 2;
+            while !chars[index..].starts_with(&['*', '/']) {
+                let next = *chars.get(index).ok_or((start, "missing */".to_string()))?;
+                if next == '\n' {
+                    line += 1;
+                }
+                index += 1;
+            }
+            index += 2;
+            continue;
+        }
+
+        // String or character
+        if char == '"' || char == '\'' {
+            let mut text = String::new();
+            index += 1;
+            loop {
+                let next = *chars
+                    .get(index)
+                    .filter(|next| **next != '\n')
+                    .ok_or((line, "missing closing quote".to_string()))?;
+                index += 1;
+                match next {
+                    _ if next == char => break,
+                    '\\' => {
+                        let escaped = *chars
+                            .get(index)
+                            .ok_or((line, "missing closing quote".to_string()))?;
+                        index += 1;
+                        text.push(match escaped {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            '0' => '\0',
+                            _ => escaped,
+                        });
+                    }
+                    _ => text.push(next),
+                }
+            }
+
+            // A character is its code
+            if char == '\'' {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(code), None) if (code as u32) < 0x10000 => {
+                        tokens.push((Token::Number(code as u16), line))
+                    }
+                    _ => return Err((line, format!("expected one character in '{}'", text))),
+                }
+            } else {
+                tokens.push((Token::String(text.into_bytes()), line));
+            }
+            continue;
+        }
+
+        // Number or name
+        let start = index;
+        while index < chars.len() && (chars[index].is_ascii_alphanumeric() || chars[index] == '_') {
+            index += 1;
+        }
+        let word: String = chars[start..index].iter().collect();
+        if word.starts_with(|char: char| char.is_ascii_digit()) {
+            let number = match word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+                Some(hex) => u16::from_str_radix(hex, 16),
+                None => word.parse(),
+            };
+            let number = number.map_err(|_| (line, format!("invalid number {}", word)))?;
+            tokens.push((Token::Number(number), line));
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push((Token::Identifier(word), line));
+            continue;
+        }
+
+        // Punctuation
+        let rest: String = chars[index..].iter().take(2).collect();
+        match PUNCTUATION
+            .iter()
+            .find(|punctuation| rest.starts_with(*punctuation))
+        {
+            Some(punctuation) => {
+                tokens.push((Token::Punctuation(punctuation), line));
+                index += punctuation.len();
+            }
+            None => return Err((line, format!("unexpected character '{}'", char))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::cpu::StopReason;
+    use crate::linker::{link, MemoryMap};
+    use crate::machine::{MachineBuilder, MachineConfig};
+
+    #[test]
+    fn arrays_must_fit_in_memory() {
+        let source = "u16 a[40000];\nu16 main() { return 0; }\n";
+        assert_eq!(
+            compile(source, "t.c").unwrap_err(),
+            "t.c:1: array is too large"
+        );
+
+        let source = "u16 main() {\n    u8 b[2];\n    u16 c[40000];\n    return 0;\n}\n";
+        assert_eq!(
+            compile(source, "t.c").unwrap_err(),
+            "t.c:3: array is too large"
+        );
+
+        let source = "u16 a[30000];\nu16 main() { return 0; }\n";
+        assert!(compile(source, "t.c").unwrap().contains("a: .fill $EA60"));
+    }
+
+    // Compile, assemble and link a program, run it and return acc
+    fn run(source: &str) -> u16 {
+        let assembly = compile(source, "t.c").unwrap();
+        let (object, _) = Assembler::new()
+            .assemble_source(&assembly, "t.asm")
+            .unwrap();
+        let image = link(&[object], &MemoryMap::default_machine()).unwrap();
+        let mut cpu = MachineBuilder::new(MachineConfig::default_machine())
+            .with_program(image.bytes)
+            .build()
+            .unwrap();
+        assert_eq!(cpu.run(), StopReason::Halted);
+        cpu.get_register("acc")
+    }
+
+    #[test]
+    fn compiled_programs_return_from_main_in_acc() {
+        let source = "
+            i16 values[4] = {3, -7, 12, -1};
+
+            u16 fib(u16 n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            i16 min(i16 *list, u16 count) {
+                i16 best = list[0];
+                u16 i = 1;
+                while (i < count) {
+                    if (list[i] < best) best = list[i];
+                    i = i + 1;
+                }
+                return best;
+            }
+
+            u16 main() {
+                return fib(10) * 100 + min(values, 4) + 7;
+            }
+        ";
+        assert_eq!(run(source), 5500);
+    }
+
+    #[test]
+    fn negative_numbers_are_signed() {
+        let source = "
+            u16 main() {
+                i16 s = -100 / 7;
+                u16 u = 100;
+                return (s == -14) + (-1 < 0) * 2 + (u / -7 == -14) * 4;
+            }
+        ";
+        assert_eq!(run(source), 7);
+    }
+}
@@ -1,73 +1,169 @@
 // Imports
-use crate::device::{Device, DeviceType};
+use crate::device::{Device, DeviceType, Endian};
 use crate::device_mapper::DeviceMapper;
-use std::collections::HashMap;
-
-// Instructions for the CPU
-
-// Move instructions
-pub const MOV_LIT_REG: u8 = 0x10;
-pub const MOV_REG_REG: u8 = 0x11;
-pub const MOV_REG_MEM: u8 = 0x12;
-pub const MOV_MEM_REG: u8 = 0x13;
-pub const MOV_LIT_MEM: u8 = 0x14;
-pub const MOV_REG_PTR_REG: u8 = 0x15;
-pub const MOV_LIT_OFF_REG: u8 = 0x16;
-
-// Arithmetic instructions
-pub const ADD_REG_REG: u8 = 0x20;
-pub const ADD_LIT_REG: u8 = 0x21;
-pub const SUB_LIT_REG: u8 = 0x22;
-pub const SUB_REG_LIT: u8 = 0x23;
-pub const SUB_REG_REG: u8 = 0x24;
-pub const INC_REG: u8 = 0x25;
-pub const DEC_REG: u8 = 0x26;
-pub const MUL_LIT_REG: u8 = 0x27;
-pub const MUL_REG_REG: u8 = 0x28;
-
-// Binary manipulation instructions
-pub const LSH_REG_LIT: u8 = 0x30;
-pub const LSH_REG_REG: u8 = 0x31;
-pub const RSH_REG_LIT: u8 = 0x32;
-pub const RSH_REG_REG: u8 = 0x33;
-pub const AND_REG_LIT: u8 = 0x34;
-pub const AND_REG_REG: u8 = 0x35;
-pub const OR_REG_LIT: u8 = 0x36;
-pub const OR_REG_REG: u8 = 0x37;
-pub const XOR_REG_LIT: u8 = 0x38;
-pub const XOR_REG_REG: u8 = 0x39;
-pub const NOT: u8 = 0x3A;
-
-// Branching instructions
-pub const JNE_REG: u8 = 0x40;
-pub const JNE_LIT: u8 = 0x41;
-pub const JEQ_REG: u8 = 0x42;
-pub const JEQ_LIT: u8 = 0x43;
-pub const JLT_REG: u8 = 0x44;
-pub const JLT_LIT: u8 = 0x45;
-pub const JGT_REG: u8 = 0x46;
-pub const JGT_LIT: u8 = 0x47;
-pub const JLE_REG: u8 = 0x48;
-pub const JLE_LIT: u8 = 0x49;
-pub const JGE_REG: u8 = 0x4A;
-pub const JGE_LIT: u8 = 0x4B;
-
-// Miscellaneous instructions
-pub const PSH_LIT: u8 = 0x50;
-pub const PSH_REG: u8 = 0x51;
-pub const POP: u8 = 0x52;
-pub const CAL_LIT: u8 = 0x53;
-pub const CAL_REG: u8 = 0x54;
-pub const RET: u8 = 0x55;
-pub const HLT: u8 = 0x56;
+use crate::fault::{Access, Fault};
+use crate::mmu::MMU;
+use crate::opcodes::*;
+use crate::profiler::Profiler;
+use crate::semihost::*;
+use crate::symbols::Symbols;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Exceptions
+
+// Address of the exception vector table, one handler address per exception
+// number, see Fault::code
+pub const EXCEPTION_VECTOR_TABLE: u16 = 0x1000;
+
+// Exception number of SYSCALL, faults use the numbers from Fault::code
+pub const EXCEPTION_SYSCALL: u16 = 0x04;
+
+// Initial stack pointer when no stack is set
+pub const DEFAULT_STACK_BASE: u16 = 0xFFFF - 2;
+
+// Instructions only allowed in supervisor mode
+const SUPERVISOR_INSTRUCTIONS: [u8; 5] = [HLT, LPT_LIT, LPT_REG, UPT, SYSRET];
+
+// Registers only writable by instructions in supervisor mode
+const SUPERVISOR_REGISTERS: [&str; 4] = ["sp", "fp", "sb", "sl"];
+
+// Registers put back when an instruction faults, acc to fp
+const RESTORED_REGISTERS: usize = 11;
+
+// Cycles an instruction takes, without the wait states of its memory accesses
+pub fn instruction_cycles(instruction: u8) -> u64 {
+    match instruction {
+        // Register moves
+        MOV_LIT_REG | MOV_REG_REG => 2,
+
+        // Memory moves
+        MOV_REG_MEM | MOV_MEM_REG | MOV_LIT_MEM | MOV_REG_PTR_REG => 4,
+        MOV_LIT_OFF_REG | MOV_ADR_REG | MOV_REG_ADR => 5,
+        MOV8_REG_MEM | MOV8_MEM_REG | MOV8S_MEM_REG | MOV8_LIT_MEM => 3,
+        MOV8_REG_PTR_REG | MOV8S_REG_PTR_REG | MOV8_REG_REG_PTR => 3,
+
+        // Multiplication
+        MUL_LIT_REG | MUL_REG_REG => 8,
+
+        // Branching
+        JNE_REG | JNE_LIT | JEQ_REG | JEQ_LIT | JLT_REG | JLT_LIT => 3,
+        JGT_REG | JGT_LIT | JLE_REG | JLE_LIT | JGE_REG | JGE_LIT => 3,
+
+        // Stack and calls
+        PSH_LIT | PSH_REG | POP => 4,
+        CAL_LIT | CAL_REG | RET => 20,
+        JSR_LIT | JSR_REG | RTS_LIT => 6,
+        HLT => 1,
+
+        // System
+        LPT_LIT | LPT_REG | UPT => 4,
+        SYSCALL | SYSRET => 24,
+
+        // Arithmetic, binary and bit instructions
+        _ => 2,
+    }
+}
+
+// Memory operand decoded from an addressing mode
+struct Operand {
+    address: u16,
+    byte: bool,
+    signed: bool,
+    register: usize,
+    update: Option<u16>,
+}
+
+// Memory access of the CPU, as given to the on_memory_access hook
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    // Address before translation by the MMU
+    pub address: u16,
+    pub access: Access,
+    pub value: u16,
+    // Word access, else a byte access
+    pub word: bool,
+}
+
+// Why a run of the CPU stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    // HLT ran
+    Halted,
+    // Next instruction is at a breakpoint
+    Breakpoint(u16),
+    // Fault without an exception handler, or a fault entering one, at the
+    // instruction at ip
+    Fault { fault: Fault, ip: u16 },
+    // Instruction or cycle budget ran out
+    Budget,
+    // Stopped through a StopHandle
+    Stopped,
+    // Guest exited with a status through semihosting
+    Exit(u16),
+}
+
+// Print stop reasons
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Halted => write!(f, "halted"),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at 0x{:04X}", address),
+            StopReason::Fault { fault, ip } => write!(f, "{} at ip 0x{:04X}", fault, ip),
+            StopReason::Budget => write!(f, "budget ran out"),
+            StopReason::Stopped => write!(f, "stopped"),
+            StopReason::Exit(status) => write!(f, "exited with status {}", status),
+        }
+    }
+}
+
+// Stops a running CPU from another thread
+#[derive(Clone)]
+pub struct StopHandle {
+    stop: Arc<AtomicBool>,
+}
+
+// StopHandle implementation
+impl StopHandle {
+    // Stop the run before the next instruction
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// Host hook called with the CPU
+type Hook = Box<dyn FnMut(&CPU) + Send>;
+
+// Host hook called with a memory access
+type MemoryHook = Box<dyn FnMut(&MemoryAccess) + Send>;
 
 // CPU class
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     device_mapper: DeviceMapper,
     registers_names: Vec<String>,
     registers: Device,
     registers_map: HashMap<String, usize>,
-    stack_frame_size: u16,
+    mmu: MMU,
+    supervisor: bool,
+    cycles: u64,
+    clock_speed: Option<u64>,
+    clock_start: Option<(Instant, u64)>,
+    profiler: Option<Profiler>,
+    symbols: Option<Symbols>,
+    on_step: Option<Hook>,
+    on_memory_access: RefCell<Option<MemoryHook>>,
+    on_halt: Option<Hook>,
+    breakpoints: HashSet<u16>,
+    stop: Arc<AtomicBool>,
+    semihosting: bool,
+    host_call: bool,
+    exit_status: Option<u16>,
+    saved_registers: [u16; RESTORED_REGISTERS],
 }
 
 // CPU implementation
@@ -87,8 +183,10 @@ impl CPU {
             String::from("r8"),  // General purpose register
             String::from("sp"),  // Stack pointer
             String::from("fp"),  // Frame pointer
+            String::from("sb"),  // Stack base, highest stack pointer
+            String::from("sl"),  // Stack limit, lowest address a push may write
         ];
-        let mut registers = Device::new(registers_names.len() * 2, DeviceType::Memory);
+        let registers = Device::new(registers_names.len() * 2, DeviceType::Memory);
 
         // Map the registers names
         let mut registers_map = HashMap::new();
@@ -96,27 +194,51 @@ impl CPU {
             registers_map.insert(name.clone(), index * 2);
         }
 
-        // Set stack pointer and frame pointer to the right address
-        let sp_offset = registers_map.get("sp").unwrap();
-        let fp_offset = registers_map.get("fp").unwrap();
-        let memory_position = ((0xFFFF - 2) as u16).to_be_bytes(); // TODO try to make this dynamic in some way
-        let stack_frame_size = 0;
-        registers.set_byte(memory_position[0], *sp_offset);
-        registers.set_byte(memory_position[0], *fp_offset);
-        registers.set_byte(memory_position[1], *sp_offset + 1);
-        registers.set_byte(memory_position[1], *fp_offset + 1);
-
-        Self {
+        let mut cpu = Self {
             device_mapper,
             registers_names,
             registers,
             registers_map,
-            stack_frame_size,
-        }
+            mmu: MMU::new(),
+            supervisor: true,
+            cycles: 0,
+            clock_speed: None,
+            clock_start: None,
+            profiler: None,
+            symbols: None,
+            on_step: None,
+            on_memory_access: RefCell::new(None),
+            on_halt: None,
+            breakpoints: HashSet::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            semihosting: false,
+            host_call: false,
+            exit_status: None,
+            saved_registers: [0; RESTORED_REGISTERS],
+        };
+
+        // Default stack at the top of memory without a limit, see set_stack
+        cpu.set_stack(DEFAULT_STACK_BASE, 0x0000);
+        cpu
+    }
+
+    // Set the byte order of all memory accesses, regions can override it
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.device_mapper.set_endian(endian);
     }
 
-    // Read a register
-    fn get_register(&self, name: &str) -> u16 {
+    // Memory of the machine, for host accesses
+    pub fn device_mapper(&self) -> &DeviceMapper {
+        &self.device_mapper
+    }
+
+    // Memory of the machine, for host accesses and mapping devices
+    pub fn device_mapper_mut(&mut self) -> &mut DeviceMapper {
+        &mut self.device_mapper
+    }
+
+    // Read a register by name, panics on an unknown name
+    pub fn get_register(&self, name: &str) -> u16 {
         // Check if register exists
         if !self.registers_map.contains_key(name) {
             panic!("Register {} not found", name);
@@ -124,120 +246,257 @@ impl CPU {
 
         // Read register
         let offset = self.registers_map.get(name).unwrap();
-        let memory = [
-            self.registers.get_byte(*offset),
-            self.registers.get_byte(*offset + 1),
-        ];
-        u16::from_be_bytes(memory)
+        self.read_register(*offset)
     }
 
-    // Write to a register
-    fn set_register(&mut self, name: &str, value: u16) {
+    // Write to a register by name, panics on an unknown name
+    pub fn set_register(&mut self, name: &str, value: u16) {
         // Check if register exists
         if !self.registers_map.contains_key(name) {
             panic!("Register {} not found", name);
         }
 
         // Write to register
-        let offset = self.registers_map.get(name).unwrap();
+        let offset = *self.registers_map.get(name).unwrap();
+        self.store_register(offset, value);
+    }
+
+    // Read a register by offset
+    fn read_register(&self, offset: usize) -> u16 {
+        let memory = [
+            self.registers.get_byte(offset),
+            self.registers.get_byte(offset + 1),
+        ];
+        u16::from_be_bytes(memory)
+    }
+
+    // Write to a register named by an instruction operand
+    fn write_register(&mut self, offset: usize, value: u16) -> Result<(), Fault> {
+        // Check if the register may be written in the current mode
+        let protected = SUPERVISOR_REGISTERS
+            .iter()
+            .any(|name| self.registers_map[*name] == offset);
+        if protected && !self.supervisor {
+            return Err(Fault::Privilege {
+                register: Some(offset as u8 / 2),
+            });
+        }
+
+        self.store_register(offset, value);
+        Ok(())
+    }
+
+    // Write to a register by offset
+    fn store_register(&mut self, offset: usize, value: u16) {
         let bytes = value.to_be_bytes();
-        self.registers.set_byte(bytes[0], *offset);
-        self.registers.set_byte(bytes[1], *offset + 1);
+        self.registers.set_byte(bytes[0], offset);
+        self.registers.set_byte(bytes[1], offset + 1);
+    }
+
+    // Memory accesses, translated by the MMU
+
+    // Tell the on_memory_access hook about an access
+    fn report_access(&self, address: u16, access: Access, value: u16, word: bool) {
+        if let Some(hook) = self.on_memory_access.borrow_mut().as_mut() {
+            hook(&MemoryAccess {
+                address,
+                access,
+                value,
+                word,
+            });
+        }
+    }
+
+    // Read a byte
+    fn read_uint_8(&self, address: u16, access: Access) -> Result<u8, Fault> {
+        let physical = self.mmu.translate(&self.device_mapper, address, access)?;
+        let value = self.device_mapper.read_uint_8(physical, access)?;
+        self.report_access(address, access, value as u16, false);
+        Ok(value)
+    }
+
+    // Read bytes
+    fn read_uint_16(&self, address: u16, access: Access) -> Result<u16, Fault> {
+        let next = address.wrapping_add(1);
+        let physical = [
+            self.mmu.translate(&self.device_mapper, address, access)?,
+            self.mmu.translate(&self.device_mapper, next, access)?,
+        ];
+
+        // Bytes on adjacent physical addresses are one access, else they are
+        // on different pages
+        let value = if physical[1] == physical[0].wrapping_add(1) {
+            self.device_mapper.read_uint_16(physical[0], access)?
+        } else {
+            let bytes = [
+                self.device_mapper.read_uint_8(physical[0], access)?,
+                self.device_mapper.read_uint_8(physical[1], access)?,
+            ];
+            self.device_mapper.endian(physical[0]).join(bytes)
+        };
+        self.report_access(address, access, value, true);
+        Ok(value)
+    }
+
+    // Write a byte
+    fn write_uint_8(&mut self, address: u16, value: u8) -> Result<(), Fault> {
+        let physical = self
+            .mmu
+            .translate(&self.device_mapper, address, Access::Write)?;
+        self.device_mapper.set_uint_8(physical, value)?;
+        self.report_access(address, Access::Write, value as u16, false);
+        Ok(())
+    }
+
+    // Write bytes
+    fn write_uint_16(&mut self, address: u16, value: u16) -> Result<(), Fault> {
+        let next = address.wrapping_add(1);
+        let physical = [
+            self.mmu
+                .translate(&self.device_mapper, address, Access::Write)?,
+            self.mmu
+                .translate(&self.device_mapper, next, Access::Write)?,
+        ];
+
+        // Bytes on adjacent physical addresses are one access, else they are
+        // on different pages
+        if physical[1] == physical[0].wrapping_add(1) {
+            self.device_mapper.set_uint_16(physical[0], value)?;
+        } else {
+            let bytes = self.device_mapper.endian(physical[0]).split(value);
+            self.device_mapper.set_uint_8(physical[0], bytes[0])?;
+            self.device_mapper.set_uint_8(physical[1], bytes[1])?;
+        }
+        self.report_access(address, Access::Write, value, true);
+        Ok(())
     }
 
     // Read byte from memory
-    fn fetch8(&mut self) -> u8 {
+    fn fetch8(&mut self) -> Result<u8, Fault> {
         let ip = self.get_register("ip");
-        let byte = self.device_mapper.get_byte(ip);
-        self.set_register("ip", ip + 1);
-        byte
+        let byte = self.read_uint_8(ip, Access::Execute)?;
+        self.set_register("ip", ip.wrapping_add(1));
+        Ok(byte)
     }
 
     // Read bytes from memory
-    fn fetch16(&mut self) -> u16 {
+    fn fetch16(&mut self) -> Result<u16, Fault> {
         let ip = self.get_register("ip");
-        let bytes = [
-            self.device_mapper.get_byte(ip),
-            self.device_mapper.get_byte(ip + 1),
-        ];
-        self.set_register("ip", ip + 2);
-        u16::from_be_bytes(bytes)
+        let value = self.read_uint_16(ip, Access::Execute)?;
+        self.set_register("ip", ip.wrapping_add(2));
+        Ok(value)
+    }
+
+    // Set the stack to grow down from base, without writing below limit
+    pub fn set_stack(&mut self, base: u16, limit: u16) {
+        self.set_register("sp", base);
+        self.set_register("fp", base);
+        self.set_register("sb", base);
+        self.set_register("sl", limit);
+    }
+
+    // Start executing at an address
+    pub fn set_ip(&mut self, address: u16) {
+        self.set_register("ip", address);
     }
 
-    // Push bytes on the stack
-    fn push(&mut self, bytes: [u8; 2]) {
+    // Push a value on the stack
+    fn push(&mut self, value: u16) -> Result<(), Fault> {
         // Read stack pointer
         let sp_address = self.get_register("sp");
 
+        // Check for stack overflow
+        if sp_address < self.get_register("sl") || sp_address > self.get_register("sb") {
+            return Err(Fault::Stack {
+                address: sp_address,
+                access: Access::Write,
+            });
+        }
+
         // Write stack
-        self.device_mapper.set_byte(bytes[0], sp_address);
-        self.device_mapper.set_byte(bytes[1], sp_address + 1);
+        self.write_uint_16(sp_address, value)?;
 
         // Move stack pointer
-        self.set_register("sp", sp_address - 2);
-        self.stack_frame_size += 2;
+        self.set_register("sp", sp_address.wrapping_sub(2));
+        Ok(())
     }
 
-    // Pop bytes from the stack
-    fn pop(&mut self) -> u16 {
+    // Pop a value from the stack
+    fn pop(&mut self) -> Result<u16, Fault> {
+        // Check for stack underflow
+        let next_sp_address = self.get_register("sp").wrapping_add(2);
+        if next_sp_address > self.get_register("sb") || next_sp_address < self.get_register("sl") {
+            return Err(Fault::Stack {
+                address: next_sp_address,
+                access: Access::Read,
+            });
+        }
+
+        // Read stack, before moving the stack pointer so a fault leaves it
+        let value = self.read_uint_16(next_sp_address, Access::Read)?;
+
         // Move stack pointer
-        let next_sp_address = self.get_register("sp") + 2;
         self.set_register("sp", next_sp_address);
-        self.stack_frame_size -= 2;
-
-        // Read stack
-        u16::from_be_bytes([
-            self.device_mapper.get_byte(next_sp_address),
-            self.device_mapper.get_byte(next_sp_address + 1),
-        ])
+        Ok(value)
     }
 
+    // Calling convention
+    //
+    // CAL/RET save the whole CPU state. The caller pushes the arguments,
+    // then the number of arguments, then calls. CAL pushes r1 to r8, the
+    // return address and the caller's frame pointer, and points fp at the
+    // new frame:
+    //
+    //   [fp + 22 + 2 * n]   first argument
+    //   [fp + 24]           last argument
+    //   [fp + 22]           number of arguments n
+    //   [fp + 20]..[fp + 6] r1..r8
+    //   [fp + 4]            return address
+    //   [fp + 2]            caller's frame pointer
+    //   [fp - 2 * k]        local k, pushed by the callee (first local at [fp])
+    //
+    // RET drops the callee's locals, restores r1 to r8, ip and fp and pops
+    // the argument count and the arguments. r1 to r8 are callee saved, acc is
+    // not saved and holds the return value.
+    //
+    // JSR/RTS are the lightweight pair. JSR pushes only the return address
+    // and the caller's frame pointer, so the first argument is at
+    // [fp + 4 + 2 * n] and the last at [fp + 6]. RTS takes the number of
+    // argument words to pop. All registers except ip, sp and fp are caller
+    // saved, acc holds the return value.
+
     // Push CPU state
-    fn push_state(&mut self) {
+    fn push_state(&mut self) -> Result<(), Fault> {
         // Push registers
-        self.push(self.get_register("r1").to_be_bytes());
-        self.push(self.get_register("r2").to_be_bytes());
-        self.push(self.get_register("r3").to_be_bytes());
-        self.push(self.get_register("r4").to_be_bytes());
-        self.push(self.get_register("r5").to_be_bytes());
-        self.push(self.get_register("r6").to_be_bytes());
-        self.push(self.get_register("r7").to_be_bytes());
-        self.push(self.get_register("r8").to_be_bytes());
-        self.push(self.get_register("ip").to_be_bytes());
-
-        // Push frame size
-        self.push((self.stack_frame_size + 2).to_be_bytes());
-        self.stack_frame_size = 0;
-
-        // Write new frame pointer
-        self.set_register("fp", self.get_register("sp"));
+        self.push(self.get_register("r1"))?;
+        self.push(self.get_register("r2"))?;
+        self.push(self.get_register("r3"))?;
+        self.push(self.get_register("r4"))?;
+        self.push(self.get_register("r5"))?;
+        self.push(self.get_register("r6"))?;
+        self.push(self.get_register("r7"))?;
+        self.push(self.get_register("r8"))?;
+
+        // Push return address and frame
+        self.push_frame()
     }
 
     // Pop CPU state
-    fn pop_state(&mut self) {
-        // Read frame pointer
-        let frame_pointer_address = self.get_register("fp");
-
-        // Write new stack pointer
-        self.set_register("sp", frame_pointer_address);
-
-        // Pop stack frame size
-        self.stack_frame_size = self.pop();
-        let stack_frame_size = self.stack_frame_size;
+    fn pop_state(&mut self) -> Result<(), Fault> {
+        // Pop return address and frame
+        self.pop_frame()?;
 
         // Pop registers
-        let ip = self.pop();
-        let r8 = self.pop();
-        let r7 = self.pop();
-        let r6 = self.pop();
-        let r5 = self.pop();
-        let r4 = self.pop();
-        let r3 = self.pop();
-        let r2 = self.pop();
-        let r1 = self.pop();
+        let r8 = self.pop()?;
+        let r7 = self.pop()?;
+        let r6 = self.pop()?;
+        let r5 = self.pop()?;
+        let r4 = self.pop()?;
+        let r3 = self.pop()?;
+        let r2 = self.pop()?;
+        let r1 = self.pop()?;
 
         // Write registers
-        self.set_register("ip", ip);
         self.set_register("r8", r8);
         self.set_register("r7", r7);
         self.set_register("r6", r6);
@@ -247,148 +506,305 @@ impl CPU {
         self.set_register("r2", r2);
         self.set_register("r1", r1);
 
-        // Remove arguments frm CAL
-        let cal_args = self.pop();
-        for _ in 0..cal_args {
-            self.pop();
-        }
+        // Remove arguments from CAL
+        let cal_args = self.pop()?;
+        self.drop_words(cal_args)
+    }
+
+    // Push return address and frame pointer and start a new frame
+    fn push_frame(&mut self) -> Result<(), Fault> {
+        self.push(self.get_register("ip"))?;
+        self.push(self.get_register("fp"))?;
+
+        // Write new frame pointer
+        self.set_register("fp", self.get_register("sp"));
+        Ok(())
+    }
+
+    // Drop the current frame and pop frame pointer and return address
+    fn pop_frame(&mut self) -> Result<(), Fault> {
+        // Drop locals
+        self.set_register("sp", self.get_register("fp"));
 
-        // Reset frame pointer
-        self.set_register("fp", frame_pointer_address + stack_frame_size);
+        // Restore frame pointer and instruction pointer
+        let fp = self.pop()?;
+        let ip = self.pop()?;
+        self.set_register("fp", fp);
+        self.set_register("ip", ip);
+        Ok(())
+    }
+
+    // Pop words from the stack without reading them
+    fn drop_words(&mut self, count: u16) -> Result<(), Fault> {
+        for _ in 0..count {
+            self.pop()?;
+        }
+        Ok(())
     }
 
     // Get register offset
-    fn fetch_register_index(&mut self) -> usize {
-        (self.fetch8() as usize % self.registers_names.len()) * 2
+    fn fetch_register_index(&mut self) -> Result<usize, Fault> {
+        Ok((self.fetch8()? as usize % self.registers_names.len()) * 2)
+    }
+
+    // Read an addressing mode and compute the address of the memory operand
+    fn fetch_operand(&mut self) -> Result<Operand, Fault> {
+        // Read mode
+        let mode = self.fetch8()?;
+        let register = (mode as usize & 0x0F) % self.registers_names.len() * 2;
+        let byte = mode & ADR_BYTE != 0;
+        let size = if byte { 1 } else { 2 };
+        let base = self.read_register(register);
+
+        // Compute address and base register update
+        let (address, update) = match mode & 0xC0 {
+            ADR_INDIRECT => (base, None),
+            ADR_OFFSET => (base.wrapping_add(self.fetch16()?), None),
+            ADR_POST_INC => (base, Some(base.wrapping_add(size))),
+            _ => (base.wrapping_sub(size), Some(base.wrapping_sub(size))),
+        };
+
+        Ok(Operand {
+            address,
+            byte,
+            signed: mode & ADR_SIGNED != 0,
+            register,
+            update,
+        })
+    }
+
+    // Update the base register of a memory operand after the access succeeded
+    fn update_operand(&mut self, operand: &Operand) -> Result<(), Fault> {
+        if let Some(value) = operand.update {
+            self.write_register(operand.register, value)?;
+        }
+        Ok(())
     }
 
     // Execute an instruction
-    fn execute(&mut self, instruction: u8) {
+    fn execute(&mut self, instruction: u8) -> Result<(), Fault> {
         match instruction {
             // Move instructions
 
             // Move literal to register
             MOV_LIT_REG => {
                 // Read instruction
-                let literal = self.fetch16();
-                let register = self.fetch_register_index();
-                let value: [u8; 2] = literal.to_be_bytes();
+                let literal = self.fetch16()?;
+                let register = self.fetch_register_index()?;
 
                 // Write to register
-                self.registers.set_byte(value[0], register);
-                self.registers.set_byte(value[1], register + 1);
+                self.write_register(register, literal)?;
             }
 
             // Move literal to memory
             MOV_LIT_MEM => {
                 // Read instruction
-                let literal = self.fetch16();
-                let address = self.fetch16();
-                let value: [u8; 2] = literal.to_be_bytes();
+                let literal = self.fetch16()?;
+                let address = self.fetch16()?;
 
-                // Write to register
-                self.device_mapper.set_byte(value[0], address);
-                self.device_mapper.set_byte(value[1], address + 1);
+                // Write to memory
+                self.write_uint_16(address, literal)?;
             }
 
             // Move register to register with offset
             MOV_LIT_OFF_REG => {
                 // Read instruction
-                let base_address = self.fetch16();
-                let register_from = self.fetch_register_index();
-                let register_to = self.fetch_register_index();
+                let base_address = self.fetch16()?;
+                let register_from = self.fetch_register_index()?;
+                let register_to = self.fetch_register_index()?;
 
                 // Read offset
-                let register_memory = [
-                    self.registers.get_byte(register_from),
-                    self.registers.get_byte(register_from + 1),
-                ];
-                let offset = u16::from_be_bytes(register_memory);
+                let offset = self.read_register(register_from);
 
                 // Read value
-                let value = [
-                    self.device_mapper.get_byte(base_address + offset),
-                    self.device_mapper.get_byte(base_address + offset),
-                ];
+                let value = self.read_uint_16(base_address.wrapping_add(offset), Access::Read)?;
 
                 // Write to register
-                self.registers.set_byte(value[0], register_to);
-                self.registers.set_byte(value[1], register_to + 1);
+                self.write_register(register_to, value)?;
             }
 
             // Move register to register
             MOV_REG_REG => {
                 // Read instruction
-                let register_from = self.fetch_register_index();
-                let register_to = self.fetch_register_index();
+                let register_from = self.fetch_register_index()?;
+                let register_to = self.fetch_register_index()?;
 
                 // Read from_register
-                let value = [
-                    self.registers.get_byte(register_from),
-                    self.registers.get_byte(register_from + 1),
-                ];
+                let value = self.read_register(register_from);
 
                 // Write to_register
-                self.registers.set_byte(value[0], register_to);
-                self.registers.set_byte(value[1], register_to + 1);
+                self.write_register(register_to, value)?;
             }
 
             // Move register to memory
             MOV_REG_MEM => {
                 // Read instruction
-                let register_from = self.fetch_register_index();
-                let address = self.fetch16();
+                let register_from = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read from_register
-                let value = [
-                    self.registers.get_byte(register_from),
-                    self.registers.get_byte(register_from + 1),
-                ];
+                let value = self.read_register(register_from);
 
                 // Write memory
-                self.device_mapper.set_byte(value[0], address);
-                self.device_mapper.set_byte(value[1], address + 1);
+                self.write_uint_16(address, value)?;
             }
 
             // Move register pointer to register
             MOV_REG_PTR_REG => {
                 // Read instruction
-                let register_from = self.fetch_register_index();
-                let register_to = self.fetch_register_index();
+                let register_from = self.fetch_register_index()?;
+                let register_to = self.fetch_register_index()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register_from),
-                    self.registers.get_byte(register_from + 1),
-                ];
-                let pointer = u16::from_be_bytes(register_memory);
+                let pointer = self.read_register(register_from);
 
                 // Read pointer value
-                let value = [
-                    self.device_mapper.get_byte(pointer),
-                    self.device_mapper.get_byte(pointer + 1),
-                ];
+                let value = self.read_uint_16(pointer, Access::Read)?;
 
                 // Write to_register
-                self.registers.set_byte(value[0], register_to);
-                self.registers.set_byte(value[1], register_to + 1);
+                self.write_register(register_to, value)?;
             }
 
             // Move memory to register
             MOV_MEM_REG => {
                 // Read instruction
-                let address = self.fetch16();
-                let register_to = self.fetch_register_index();
+                let address = self.fetch16()?;
+                let register_to = self.fetch_register_index()?;
+
+                // Read from memory
+                let value = self.read_uint_16(address, Access::Read)?;
+
+                // Write register
+                self.write_register(register_to, value)?;
+            }
+
+            // Move addressed memory to register
+            MOV_ADR_REG => {
+                // Read instruction
+                let operand = self.fetch_operand()?;
+                let register_to = self.fetch_register_index()?;
+
+                // Read from memory
+                let value = match (operand.byte, operand.signed) {
+                    (false, _) => self.read_uint_16(operand.address, Access::Read)?,
+                    (true, false) => self.read_uint_8(operand.address, Access::Read)? as u16,
+                    (true, true) => self.read_uint_8(operand.address, Access::Read)? as i8 as u16,
+                };
+
+                // Write register, the loaded value wins over the base register update
+                self.update_operand(&operand)?;
+                self.write_register(register_to, value)?;
+            }
+
+            // Move register to addressed memory
+            MOV_REG_ADR => {
+                // Read instruction
+                let register_from = self.fetch_register_index()?;
+                let operand = self.fetch_operand()?;
+
+                // Read from_register
+                let value = self.read_register(register_from);
+
+                // Write memory
+                if operand.byte {
+                    self.write_uint_8(operand.address, value as u8)?;
+                } else {
+                    self.write_uint_16(operand.address, value)?;
+                }
+                self.update_operand(&operand)?;
+            }
+
+            // Byte move instructions
+
+            // Move low byte of register to memory
+            MOV8_REG_MEM => {
+                // Read instruction
+                let register_from = self.fetch_register_index()?;
+                let address = self.fetch16()?;
+
+                // Read from_register
+                let value = self.read_register(register_from);
+
+                // Write memory
+                self.write_uint_8(address, value as u8)?;
+            }
+
+            // Move memory byte to register
+            MOV8_MEM_REG => {
+                // Read instruction
+                let address = self.fetch16()?;
+                let register_to = self.fetch_register_index()?;
 
                 // Read from memory
-                let value = [
-                    self.device_mapper.get_byte(address),
-                    self.device_mapper.get_byte(address + 1),
-                ];
+                let value = self.read_uint_8(address, Access::Read)?;
 
                 // Write register
-                self.registers.set_byte(value[0], register_to);
-                self.registers.set_byte(value[1], register_to + 1);
+                self.write_register(register_to, value as u16)?;
+            }
+
+            // Move memory byte to register with sign extension
+            MOV8S_MEM_REG => {
+                // Read instruction
+                let address = self.fetch16()?;
+                let register_to = self.fetch_register_index()?;
+
+                // Read from memory
+                let value = self.read_uint_8(address, Access::Read)?;
+
+                // Write register
+                self.write_register(register_to, value as i8 as u16)?;
+            }
+
+            // Move byte literal to memory
+            MOV8_LIT_MEM => {
+                // Read instruction
+                let literal = self.fetch8()?;
+                let address = self.fetch16()?;
+
+                // Write to memory
+                self.write_uint_8(address, literal)?;
+            }
+
+            // Move byte at register pointer to register
+            MOV8_REG_PTR_REG => {
+                // Read instruction
+                let register_from = self.fetch_register_index()?;
+                let register_to = self.fetch_register_index()?;
+
+                // Read pointer value
+                let pointer = self.read_register(register_from);
+                let value = self.read_uint_8(pointer, Access::Read)?;
+
+                // Write to_register
+                self.write_register(register_to, value as u16)?;
+            }
+
+            // Move byte at register pointer to register with sign extension
+            MOV8S_REG_PTR_REG => {
+                // Read instruction
+                let register_from = self.fetch_register_index()?;
+                let register_to = self.fetch_register_index()?;
+
+                // Read pointer value
+                let pointer = self.read_register(register_from);
+                let value = self.read_uint_8(pointer, Access::Read)?;
+
+                // Write to_register
+                self.write_register(register_to, value as i8 as u16)?;
+            }
+
+            // Move low byte of register to register pointer
+            MOV8_REG_REG_PTR => {
+                // Read instruction
+                let register_from = self.fetch_register_index()?;
+                let register_pointer = self.fetch_register_index()?;
+
+                // Read registers
+                let value = self.read_register(register_from);
+                let pointer = self.read_register(register_pointer);
+
+                // Write memory
+                self.write_uint_8(pointer, value as u8)?;
             }
 
             // Algorithmic instructions
@@ -396,179 +812,119 @@ impl CPU {
             // Add register to register
             ADD_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
-
-                // Read register 2
-                let register2_value = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let value_register2 = u16::from_be_bytes(register2_value);
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let value_register2 = self.read_register(register2);
 
                 // Add values
-                self.set_register("acc", value_register1 + value_register2);
+                self.set_register("acc", value_register1.wrapping_add(value_register2));
             }
 
             // Add literal to register
             ADD_LIT_REG => {
                 // Read instruction
-                let literal = self.fetch16();
-                let register = self.fetch_register_index();
+                let literal = self.fetch16()?;
+                let register = self.fetch_register_index()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Add values
-                self.set_register("acc", value_register + literal);
+                self.set_register("acc", value_register.wrapping_add(literal));
             }
 
             // Subtract literal from register
             SUB_LIT_REG => {
                 // Read instruction
-                let literal = self.fetch16();
-                let register = self.fetch_register_index();
+                let literal = self.fetch16()?;
+                let register = self.fetch_register_index()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Subtract values
-                self.set_register("acc", value_register - literal);
+                self.set_register("acc", value_register.wrapping_sub(literal));
             }
 
             // Subtract register from literal
             SUB_REG_LIT => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let literal = self.fetch16();
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
 
                 // Subtract values
-                self.set_register("acc", value_register - literal);
+                self.set_register("acc", value_register.wrapping_sub(literal));
             }
 
             // Subtract register from register
             SUB_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
-
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Read register 2
-                let register2_value = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let value_register2 = u16::from_be_bytes(register2_value);
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let value_register2 = self.read_register(register2);
 
                 // Subtract values
-                self.set_register("acc", value_register1 - value_register2);
+                self.set_register("acc", value_register1.wrapping_sub(value_register2));
             }
 
             // Multiply literal by register
             MUL_LIT_REG => {
                 // Read instruction
-                let literal = self.fetch16();
-                let register = self.fetch_register_index();
+                let literal = self.fetch16()?;
+                let register = self.fetch_register_index()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Multiply values
-                self.set_register("acc", value_register * literal);
+                self.set_register("acc", value_register.wrapping_mul(literal));
             }
 
             // Multiply register by register
             MUL_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
-
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Read register 2
-                let register2_value = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let value_register2 = u16::from_be_bytes(register2_value);
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let value_register2 = self.read_register(register2);
 
                 // Multiply values
-                self.set_register("acc", value_register1 * value_register2);
+                self.set_register("acc", value_register1.wrapping_mul(value_register2));
             }
 
             // Increment register
             INC_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-
-                // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
+                let register = self.fetch_register_index()?;
 
                 // Increment value
-                let old_value = u16::from_be_bytes(register_memory);
-                let new_value = (old_value + 1).to_be_bytes();
+                let old_value = self.read_register(register);
 
                 // Write register
-                self.registers.set_byte(new_value[0], register);
-                self.registers.set_byte(new_value[1], register + 1);
+                self.write_register(register, old_value.wrapping_add(1))?;
             }
 
             // Decrement register
             DEC_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-
-                // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
+                let register = self.fetch_register_index()?;
 
                 // Decrement value
-                let old_value = u16::from_be_bytes(register_memory);
-                let new_value = (old_value - 1).to_be_bytes();
+                let old_value = self.read_register(register);
 
                 // Write register
-                self.registers.set_byte(new_value[0], register);
-                self.registers.set_byte(new_value[1], register + 1);
+                self.write_register(register, old_value.wrapping_sub(1))?;
             }
 
             // Binary manipulation instructions
@@ -576,113 +932,65 @@ impl CPU {
             // Left shift register by literal
             LSH_REG_LIT => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let literal = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
-
-                // Shift value
-                let new_value = (value_register << literal).to_be_bytes();
+                let value_register = self.read_register(register);
 
                 // Left shift value
-                self.registers.set_byte(new_value[0], register);
-                self.registers.set_byte(new_value[1], register + 1);
+                self.write_register(register, shift_left(value_register, literal))?;
             }
 
             // Left shift register by register
             LSH_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
-
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
-
-                // Read register 2
-                let register2_memory = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let shift_by = u16::from_be_bytes(register2_memory);
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Shift value
-                let new_value = (value_register1 << shift_by).to_be_bytes();
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let shift_by = self.read_register(register2);
 
                 // Write register 1
-                self.registers.set_byte(new_value[0], register1);
-                self.registers.set_byte(new_value[1], register1 + 1);
+                self.write_register(register1, shift_left(value_register1, shift_by))?;
             }
 
             // Right shift register by literal
             RSH_REG_LIT => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let literal = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
-                // Shift value
-                let new_value = (value_register >> literal).to_be_bytes();
-
-                // Left shift value
-                self.registers.set_byte(new_value[0], register);
-                self.registers.set_byte(new_value[1], register + 1);
+                // Right shift value
+                self.write_register(register, shift_right(value_register, literal))?;
             }
 
             // Right shift register by register
             RSH_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
-
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
-
-                // Read register 2
-                let register2_memory = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let shift_by = u16::from_be_bytes(register2_memory);
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Shift value
-                let new_value = (value_register1 >> shift_by).to_be_bytes();
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let shift_by = self.read_register(register2);
 
                 // Write register 1
-                self.registers.set_byte(new_value[0], register1);
-                self.registers.set_byte(new_value[1], register1 + 1);
+                self.write_register(register1, shift_right(value_register1, shift_by))?;
             }
 
             // And register with literal
             AND_REG_LIT => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let literal = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // And values
                 self.set_register("acc", value_register & literal)
@@ -691,22 +999,12 @@ impl CPU {
             // And register with register
             AND_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
-
-                // Read register 1
-                let register2_memory = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let value_register2 = u16::from_be_bytes(register2_memory);
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let value_register2 = self.read_register(register2);
 
                 // And values
                 self.set_register("acc", value_register1 & value_register2)
@@ -715,74 +1013,244 @@ impl CPU {
             // Or register with literal
             OR_REG_LIT => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let literal = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
-                // And values
+                // Or values
                 self.set_register("acc", value_register | literal)
             }
 
             // Or register with register
             OR_REG_REG => {
                 // Read instruction
-                let register1 = self.fetch_register_index();
-                let register2 = self.fetch_register_index();
-
-                // Read register 1
-                let register1_memory = [
-                    self.registers.get_byte(register1),
-                    self.registers.get_byte(register1 + 1),
-                ];
-                let value_register1 = u16::from_be_bytes(register1_memory);
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
 
-                // Read register 1
-                let register2_memory = [
-                    self.registers.get_byte(register2),
-                    self.registers.get_byte(register2 + 1),
-                ];
-                let value_register2 = u16::from_be_bytes(register2_memory);
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let value_register2 = self.read_register(register2);
 
-                // And values
+                // Or values
                 self.set_register("acc", value_register1 | value_register2)
             }
 
             // Not register
             NOT => {
                 // Read instruction
-                let register = self.fetch_register_index();
+                let register = self.fetch_register_index()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Not value
                 self.set_register("acc", !value_register)
             }
 
+            // Xor register with literal
+            XOR_REG_LIT => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Xor values
+                self.set_register("acc", value_register ^ literal)
+            }
+
+            // Xor register with register
+            XOR_REG_REG => {
+                // Read instruction
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
+
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let value_register2 = self.read_register(register2);
+
+                // Xor values
+                self.set_register("acc", value_register1 ^ value_register2)
+            }
+
+            // Rotate register left by literal
+            ROL_REG_LIT => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Rotate value
+                let new_value = value_register.rotate_left(literal as u32 % 16);
+                self.write_register(register, new_value)?;
+            }
+
+            // Rotate register left by register
+            ROL_REG_REG => {
+                // Read instruction
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
+
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let rotate_by = self.read_register(register2);
+
+                // Write register 1
+                let new_value = value_register1.rotate_left(rotate_by as u32 % 16);
+                self.write_register(register1, new_value)?;
+            }
+
+            // Rotate register right by literal
+            ROR_REG_LIT => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Rotate value
+                let new_value = value_register.rotate_right(literal as u32 % 16);
+                self.write_register(register, new_value)?;
+            }
+
+            // Rotate register right by register
+            ROR_REG_REG => {
+                // Read instruction
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
+
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let rotate_by = self.read_register(register2);
+
+                // Write register 1
+                let new_value = value_register1.rotate_right(rotate_by as u32 % 16);
+                self.write_register(register1, new_value)?;
+            }
+
+            // Bit instructions, bit numbers are taken modulo 16
+
+            // Test bit of register by literal
+            TST_REG_LIT => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Test bit
+                self.set_register("acc", (value_register >> (literal % 16)) & 1)
+            }
+
+            // Test bit of register by register
+            TST_REG_REG => {
+                // Read instruction
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
+
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let bit = self.read_register(register2);
+
+                // Test bit
+                self.set_register("acc", (value_register1 >> (bit % 16)) & 1)
+            }
+
+            // Set bit of register by literal
+            SET_REG_LIT => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Set bit
+                self.write_register(register, value_register | (1 << (literal % 16)))?;
+            }
+
+            // Set bit of register by register
+            SET_REG_REG => {
+                // Read instruction
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
+
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let bit = self.read_register(register2);
+
+                // Set bit
+                self.write_register(register1, value_register1 | (1 << (bit % 16)))?;
+            }
+
+            // Clear bit of register by literal
+            CLR_REG_LIT => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+                let literal = self.fetch16()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Clear bit
+                self.write_register(register, value_register & !(1 << (literal % 16)))?;
+            }
+
+            // Clear bit of register by register
+            CLR_REG_REG => {
+                // Read instruction
+                let register1 = self.fetch_register_index()?;
+                let register2 = self.fetch_register_index()?;
+
+                // Read registers
+                let value_register1 = self.read_register(register1);
+                let bit = self.read_register(register2);
+
+                // Clear bit
+                self.write_register(register1, value_register1 & !(1 << (bit % 16)))?;
+            }
+
+            // Count set bits of register
+            CNT_REG => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Count bits
+                self.set_register("acc", value_register.count_ones() as u16)
+            }
+
+            // Swap bytes of register
+            SWP_REG => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+
+                // Read register
+                let value_register = self.read_register(register);
+
+                // Swap bytes
+                self.write_register(register, value_register.swap_bytes())?;
+            }
+
             // Branching instructions
 
             // Jump if register not equal
             JNE_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let address = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Move instruction pointer
                 if value_register != self.get_register("acc") {
@@ -793,8 +1261,8 @@ impl CPU {
             // Jump if literal not equal
             JNE_LIT => {
                 // Read instruction
-                let value = self.fetch16();
-                let address = self.fetch16();
+                let value = self.fetch16()?;
+                let address = self.fetch16()?;
 
                 // Move instruction pointer
                 if value != self.get_register("acc") {
@@ -805,15 +1273,11 @@ impl CPU {
             // Jump if register equal
             JEQ_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let address = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Move instruction pointer
                 if value_register == self.get_register("acc") {
@@ -824,8 +1288,8 @@ impl CPU {
             // Jump if literal equal
             JEQ_LIT => {
                 // Read instruction
-                let value = self.fetch16();
-                let address = self.fetch16();
+                let value = self.fetch16()?;
+                let address = self.fetch16()?;
 
                 // Move instruction pointer
                 if value == self.get_register("acc") {
@@ -836,15 +1300,11 @@ impl CPU {
             // Jump if register less then
             JLT_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let address = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Move instruction pointer
                 if value_register < self.get_register("acc") {
@@ -855,8 +1315,8 @@ impl CPU {
             // Jump if literal less than
             JLT_LIT => {
                 // Read instruction
-                let value = self.fetch16();
-                let address = self.fetch16();
+                let value = self.fetch16()?;
+                let address = self.fetch16()?;
 
                 // Move instruction pointer
                 if value < self.get_register("acc") {
@@ -867,15 +1327,11 @@ impl CPU {
             // Jump if register greater then
             JGT_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let address = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Move instruction pointer
                 if value_register > self.get_register("acc") {
@@ -886,11 +1342,11 @@ impl CPU {
             // Jump if literal greater than
             JGT_LIT => {
                 // Read instruction
-                let value = self.fetch16();
-                let address = self.fetch16();
+                let value = self.fetch16()?;
+                let address = self.fetch16()?;
 
                 // Move instruction pointer
-                if value < self.get_register("acc") {
+                if value > self.get_register("acc") {
                     self.set_register("ip", address);
                 }
             }
@@ -898,15 +1354,11 @@ impl CPU {
             // Jump if register greater then
             JLE_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let address = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Move instruction pointer
                 if value_register <= self.get_register("acc") {
@@ -917,8 +1369,8 @@ impl CPU {
             // Jump if literal less or equal than
             JLE_LIT => {
                 // Read instruction
-                let value = self.fetch16();
-                let address = self.fetch16();
+                let value = self.fetch16()?;
+                let address = self.fetch16()?;
 
                 // Move instruction pointer
                 if value <= self.get_register("acc") {
@@ -929,15 +1381,11 @@ impl CPU {
             // Jump if register greater then
             JGE_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
-                let address = self.fetch16();
+                let register = self.fetch_register_index()?;
+                let address = self.fetch16()?;
 
                 // Read register
-                let register_memory = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
-                let value_register = u16::from_be_bytes(register_memory);
+                let value_register = self.read_register(register);
 
                 // Move instruction pointer
                 if value_register >= self.get_register("acc") {
@@ -948,8 +1396,8 @@ impl CPU {
             // Jump if literal greater or equal than
             JGE_LIT => {
                 // Read instruction
-                let value = self.fetch16();
-                let address = self.fetch16();
+                let value = self.fetch16()?;
+                let address = self.fetch16()?;
 
                 // Move instruction pointer
                 if value >= self.get_register("acc") {
@@ -962,48 +1410,43 @@ impl CPU {
             // Push literal
             PSH_LIT => {
                 // Read instruction
-                let literal = self.fetch16().to_be_bytes();
+                let literal = self.fetch16()?;
 
                 // Push literal
-                self.push(literal);
+                self.push(literal)?;
             }
 
             // Push register
             PSH_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
+                let register = self.fetch_register_index()?;
 
                 // Read register
-                let value = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
+                let value = self.read_register(register);
 
                 // Push register
-                self.push(value);
+                self.push(value)?;
             }
 
             // Pop
             POP => {
                 // Read instruction
-                let register = self.fetch_register_index();
+                let register = self.fetch_register_index()?;
 
                 // Pop value
-                let value = self.pop();
-                let bytes = value.to_be_bytes();
+                let value = self.pop()?;
 
                 // Write register
-                self.registers.set_byte(bytes[0], register);
-                self.registers.set_byte(bytes[1], register + 1);
+                self.write_register(register, value)?;
             }
 
             // Call subroutine from literal
             CAL_LIT => {
                 // Read instruction
-                let literal = self.fetch16();
+                let literal = self.fetch16()?;
 
                 // Push state
-                self.push_state();
+                self.push_state()?;
 
                 // Move instruction pointer
                 self.set_register("ip", literal);
@@ -1012,91 +1455,1039 @@ impl CPU {
             // Call subroutine from register
             CAL_REG => {
                 // Read instruction
-                let register = self.fetch_register_index();
+                let register = self.fetch_register_index()?;
 
                 // Read register
-                let address = [
-                    self.registers.get_byte(register),
-                    self.registers.get_byte(register + 1),
-                ];
+                let address = self.read_register(register);
 
                 // Push state
-                self.push_state();
+                self.push_state()?;
 
                 // Move instruction pointer
-                self.set_register("ip", u16::from_be_bytes(address));
+                self.set_register("ip", address);
             }
 
             // Return from CAL
             RET => {
                 // Restore state from the stack
-                self.pop_state();
+                self.pop_state()?;
+            }
+
+            // Jump to subroutine from literal
+            JSR_LIT => {
+                // Read instruction
+                let literal = self.fetch16()?;
+
+                // Push return address and frame
+                self.push_frame()?;
+
+                // Move instruction pointer
+                self.set_register("ip", literal);
+            }
+
+            // Jump to subroutine from register
+            JSR_REG => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+
+                // Read register
+                let address = self.read_register(register);
+
+                // Push return address and frame
+                self.push_frame()?;
+
+                // Move instruction pointer
+                self.set_register("ip", address);
+            }
+
+            // Return from JSR and pop literal argument words
+            RTS_LIT => {
+                // Read instruction
+                let arguments = self.fetch16()?;
+
+                // Restore frame and remove arguments
+                self.pop_frame()?;
+                self.drop_words(arguments)?;
+            }
+
+            // System instructions
+
+            // Load page table from literal
+            LPT_LIT => {
+                // Read instruction
+                let address = self.fetch16()?;
+
+                // Enable address translation
+                self.mmu.load_page_table(address);
+            }
+
+            // Load page table from register
+            LPT_REG => {
+                // Read instruction
+                let register = self.fetch_register_index()?;
+
+                // Read register
+                let address = self.read_register(register);
+
+                // Enable address translation
+                self.mmu.load_page_table(address);
+            }
+
+            // Unload page table
+            UPT => {
+                // Go back to identity mapping
+                self.mmu.unload_page_table();
+            }
+
+            // Call the supervisor
+            SYSCALL => {
+                // Read instruction
+                let number = self.fetch16()?;
+
+                // Let the host handle semihosting calls
+                if self.semihosting && is_host_call(number) {
+                    return self.semihost(number);
+                }
+
+                // Call handler with the syscall number, returning after SYSCALL
+                let return_address = self.get_register("ip");
+                if !self.enter_exception(EXCEPTION_SYSCALL, &[number], return_address)? {
+                    return Err(Fault::Syscall { number });
+                }
+            }
+
+            // Return from an exception handler
+            SYSRET => {
+                // Restore state from the stack
+                self.pop_state()?;
+
+                // Restore mode
+                let supervisor = self.pop()?;
+                self.set_supervisor(supervisor != 0);
             }
 
             _ => {
-                panic!("Instruction: 0x{:02X} not found", instruction);
+                return Err(Fault::Instruction {
+                    opcode: instruction,
+                })
             }
         }
+
+        Ok(())
     }
 
-    // Run one instruction
-    fn step(&mut self, debug: bool) -> bool {
-        // Read instruction
-        let instruction = self.fetch8();
+    // Handle a semihosting call, see semihost.rs
+    fn semihost(&mut self, number: u16) -> Result<(), Fault> {
+        match number {
+            // Stop with the status
+            SEMIHOST_EXIT => self.exit_status = Some(self.get_register("r1")),
+
+            // Print a string
+            SEMIHOST_PRINT => {
+                let text = self.read_string(self.get_register("r1"))?;
+                eprint!("{}", String::from_utf8_lossy(&text));
+            }
 
-        // Check if program ended
-        if instruction == HLT {
-            // Return true if ended
-            return true;
+            // Read a file to a buffer, the size is capped so it can't be the error
+            SEMIHOST_READ => {
+                let path = self.read_string(self.get_register("r1"))?;
+                let buffer = self.get_register("r2");
+                let size = self.get_register("r3").min(0xFFFE);
+                let offset = self.get_register("r4");
+                let result = match read_file(&String::from_utf8_lossy(&path), offset, size) {
+                    Some(bytes) => {
+                        for (index, byte) in bytes.iter().enumerate() {
+                            self.write_uint_8(buffer.wrapping_add(index as u16), *byte)?;
+                        }
+                        bytes.len() as u16
+                    }
+                    None => SEMIHOST_ERROR,
+                };
+                self.set_register("acc", result);
+            }
+
+            // Seconds since the Unix epoch
+            SEMIHOST_TIME => {
+                let time = time();
+                self.set_register("acc", time as u16);
+                self.set_register("r1", (time >> 16) as u16);
+            }
+
+            _ => return Err(Fault::Syscall { number }),
         }
 
-        // Execute instruction
-        self.execute(instruction);
+        self.host_call = true;
+        Ok(())
+    }
+
+    // Read a 0-terminated string
+    fn read_string(&self, address: u16) -> Result<Vec<u8>, Fault> {
+        let mut text = Vec::new();
+        for offset in 0..0xFFFF {
+            match self.read_uint_8(address.wrapping_add(offset), Access::Read)? {
+                0x00 => break,
+                byte => text.push(byte),
+            }
+        }
+        Ok(text)
+    }
 
-        // Print debug info
-        if debug {
-            self.debug();
-            self.device_mapper.view_memory(self.get_register("ip"), 16);
-            self.device_mapper.view_memory(0xFFFF - 16, 16);
-            self.device_mapper.view_memory(0x0F00, 16);
-            println!("");
+    // Switch between supervisor and user mode
+    fn set_supervisor(&mut self, supervisor: bool) {
+        self.supervisor = supervisor;
+        self.device_mapper.set_supervisor(supervisor);
+    }
 
-            // Wait for input
-            std::io::stdin().read_line(&mut String::new()).unwrap();
+    // Call the handler of exception number code
+    //
+    // The handler runs in supervisor mode and is called like a CAL with the
+    // given arguments. The mode to return to is pushed before the arguments,
+    // so handlers return with SYSRET. Returns false if there is no handler.
+    // If a push faults the mode and stack pointer are put back.
+    fn enter_exception(
+        &mut self,
+        code: u16,
+        arguments: &[u16],
+        return_address: u16,
+    ) -> Result<bool, Fault> {
+        let previous_supervisor = self.supervisor;
+        self.set_supervisor(true);
+
+        // Find handler, 0x0000 means not handled
+        let vector = EXCEPTION_VECTOR_TABLE.wrapping_add(code * 2);
+        let handler = self.device_mapper.get_uint_16(vector).unwrap_or(0x0000);
+        if handler == 0x0000 {
+            self.set_supervisor(previous_supervisor);
+            return Ok(false);
         }
 
-        // Return false if not ended
-        false
+        // Call handler
+        let sp = self.get_register("sp");
+        self.set_register("ip", return_address);
+        if let Err(fault) = self.push_exception(previous_supervisor, arguments) {
+            self.set_supervisor(previous_supervisor);
+            self.set_register("sp", sp);
+            return Err(fault);
+        }
+        self.set_register("ip", handler);
+        Ok(true)
     }
 
-    // Run program
-    pub fn run(&mut self, debug: bool) {
-        // Set halt to false
-        let mut halt = false;
+    // Push the mode, the arguments and the state for an exception handler
+    fn push_exception(&mut self, supervisor: bool, arguments: &[u16]) -> Result<(), Fault> {
+        self.push(supervisor as u16)?;
+        for argument in arguments {
+            self.push(*argument)?;
+        }
+        self.push(arguments.len() as u16)?;
+        self.push_state()
+    }
 
-        // While running program
-        while !halt {
-            // Run instruction
-            halt = self.step(debug);
+    // Deliver a fault to its exception handler
+    //
+    // The handler gets two arguments, the faulting address and the kind of
+    // access (0 read, 1 write, 2 execute). The saved ip is the start of the
+    // faulting instruction and the registers are put back as they were
+    // before it, so SYSRET retries it. Without a handler, or on a fault
+    // entering it, the CPU stops at the faulting instruction.
+    fn raise(&mut self, fault: Fault, instruction_address: u16) -> Option<StopReason> {
+        for (index, value) in self.saved_registers.into_iter().enumerate() {
+            self.store_register((index + 1) * 2, value);
         }
 
-        // Print debug info
-        if debug {
-            self.debug();
-            self.device_mapper.view_memory(self.get_register("ip"), 16);
-            self.device_mapper.view_memory(0xFFFF - 16, 16);
-            self.device_mapper.view_memory(0x0F00, 16);
-            println!("");
+        let arguments = [fault.address(), fault.access() as u16];
+        let fault = match self.enter_exception(fault.code(), &arguments, instruction_address) {
+            Ok(true) => return None,
+            Ok(false) => fault,
+            Err(double_fault) => double_fault,
+        };
+        self.set_register("ip", instruction_address);
+        Some(StopReason::Fault {
+            fault,
+            ip: instruction_address,
+        })
+    }
+
+    // Run one instruction, returns why the CPU stopped if it did
+    pub fn step(&mut self) -> Option<StopReason> {
+        // Let the host look at the CPU before the instruction
+        if let Some(mut hook) = self.on_step.take() {
+            hook(self);
+            self.on_step = Some(hook);
+        }
+
+        // Remember where the instruction starts and the registers it may
+        // change, to restart it after a fault
+        let instruction_address = self.get_register("ip");
+        self.saved_registers = std::array::from_fn(|index| self.read_register((index + 1) * 2));
+
+        // Read and execute instruction
+        let mut opcode = None;
+        let result = self.fetch8().and_then(|instruction| {
+            opcode = Some(instruction);
+
+            // Check if the instruction is allowed in the current mode
+            if !self.supervisor && SUPERVISOR_INSTRUCTIONS.contains(&instruction) {
+                return Err(Fault::Privilege { register: None });
+            }
+
+            // Check if program ended
+            if instruction == HLT {
+                return Ok(true);
+            }
+
+            self.execute(instruction).map(|_| false)
+        });
+
+        // Hand faults to the guest
+        let host_call = std::mem::take(&mut self.host_call);
+        let (faulted, stop) = match result {
+            Ok(true) => (false, Some(StopReason::Halted)),
+            Ok(false) => (false, self.exit_status.take().map(StopReason::Exit)),
+            Err(fault) => (true, self.raise(fault, instruction_address)),
+        };
+
+        // Count cycles of the instruction and its memory accesses
+        let cycles = opcode.map_or(0, instruction_cycles) + self.device_mapper.take_wait_cycles();
+        self.cycles += cycles;
+        self.device_mapper.tick(cycles);
+        self.throttle();
+
+        // Profile instruction
+        let ip = self.get_register("ip");
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(instruction_address, cycles);
+            match opcode {
+                _ if faulted && stop.is_none() => profiler.enter(ip),
+                Some(SYSCALL) if host_call => {}
+                Some(CAL_LIT | CAL_REG | JSR_LIT | JSR_REG | SYSCALL) => profiler.enter(ip),
+                Some(RET | RTS_LIT | SYSRET) => profiler.leave(),
+                _ => {}
+            }
         }
 
-        // Exit program
-        std::process::exit(1)
+        // Return why the CPU stopped
+        if stop.is_some() {
+            if stop == Some(StopReason::Halted) {
+                if let Some(mut hook) = self.on_halt.take() {
+                    hook(self);
+                    self.on_halt = Some(hook);
+                }
+            }
+            return stop;
+        }
+
+        None
+    }
+
+    // Run until the program stops
+    pub fn run(&mut self) -> StopReason {
+        self.run_within(u64::MAX, u64::MAX)
+    }
+
+    // Run at most the given number of instructions
+    pub fn run_for(&mut self, instructions: u64) -> StopReason {
+        self.run_within(instructions, u64::MAX)
+    }
+
+    // Run until the given number of cycles passed
+    pub fn run_for_cycles(&mut self, cycles: u64) -> StopReason {
+        self.run_within(u64::MAX, cycles)
+    }
+
+    // Run until the program stops or a budget runs out
+    //
+    // A breakpoint stops the CPU before the instruction at its address, except
+    // for the first instruction, so running again continues past it.
+    fn run_within(&mut self, instructions: u64, cycles: u64) -> StopReason {
+        let end = self.cycles.saturating_add(cycles);
+        let mut count = 0;
+        loop {
+            if self.stop.swap(false, Ordering::Relaxed) {
+                return StopReason::Stopped;
+            }
+            if count == instructions || self.cycles >= end {
+                return StopReason::Budget;
+            }
+            let ip = self.get_register("ip");
+            if count != 0 && self.breakpoints.contains(&ip) {
+                return StopReason::Breakpoint(ip);
+            }
+
+            if let Some(reason) = self.step() {
+                return reason;
+            }
+            count += 1;
+        }
+    }
+
+    // Handle to stop a run from another thread, running again continues
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            stop: self.stop.clone(),
+        }
+    }
+
+    // Stop runs before the instruction at an address
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    // Remove the breakpoint at an address
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    // Call a hook before every instruction
+    pub fn on_step(&mut self, hook: impl FnMut(&CPU) + Send + 'static) {
+        self.on_step = Some(Box::new(hook));
+    }
+
+    // Call a hook on every memory access of the CPU, after it succeeded
+    pub fn on_memory_access(&mut self, hook: impl FnMut(&MemoryAccess) + Send + 'static) {
+        *self.on_memory_access.borrow_mut() = Some(Box::new(hook));
+    }
+
+    // Call a hook when the program halts
+    pub fn on_halt(&mut self, hook: impl FnMut(&CPU) + Send + 'static) {
+        self.on_halt = Some(Box::new(hook));
+    }
+
+    // Let the host handle SYSCALLs with semihosting numbers, see semihost.rs
+    pub fn enable_semihosting(&mut self) {
+        self.semihosting = true;
+    }
+
+    // Count executions and cycles of every instruction from now on
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    // Profiler, if enabled
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Use symbols to show addresses as labels
+    pub fn load_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    // Symbols, if loaded
+    pub fn symbols(&self) -> Option<&Symbols> {
+        self.symbols.as_ref()
+    }
+
+    // Number of cycles run so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Slow the CPU down to run at most hz cycles per second, None runs at full speed
+    pub fn set_clock_speed(&mut self, hz: Option<u64>) {
+        self.clock_speed = hz;
+        self.clock_start = None;
+    }
+
+    // Sleep until real time catches up with the virtual clock
+    fn throttle(&mut self) {
+        let Some(hz) = self.clock_speed else {
+            return;
+        };
+
+        // Start the clock at the first instruction
+        let (start, start_cycles) = *self
+            .clock_start
+            .get_or_insert((Instant::now(), self.cycles));
+
+        // Sleep when ahead, in steps of at least a millisecond
+        let expected = Duration::from_secs_f64((self.cycles - start_cycles) as f64 / hz as f64);
+        let elapsed = start.elapsed();
+        if expected > elapsed + Duration::from_millis(1) {
+            std::thread::sleep(expected - elapsed);
+        }
     }
 
     // Print registers
     pub fn debug(&self) {
-        for (_, name) in self.registers_names.iter().enumerate() {
-            println!("{}: 0x{:04X}", name, self.get_register(name));
+        for name in self.registers_names.iter() {
+            let value = self.get_register(name);
+            match &self.symbols {
+                Some(symbols) if name == "ip" => {
+                    println!("{}: {}", name, symbols.format_address(value))
+                }
+                _ => println!("{}: 0x{:04X}", name, value),
+            }
+        }
+
+        // Print source line
+        let ip = self.get_register("ip");
+        if let Some(line) = self.symbols.as_ref().and_then(|symbols| symbols.line(ip)) {
+            println!("line: {}:{}", line.file, line.line);
+        }
+
+        // Print mode
+        let mode = if self.supervisor {
+            "supervisor"
+        } else {
+            "user"
+        };
+        println!("mode: {}", mode);
+
+        // Print page table base register
+        if self.mmu.is_enabled() {
+            println!("ptb: 0x{:04X}", self.mmu.page_table());
+        }
+    }
+}
+
+// Shift left, bits shifted out are lost
+fn shift_left(value: u16, amount: u16) -> u16 {
+    value.checked_shl(amount as u32).unwrap_or(0)
+}
+
+// Shift right, bits shifted out are lost
+fn shift_right(value: u16, amount: u16) -> u16 {
+    value.checked_shr(amount as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_mapper::Permissions;
+    use crate::machine::{MachineBuilder, MachineConfig};
+    use crate::mmu::{PAGE_COUNT, PAGE_EXECUTE, PAGE_PRESENT, PAGE_USER, PAGE_WRITE};
+
+    // CPU of the default machine running a program
+    fn cpu(program: &[u8]) -> CPU {
+        MachineBuilder::new(MachineConfig::default_machine())
+            .with_program(program.to_vec())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn cpu_runs_on_another_thread() {
+        let mut cpu = cpu(&[MOV_LIT_REG, 0x12, 0x34, ACC, HLT]);
+        cpu.on_halt(|cpu| assert_eq!(cpu.get_register("acc"), 0x1234));
+        let reason = std::thread::spawn(move || cpu.run()).join().unwrap();
+        assert_eq!(reason, StopReason::Halted);
+    }
+
+    // Program made of code at addresses, one instruction per slice
+    fn image(parts: &[(u16, &[&[u8]])]) -> Vec<u8> {
+        let mut image = Vec::new();
+        for (address, code) in parts {
+            let code = code.concat();
+            let start = *address as usize;
+            image.resize(image.len().max(start + code.len()), 0);
+            image[start..start + code.len()].copy_from_slice(&code);
         }
+        image
+    }
+
+    // Program with a caller at $0000 and a subroutine at $0100
+    fn program(caller: &[&[u8]], subroutine: &[&[u8]]) -> Vec<u8> {
+        image(&[(0x0000, caller), (0x0100, subroutine)])
+    }
+
+    // Identity page table at $9000, with the flags of every page
+    fn page_table(cpu: &mut CPU, flags: impl Fn(u16) -> u16) {
+        for page in 0..PAGE_COUNT {
+            let entry = page << 8 | flags(page);
+            let address = 0x9000 + page * 2;
+            cpu.device_mapper_mut()
+                .set_byte((entry >> 8) as u8, address);
+            cpu.device_mapper_mut().set_byte(entry as u8, address + 1);
+        }
+    }
+
+    #[test]
+    fn ret_restores_the_caller_and_drops_the_arguments() {
+        let mut cpu = cpu(&program(
+            &[
+                &[MOV_LIT_REG, 0x11, 0x11, R1],
+                &[MOV_LIT_REG, 0x88, 0x88, R8],
+                &[PSH_LIT, 0x00, 0x05],
+                &[PSH_LIT, 0x00, 0x07],
+                &[PSH_LIT, 0x00, 0x02],
+                &[CAL_LIT, 0x01, 0x00],
+                &[HLT],
+            ],
+            &[
+                // Clobber r1 and r8 and push a local
+                &[MOV_LIT_REG, 0xAA, 0xAA, R1],
+                &[MOV_LIT_REG, 0xBB, 0xBB, R8],
+                &[PSH_LIT, 0xCC, 0xCC],
+                // First and last argument, their sum is the return value
+                &[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 26, R2],
+                &[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 24, R3],
+                &[ADD_REG_REG, R2, R3],
+                &[RET],
+            ],
+        ));
+        let (sp, fp) = (cpu.get_register("sp"), cpu.get_register("fp"));
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("acc"), 5 + 7);
+        assert_eq!(cpu.get_register("r1"), 0x1111);
+        assert_eq!(cpu.get_register("r2"), 0);
+        assert_eq!(cpu.get_register("r3"), 0);
+        assert_eq!(cpu.get_register("r8"), 0x8888);
+        assert_eq!(cpu.get_register("ip"), 21);
+        assert_eq!(cpu.get_register("sp"), sp);
+        assert_eq!(cpu.get_register("fp"), fp);
+    }
+
+    #[test]
+    fn rts_pops_the_arguments_of_jsr() {
+        let mut cpu = cpu(&program(
+            &[
+                &[PSH_LIT, 0x00, 0x05],
+                &[PSH_LIT, 0x00, 0x07],
+                &[JSR_LIT, 0x01, 0x00],
+                &[HLT],
+            ],
+            &[
+                // Last argument is the return value, r1 is caller saved
+                &[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 6, ACC],
+                &[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 8, R1],
+                &[RTS_LIT, 0x00, 0x02],
+            ],
+        ));
+        let (sp, fp) = (cpu.get_register("sp"), cpu.get_register("fp"));
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("acc"), 7);
+        assert_eq!(cpu.get_register("r1"), 5);
+        assert_eq!(cpu.get_register("ip"), 10);
+        assert_eq!(cpu.get_register("sp"), sp);
+        assert_eq!(cpu.get_register("fp"), fp);
+    }
+
+    #[test]
+    fn pop_is_retried_after_a_page_fault() {
+        let mut cpu = cpu(&image(&[
+            (0x0000, &[&[LPT_LIT, 0x90, 0x00], &[POP, R1], &[HLT]]),
+            // Page fault handler maps page $80
+            (0x0100, &[&[MOV_LIT_MEM, 0x80, 0x07, 0x91, 0x00], &[SYSRET]]),
+            (0x1004, &[&[0x01, 0x00]]),
+        ]));
+        page_table(&mut cpu, |page| match page {
+            0x80 => 0,
+            _ => PAGE_PRESENT | PAGE_WRITE | PAGE_EXECUTE,
+        });
+        cpu.device_mapper_mut().set_byte(0x12, 0x8000);
+        cpu.device_mapper_mut().set_byte(0x34, 0x8001);
+        cpu.set_stack(0x80FE, 0x7000);
+        cpu.set_register("sp", 0x7FFE);
+
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("r1"), 0x1234);
+        assert_eq!(cpu.get_register("sp"), 0x8000);
+    }
+
+    #[test]
+    fn cal_is_retried_after_a_page_fault() {
+        let mut cpu = cpu(&image(&[
+            (
+                0x0000,
+                &[
+                    &[LPT_LIT, 0x90, 0x00],
+                    // Call SYSRET to drop to user mode after the call
+                    &[PSH_LIT, 0x00, 0x00],
+                    &[PSH_LIT, 0x00, 0x00],
+                    &[CAL_LIT, 0x00, 0x40],
+                    // The third push of the call is on the supervisor page
+                    &[PSH_LIT, 0x00, 0x05],
+                    &[PSH_LIT, 0x00, 0x01],
+                    &[CAL_LIT, 0x00, 0x60],
+                ],
+            ),
+            (0x0040, &[&[SYSRET]]),
+            (
+                0x0060,
+                &[&[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 24, ACC], &[RET]],
+            ),
+            // Page fault handler gives page $7F to user mode
+            (0x0100, &[&[MOV_LIT_MEM, 0x7F, 0x0F, 0x90, 0xFE], &[SYSRET]]),
+            (0x1004, &[&[0x01, 0x00]]),
+        ]));
+        page_table(&mut cpu, |page| match page {
+            0x7F => PAGE_PRESENT | PAGE_WRITE | PAGE_EXECUTE,
+            _ => PAGE_PRESENT | PAGE_WRITE | PAGE_EXECUTE | PAGE_USER,
+        });
+        cpu.set_stack(0x8008, 0x7000);
+        cpu.add_breakpoint(21);
+
+        assert_eq!(cpu.run(), StopReason::Breakpoint(21));
+        assert!(!cpu.device_mapper().is_supervisor());
+        assert_eq!(cpu.device_mapper().get_byte(0x90FF), 0x0F);
+        assert_eq!(cpu.get_register("acc"), 5);
+        assert_eq!(cpu.get_register("sp"), 0x8008);
+        assert_eq!(cpu.get_register("fp"), 0x8008);
+    }
+
+    #[test]
+    fn double_fault_stops_in_the_mode_of_the_instruction() {
+        let mut cpu = cpu(&image(&[
+            (
+                0x0000,
+                &[
+                    // Call SYSRET to drop to user mode after the call
+                    &[PSH_LIT, 0x00, 0x00],
+                    &[PSH_LIT, 0x00, 0x00],
+                    &[CAL_LIT, 0x00, 0x40],
+                    // Push until the stack overflows
+                    &[PSH_LIT, 0x00, 0x00],
+                    &[JEQ_LIT, 0x00, 0x00, 0x00, 0x09],
+                ],
+            ),
+            (0x0040, &[&[SYSRET]]),
+            (0x0100, &[&[HLT]]),
+            (0x100A, &[&[0x01, 0x00]]),
+        ]));
+        cpu.set_stack(0x8000, 0x7FEA);
+
+        assert_eq!(
+            cpu.run(),
+            StopReason::Fault {
+                fault: Fault::Stack {
+                    address: 0x7FE8,
+                    access: Access::Write
+                },
+                ip: 0x0009
+            }
+        );
+        assert!(!cpu.device_mapper().is_supervisor());
+        assert_eq!(cpu.get_register("sp"), 0x7FE8);
+    }
+
+    // CPU with a ROM at $8000 and data memory that can't be executed at $8100
+    fn protected_cpu(program: &[u8]) -> CPU {
+        let mut cpu = cpu(program);
+        let mm = cpu.device_mapper_mut();
+        mm.map(Device::new(0x0100, DeviceType::Rom), 0x8000, 0x80FF, true);
+        mm.map(
+            Device::new(0x0100, DeviceType::Memory),
+            0x8100,
+            0x81FF,
+            true,
+        )
+        .set_permissions(Permissions::READ_WRITE);
+        cpu
+    }
+
+    #[test]
+    fn writing_rom_faults() {
+        let mut cpu = protected_cpu(&[MOV_LIT_MEM, 0x12, 0x34, 0x80, 0x00, HLT]);
+        assert_eq!(
+            cpu.run(),
+            StopReason::Fault {
+                fault: Fault::Protection {
+                    address: 0x8000,
+                    access: Access::Write
+                },
+                ip: 0x0000
+            }
+        );
+        assert_eq!(cpu.device_mapper().get_byte(0x8000), 0x00);
+    }
+
+    #[test]
+    fn executing_data_faults() {
+        let mut cpu = protected_cpu(&[JEQ_LIT, 0x00, 0x00, 0x81, 0x00]);
+        assert_eq!(
+            cpu.run(),
+            StopReason::Fault {
+                fault: Fault::Protection {
+                    address: 0x8100,
+                    access: Access::Execute
+                },
+                ip: 0x8100
+            }
+        );
+    }
+
+    #[test]
+    fn handlers_catch_faults() {
+        let mut cpu = protected_cpu(&image(&[
+            (0x0000, &[&[MOV_LIT_MEM, 0x12, 0x34, 0x80, 0x00]]),
+            // Protection fault handler halts with the address and access
+            (
+                0x0100,
+                &[
+                    &[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 26, R1],
+                    &[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 24, R2],
+                    &[HLT],
+                ],
+            ),
+            (0x1002, &[&[0x01, 0x00]]),
+        ]));
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("r1"), 0x8000);
+        assert_eq!(cpu.get_register("r2"), Access::Write as u16);
+    }
+
+    #[test]
+    fn pushing_past_the_limit_overflows() {
+        let mut cpu = cpu(&[
+            PSH_LIT, 0x00, 0x01, PSH_LIT, 0x00, 0x02, PSH_LIT, 0x00, 0x03, HLT,
+        ]);
+        cpu.set_stack(0x8004, 0x8002);
+        assert_eq!(
+            cpu.run(),
+            StopReason::Fault {
+                fault: Fault::Stack {
+                    address: 0x8000,
+                    access: Access::Write
+                },
+                ip: 0x0006
+            }
+        );
+        assert_eq!(cpu.get_register("sp"), 0x8000);
+    }
+
+    #[test]
+    fn popping_past_the_base_underflows() {
+        let mut cpu = cpu(&[PSH_LIT, 0x00, 0x01, POP, R1, POP, R2, HLT]);
+        cpu.set_stack(0x8004, 0x8000);
+        assert_eq!(
+            cpu.run(),
+            StopReason::Fault {
+                fault: Fault::Stack {
+                    address: 0x8006,
+                    access: Access::Read
+                },
+                ip: 0x0005
+            }
+        );
+        assert_eq!(cpu.get_register("r1"), 0x0001);
+        assert_eq!(cpu.get_register("sp"), 0x8004);
+    }
+
+    #[test]
+    fn machines_set_the_stack() {
+        let config = MachineConfig::parse(
+            "device ram memory $0000 $FFFF program\nstack $8000 $7000\n",
+            std::path::Path::new(""),
+        )
+        .unwrap();
+        let cpu = MachineBuilder::new(config).build().unwrap();
+        assert_eq!(cpu.get_register("sp"), 0x8000);
+        assert_eq!(cpu.get_register("fp"), 0x8000);
+        assert_eq!(cpu.get_register("sb"), 0x8000);
+        assert_eq!(cpu.get_register("sl"), 0x7000);
+    }
+
+    // Program adding 1 to acc three times, two bytes per instruction
+    const COUNT: [u8; 7] = [INC_REG, ACC, INC_REG, ACC, INC_REG, ACC, HLT];
+
+    #[test]
+    fn budgets_stop_the_run() {
+        let (mut cpu, mut timed) = (cpu(&COUNT), cpu(&COUNT));
+        assert_eq!(cpu.run_for(2), StopReason::Budget);
+        assert_eq!(cpu.get_register("acc"), 2);
+        assert_eq!(cpu.get_register("ip"), 4);
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("acc"), 3);
+
+        assert_eq!(timed.run_for_cycles(3), StopReason::Budget);
+        assert_eq!(timed.cycles(), 4);
+        assert_eq!(timed.get_register("acc"), 2);
+    }
+
+    #[test]
+    fn breakpoints_stop_before_their_address() {
+        let mut cpu = cpu(&COUNT);
+        cpu.add_breakpoint(0);
+        cpu.add_breakpoint(4);
+        assert_eq!(cpu.run(), StopReason::Breakpoint(4));
+        assert_eq!(cpu.get_register("acc"), 2);
+
+        // Running again continues past it
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("acc"), 3);
+
+        cpu.set_ip(0);
+        cpu.remove_breakpoint(4);
+        assert_eq!(cpu.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn stop_handles_stop_the_run() {
+        let (mut cpu, mut looping) = (cpu(&COUNT), cpu(&[JEQ_LIT, 0x00, 0x00, 0x00, 0x00]));
+        cpu.stop_handle().stop();
+        assert_eq!(cpu.run(), StopReason::Stopped);
+        assert_eq!(cpu.get_register("ip"), 0);
+        assert_eq!(cpu.run(), StopReason::Halted);
+
+        // Stop a loop running on another thread
+        let handle = looping.stop_handle();
+        let thread = std::thread::spawn(move || looping.run());
+        std::thread::sleep(Duration::from_millis(10));
+        handle.stop();
+        assert_eq!(thread.join().unwrap(), StopReason::Stopped);
+    }
+
+    // Run instructions up to a HLT
+    fn run(code: &[&[u8]]) -> CPU {
+        let mut cpu = cpu(&[code.concat(), vec![HLT]].concat());
+        assert_eq!(cpu.run(), StopReason::Halted);
+        cpu
+    }
+
+    #[test]
+    fn conditional_jumps_compare_their_value_with_acc() {
+        let jumps = [
+            (JEQ_REG, JEQ_LIT, [false, true, false]),
+            (JNE_REG, JNE_LIT, [true, false, true]),
+            (JLT_REG, JLT_LIT, [true, false, false]),
+            (JGT_REG, JGT_LIT, [false, false, true]),
+            (JLE_REG, JLE_LIT, [true, true, false]),
+            (JGE_REG, JGE_LIT, [false, true, true]),
+        ];
+        for (register, literal, taken) in jumps {
+            for (value, taken) in [4, 5, 6].into_iter().zip(taken) {
+                // The jump skips a HLT to set r2
+                for jump in [vec![register, R1], vec![literal, 0x00, value]] {
+                    let target = 8 + jump.len() as u8 + 3;
+                    let cpu = run(&[
+                        &[MOV_LIT_REG, 0x00, value, R1],
+                        &[MOV_LIT_REG, 0x00, 0x05, ACC],
+                        &jump,
+                        &[0x00, target],
+                        &[HLT],
+                        &[MOV_LIT_REG, 0x00, 0x01, R2],
+                    ]);
+                    assert_eq!(
+                        cpu.get_register("r2") == 1,
+                        taken,
+                        "{:02X} {}",
+                        jump[0],
+                        value
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn xor_leaves_its_result_in_acc() {
+        let cpu = run(&[
+            &[MOV_LIT_REG, 0xF0, 0xF0, R1],
+            &[MOV_LIT_REG, 0xFF, 0x00, R2],
+            &[XOR_REG_REG, R1, R2],
+            &[MOV_REG_REG, ACC, R3],
+            &[XOR_REG_LIT, R1, 0x0F, 0x0F],
+        ]);
+        assert_eq!(cpu.get_register("r3"), 0x0FF0);
+        assert_eq!(cpu.get_register("acc"), 0xFFFF);
+    }
+
+    #[test]
+    fn rotates_move_bits_around() {
+        let cpu = run(&[
+            &[MOV_LIT_REG, 0x80, 0x01, R1],
+            &[ROL_REG_LIT, R1, 0x00, 0x01],
+            &[MOV_LIT_REG, 0x00, 0x01, R2],
+            &[ROR_REG_LIT, R2, 0x00, 0x01],
+            // Counts are taken modulo 16
+            &[MOV_LIT_REG, 0x00, 0x14, R8],
+            &[MOV_LIT_REG, 0x12, 0x34, R3],
+            &[ROL_REG_REG, R3, R8],
+            &[MOV_LIT_REG, 0x12, 0x34, R4],
+            &[ROR_REG_REG, R4, R8],
+        ]);
+        assert_eq!(cpu.get_register("r1"), 0x0003);
+        assert_eq!(cpu.get_register("r2"), 0x8000);
+        assert_eq!(cpu.get_register("r3"), 0x2341);
+        assert_eq!(cpu.get_register("r4"), 0x4123);
+    }
+
+    #[test]
+    fn bit_instructions_test_set_and_clear_bits() {
+        let cpu = run(&[
+            &[MOV_LIT_REG, 0x00, 0x10, R1],
+            &[MOV_LIT_REG, 0x00, 0x03, R2],
+            &[TST_REG_LIT, R1, 0x00, 0x04],
+            &[MOV_REG_REG, ACC, R3],
+            &[TST_REG_REG, R1, R2],
+            &[MOV_REG_REG, ACC, R4],
+            &[SET_REG_LIT, R1, 0x00, 0x00],
+            &[SET_REG_REG, R1, R2],
+            &[MOV_REG_REG, R1, R5],
+            &[CLR_REG_LIT, R1, 0x00, 0x14],
+            &[CLR_REG_REG, R1, R2],
+        ]);
+        assert_eq!(cpu.get_register("r3"), 1);
+        assert_eq!(cpu.get_register("r4"), 0);
+        assert_eq!(cpu.get_register("r5"), 0x0019);
+        assert_eq!(cpu.get_register("r1"), 0x0001);
+    }
+
+    #[test]
+    fn cnt_counts_bits_and_swp_swaps_bytes() {
+        let cpu = run(&[
+            &[MOV_LIT_REG, 0xF0, 0x0F, R1],
+            &[CNT_REG, R1],
+            &[SWP_REG, R1],
+        ]);
+        assert_eq!(cpu.get_register("acc"), 8);
+        assert_eq!(cpu.get_register("r1"), 0x0FF0);
+    }
+
+    #[test]
+    fn byte_moves_zero_or_sign_extend() {
+        let cpu = run(&[
+            &[MOV8_LIT_MEM, 0x80, 0x08, 0x00],
+            &[MOV_LIT_REG, 0x12, 0x34, R1],
+            &[MOV8_REG_MEM, R1, 0x08, 0x01],
+            &[MOV8_MEM_REG, 0x08, 0x00, R2],
+            &[MOV8S_MEM_REG, 0x08, 0x00, R3],
+            &[MOV8S_MEM_REG, 0x08, 0x01, R8],
+            &[MOV_LIT_REG, 0x08, 0x00, R4],
+            &[MOV8_REG_PTR_REG, R4, R5],
+            &[MOV8S_REG_PTR_REG, R4, R6],
+            &[MOV_LIT_REG, 0x08, 0x02, R7],
+            &[MOV8_REG_REG_PTR, R1, R7],
+        ]);
+        let bytes: Vec<u8> = (0x0800..0x0804)
+            .map(|address| cpu.device_mapper().get_byte(address))
+            .collect();
+        assert_eq!(bytes, [0x80, 0x34, 0x34, 0x00]);
+        assert_eq!(cpu.get_register("r2"), 0x0080);
+        assert_eq!(cpu.get_register("r3"), 0xFF80);
+        assert_eq!(cpu.get_register("r8"), 0x0034);
+        assert_eq!(cpu.get_register("r5"), 0x0080);
+        assert_eq!(cpu.get_register("r6"), 0xFF80);
+    }
+
+    #[test]
+    fn addressing_modes_move_through_memory() {
+        let cpu = run(&[
+            &[MOV_LIT_REG, 0x08, 0x00, R1],
+            &[MOV_LIT_REG, 0x12, 0x34, R2],
+            &[MOV_REG_ADR, R2, ADR_POST_INC | R1],
+            &[MOV_LIT_REG, 0xAB, 0xCD, R2],
+            &[MOV_REG_ADR, R2, ADR_POST_INC | R1],
+            &[MOV_ADR_REG, ADR_PRE_DEC | R1, R3],
+            &[MOV_ADR_REG, ADR_PRE_DEC | R1, R4],
+            &[MOV_ADR_REG, ADR_INDIRECT | R1, R5],
+            &[MOV_ADR_REG, ADR_OFFSET | R1, 0x00, 0x02, R6],
+            &[
+                MOV_ADR_REG,
+                ADR_OFFSET | ADR_BYTE | ADR_SIGNED | R1,
+                0x00,
+                0x02,
+                R7,
+            ],
+            &[MOV_ADR_REG, ADR_POST_INC | ADR_BYTE | R1, R8],
+        ]);
+        assert_eq!(cpu.get_register("r3"), 0xABCD);
+        assert_eq!(cpu.get_register("r4"), 0x1234);
+        assert_eq!(cpu.get_register("r5"), 0x1234);
+        assert_eq!(cpu.get_register("r6"), 0xABCD);
+        assert_eq!(cpu.get_register("r7"), 0xFFAB);
+        assert_eq!(cpu.get_register("r8"), 0x0012);
+        assert_eq!(cpu.get_register("r1"), 0x0801);
     }
 }
@@ -1,7 +1,68 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
 // Device types
 pub enum DeviceType {
     Memory,
+    Rom,
     Stdout,
+    // Counts cycles in a 16-bit counter at address 0 in the byte order of its
+    // region, writable to reset it
+    Timer,
+    // Status at address 0, reading address 1 takes the next key of the input
+    Keyboard,
+    // Like the keyboard, writing address 1 sends a byte to the output
+    Serial,
+}
+
+// Status bits of keyboard and serial devices
+pub const STATUS_READY: u8 = 0x01;
+pub const STATUS_CLOSED: u8 = 0x02;
+
+// Bytes passed between the host and a keyboard or serial device
+#[derive(Default)]
+pub struct Stream {
+    pub bytes: VecDeque<u8>,
+    // No more bytes will be added
+    pub closed: bool,
+}
+
+// Stream shared by the host and a device
+pub type SharedStream = Arc<Mutex<Stream>>;
+
+// Byte orders
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+// Byte order implementation
+impl Endian {
+    // Split a value into bytes in this byte order
+    pub fn split(self, value: u16) -> [u8; 2] {
+        match self {
+            Endian::Big => value.to_be_bytes(),
+            Endian::Little => value.to_le_bytes(),
+        }
+    }
+
+    // Join bytes in this byte order into a value
+    pub fn join(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endian::Big => u16::from_be_bytes(bytes),
+            Endian::Little => u16::from_le_bytes(bytes),
+        }
+    }
+}
+
+// Bus widths
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BusWidth {
+    // 16-bit accesses are split into two byte accesses
+    Byte,
+    // 16-bit accesses are handed to the device as one word access
+    Word,
 }
 
 // Stdout codes
@@ -12,8 +73,13 @@ pub const STDOUT_REGULAR: u8 = 0xF2;
 // Memory class
 pub struct Device {
     buffer: Vec<u8>,
-    pub length: u16,
+    pub length: usize,
     device_type: DeviceType,
+    pub bus_width: BusWidth,
+    bank_size: usize,
+    bank: usize,
+    input: SharedStream,
+    output: Option<SharedStream>,
 }
 
 // Memory implementation
@@ -21,16 +87,104 @@ impl Device {
     pub fn new(length: usize, device_type: DeviceType) -> Self {
         Self {
             buffer: vec![0x00; length],
-            length: length as u16,
+            length,
             device_type,
+            bus_width: BusWidth::Byte,
+            bank_size: 0,
+            bank: 0,
+            input: SharedStream::default(),
+            output: None,
         }
     }
 
+    // Create a device holding an image, like a ROM
+    pub fn from_image(image: Vec<u8>, device_type: DeviceType) -> Self {
+        Self {
+            length: image.len(),
+            buffer: image,
+            device_type,
+            bus_width: BusWidth::Byte,
+            bank_size: 0,
+            bank: 0,
+            input: SharedStream::default(),
+            output: None,
+        }
+    }
+
+    // Read the input of a keyboard or serial device from a stream
+    pub fn with_input(mut self, input: SharedStream) -> Self {
+        self.input = input;
+        self
+    }
+
+    // Write the output of a serial device to a stream, it is dropped without one
+    pub fn with_output(mut self, output: SharedStream) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    // Split the buffer in banks of bank_size bytes
+    //
+    // The device then shows one bank at a time as a window at addresses
+    // 0..bank_size. The byte right after the window is the bank register,
+    // writing a bank number to it switches the bank shown in the window.
+    pub fn with_banks(mut self, bank_size: usize) -> Self {
+        self.bank_size = bank_size;
+        self.length = bank_size + 1;
+        self
+    }
+
+    // Number of banks of a banked device
+    pub fn bank_count(&self) -> usize {
+        if self.bank_size == 0 {
+            return 1;
+        }
+
+        self.buffer.len().div_ceil(self.bank_size).max(1)
+    }
+
+    // Switch the bank shown in the window
+    pub fn set_bank(&mut self, bank: usize) {
+        self.bank = bank % self.bank_count();
+    }
+
+    // Check if the address is the bank register of a banked device
+    fn is_bank_register(&self, address: usize) -> bool {
+        self.bank_size != 0 && address == self.bank_size
+    }
+
+    // Translate a device address to a buffer address
+    fn buffer_address(&self, address: usize) -> usize {
+        self.bank * self.bank_size + address
+    }
+
+    // Check if the CPU may write to the device
+    pub fn is_writable(&self, address: usize) -> bool {
+        !matches!(self.device_type, DeviceType::Rom) || self.is_bank_register(address)
+    }
+
+    // Set the bus width of the device
+    pub fn with_bus_width(mut self, bus_width: BusWidth) -> Self {
+        self.bus_width = bus_width;
+        self
+    }
+
     // Write a byte to device
     pub fn set_byte(&mut self, data: u8, address: usize) {
         match self.device_type {
-            DeviceType::Memory => {
-                self.buffer[address] = data;
+            // The host can still write ROM, the CPU is stopped by the DeviceMapper
+            DeviceType::Memory | DeviceType::Rom | DeviceType::Timer => {
+                // Switch bank
+                if self.is_bank_register(address) {
+                    self.set_bank(data as usize);
+                    return;
+                }
+
+                // Writes past the end of a partial last bank are dropped
+                let buffer_address = self.buffer_address(address);
+                if let Some(byte) = self.buffer.get_mut(buffer_address) {
+                    *byte = data;
+                }
             }
 
             DeviceType::Stdout => {
@@ -53,35 +207,154 @@ impl Device {
                     _ => (),
                 }
 
-                let character_raw = data & 0xFF;
-
                 let x = ((address % 16) * 2) + 1;
                 let y = address / 16;
                 self.move_to(x, y);
 
-                let character = String::from_utf16(&vec![character_raw as u16]).unwrap();
+                let character = String::from_utf16(&[data as u16]).unwrap();
                 print!("{}", character);
             }
+
+            // Send a byte
+            DeviceType::Serial if address == 1 => {
+                if let Some(output) = &self.output {
+                    output.lock().unwrap().bytes.push_back(data);
+                }
+            }
+            DeviceType::Keyboard | DeviceType::Serial => {}
         }
     }
 
     // Read a byte from device
     pub fn get_byte(&self, address: usize) -> u8 {
         match self.device_type {
-            DeviceType::Memory => self.buffer[address],
+            DeviceType::Memory | DeviceType::Rom | DeviceType::Timer => {
+                // Read bank register
+                if self.is_bank_register(address) {
+                    return self.bank as u8;
+                }
+
+                // Reads past the end of a partial last bank read as 0x00
+                let buffer_address = self.buffer_address(address);
+                self.buffer.get(buffer_address).copied().unwrap_or(0x00)
+            }
 
             DeviceType::Stdout => 0x00,
+
+            // Status, or the next byte of the input
+            DeviceType::Keyboard | DeviceType::Serial => {
+                let mut input = self.input.lock().unwrap();
+                match address {
+                    0 => {
+                        let ready = !input.bytes.is_empty() as u8 * STATUS_READY;
+                        let closed = input.closed as u8 * STATUS_CLOSED;
+                        ready | closed
+                    }
+                    1 => input.bytes.pop_front().unwrap_or(0x00),
+                    _ => 0x00,
+                }
+            }
         }
     }
 
-    // Move cursor to x, y on stdout
-    pub fn move_to(&self, x: usize, y: usize) {
+    // Write a word to device in one access
+    pub fn set_word(&mut self, data: u16, address: usize, endian: Endian) {
         match self.device_type {
+            DeviceType::Memory
+            | DeviceType::Rom
+            | DeviceType::Timer
+            | DeviceType::Keyboard
+            | DeviceType::Serial => {
+                let bytes = endian.split(data);
+                self.set_byte(bytes[0], address);
+                self.set_byte(bytes[1], address + 1);
+            }
+
             DeviceType::Stdout => {
-                print!("\x1B[{};{}H", y, x);
+                // Control codes and ASCII keep their byte behaviour
+                if data <= 0xFF {
+                    self.set_byte(data as u8, address);
+                    return;
+                }
+
+                // Print the word as a single UTF-16 code unit
+                let x = ((address % 16) * 2) + 1;
+                let y = address / 16;
+                self.move_to(x, y);
+
+                let character = String::from_utf16_lossy(&[data]);
+                print!("{}", character);
+            }
+        }
+    }
+
+    // Read a word from device in one access
+    pub fn get_word(&self, address: usize, endian: Endian) -> u16 {
+        match self.device_type {
+            DeviceType::Memory
+            | DeviceType::Rom
+            | DeviceType::Timer
+            | DeviceType::Keyboard
+            | DeviceType::Serial => {
+                endian.join([self.get_byte(address), self.get_byte(address + 1)])
             }
 
-            _ => {}
+            DeviceType::Stdout => 0x0000,
+        }
+    }
+
+    // Let cycles pass on the device, counters are in the byte order of endian
+    pub fn tick(&mut self, cycles: u64, endian: Endian) {
+        // A timer too small for its counter never counts
+        if let (DeviceType::Timer, [first, second, ..]) = (&self.device_type, &mut self.buffer[..])
+        {
+            let counter = endian.join([*first, *second]).wrapping_add(cycles as u16);
+            [*first, *second] = endian.split(counter);
+        }
+    }
+
+    // Move cursor to x, y on stdout
+    pub fn move_to(&self, x: usize, y: usize) {
+        if let DeviceType::Stdout = self.device_type {
+            print!("\x1B[{};{}H", y, x);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_counts_cycles() {
+        let mut timer = Device::new(2, DeviceType::Timer);
+        timer.tick(0x0102, Endian::Big);
+        timer.tick(0xFFFF, Endian::Big);
+        assert_eq!(timer.get_word(0, Endian::Big), 0x0101);
+    }
+
+    #[test]
+    fn timer_counts_in_the_byte_order_of_its_region() {
+        let mut timer = Device::new(2, DeviceType::Timer);
+        timer.tick(0x0102, Endian::Little);
+        timer.tick(0x00FF, Endian::Little);
+        assert_eq!([timer.get_byte(0), timer.get_byte(1)], [0x01, 0x02]);
+        assert_eq!(timer.get_word(0, Endian::Little), 0x0201);
+    }
+
+    #[test]
+    fn serial_sends_to_its_output() {
+        let output = SharedStream::default();
+        let mut serial = Device::new(2, DeviceType::Serial).with_output(output.clone());
+        serial.set_byte(b'A', 1);
+        serial.set_byte(b'B', 0);
+        assert_eq!(output.lock().unwrap().bytes, [b'A']);
+    }
+
+    #[test]
+    fn small_timer_does_not_count() {
+        let mut timer = Device::new(1, DeviceType::Timer);
+        timer.tick(4, Endian::Big);
+        assert_eq!(timer.get_byte(0), 0x00);
+    }
+}
@@ -1,4 +1,47 @@
-use crate::device::Device;
+use crate::device::{BusWidth, Device, Endian};
+use crate::fault::{Access, Fault};
+use std::cell::Cell;
+
+// Region permissions
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+// Permissions implementation
+impl Permissions {
+    pub const ALL: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+    pub const READ_WRITE: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    pub const READ_EXECUTE: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    pub const READ_ONLY: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: false,
+    };
+
+    // Check if the access is allowed
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
 
 // Region class
 pub struct Region {
@@ -6,11 +49,74 @@ pub struct Region {
     start: u16,
     end: u16,
     remap: bool,
+    endian: Option<Endian>,
+    permissions: Permissions,
+    supervisor_only: bool,
+    wait_states: u64,
+}
+
+// Region implementation
+impl Region {
+    // Set what the CPU is allowed to do with the region
+    pub fn set_permissions(&mut self, permissions: Permissions) -> &mut Self {
+        self.permissions = permissions;
+        self
+    }
+
+    // Only allow the CPU to access the region in supervisor mode
+    pub fn set_supervisor_only(&mut self, supervisor_only: bool) -> &mut Self {
+        self.supervisor_only = supervisor_only;
+        self
+    }
+
+    // Set the extra cycles every CPU access to the region takes
+    pub fn set_wait_states(&mut self, wait_states: u64) -> &mut Self {
+        self.wait_states = wait_states;
+        self
+    }
+
+    // Check if the CPU may access an address in the region
+    fn allows(&self, access: Access, address: u16, supervisor: bool) -> bool {
+        (supervisor || !self.supervisor_only)
+            && self.permissions.allows(access)
+            && (access != Access::Write || self.device.is_writable(self.device_address(address)))
+    }
+
+    // Override the byte order of the DeviceMapper for this region
+    pub fn set_endian(&mut self, endian: Endian) -> &mut Self {
+        self.endian = Some(endian);
+        self
+    }
+
+    // Check if address is inside the region
+    fn contains(&self, address: u16) -> bool {
+        address >= self.start && address <= self.end
+    }
+
+    // Translate an address to an address on the device
+    fn device_address(&self, address: u16) -> usize {
+        // Remap the address if needed
+        if self.remap {
+            (address - self.start) as usize
+        } else {
+            address as usize
+        }
+    }
 }
 
 // DeviceMapper class
 pub struct DeviceMapper {
     regions: Vec<Region>,
+    endian: Endian,
+    supervisor: bool,
+    wait_cycles: Cell<u64>,
+}
+
+// Default DeviceMapper
+impl Default for DeviceMapper {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // DeviceMapper implementation
@@ -18,11 +124,43 @@ impl DeviceMapper {
     pub fn new() -> Self {
         Self {
             regions: Vec::new(),
+            endian: Endian::Big,
+            supervisor: true,
+            wait_cycles: Cell::new(0),
+        }
+    }
+
+    // Take the wait states of the CPU accesses since the last call
+    pub fn take_wait_cycles(&self) -> u64 {
+        self.wait_cycles.take()
+    }
+
+    // Let devices know cycles passed
+    pub fn tick(&mut self, cycles: u64) {
+        for region in self.regions.iter_mut() {
+            region
+                .device
+                .tick(cycles, region.endian.unwrap_or(self.endian));
         }
     }
 
+    // Set the mode of CPU accesses
+    pub fn set_supervisor(&mut self, supervisor: bool) {
+        self.supervisor = supervisor;
+    }
+
+    // Check if CPU accesses are made in supervisor mode
+    pub fn is_supervisor(&self) -> bool {
+        self.supervisor
+    }
+
+    // Set the byte order used by regions without an override
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+    }
+
     // Map a memory to a region
-    pub fn map(&mut self, device: Device, start: u16, end: u16, remap: bool) {
+    pub fn map(&mut self, device: Device, start: u16, end: u16, remap: bool) -> &mut Region {
         self.regions.insert(
             0,
             Region {
@@ -30,60 +168,22 @@ impl DeviceMapper {
                 start,
                 end,
                 remap,
+                endian: None,
+                permissions: Permissions::ALL,
+                supervisor_only: false,
+                wait_states: 0,
             },
         );
+        &mut self.regions[0]
     }
 
     // TODO: Write a function to remove regions from the memory-mapper
 
-    // Write a byte
-    pub fn set_byte(&mut self, data: u8, address: u16) {
-        // Find address in region
-        for region in self.regions.iter_mut() {
-            if address >= region.start && address <= region.end {
-                // Remap the address if needed
-                let final_address = if region.remap {
-                    address - region.start
-                } else {
-                    address
-                };
-
-                // Write byte
-                region.device.set_byte(data, final_address as usize);
-                return;
-            }
-        }
-
-        // If address out of bounds, panic
-        panic!("Index out of bounds");
-    }
-
-    // Read a byte
-    pub fn get_byte(&self, address: u16) -> u8 {
-        // Find address is region
-        for region in self.regions.iter() {
-            if address >= region.start && address <= region.end {
-                // Remap the address if needed
-                let final_address = if region.remap {
-                    address - region.start
-                } else {
-                    address
-                };
-
-                // Read byte
-                return region.device.get_byte(final_address as usize);
-            }
-        }
-
-        // If address out of bounds, panic
-        panic!("Index out of bounds");
-    }
-
     // Find region by address
     pub fn mut_find_region(&mut self, address: u16) -> &mut Region {
         // Find address in region
         for region in self.regions.iter_mut() {
-            if address >= region.start && address <= region.end {
+            if region.contains(address) {
                 return region;
             }
         }
@@ -96,7 +196,7 @@ impl DeviceMapper {
     pub fn find_region(&self, address: u16) -> &Region {
         // Find address in region
         for region in self.regions.iter() {
-            if address >= region.start && address <= region.end {
+            if region.contains(address) {
                 return region;
             }
         }
@@ -105,93 +205,292 @@ impl DeviceMapper {
         panic!("Address 0x{:04X} not found in any region", address);
     }
 
-    // Read bytes
-    pub fn get_uint_16(&self, address: u16) -> u16 {
+    // Check if an address is in a region
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.regions.iter().any(|region| region.contains(address))
+    }
+
+    // Host accesses
+
+    // Write a byte, bypassing the region permissions
+    pub fn set_byte(&mut self, data: u8, address: u16) {
+        let region = self.mut_find_region(address);
+        let final_address = region.device_address(address);
+        region.device.set_byte(data, final_address);
+    }
+
+    // Read a byte, bypassing the region permissions
+    pub fn get_byte(&self, address: u16) -> u8 {
         let region = self.find_region(address);
+        region.device.get_byte(region.device_address(address))
+    }
 
-        // Remap the address if needed
-        let final_address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+    // CPU accesses
+    //
+    // Every byte of a multi-byte access is routed to the region that owns
+    // it and checked against that region's permissions. Addresses wrap
+    // around from 0xFFFF to 0x0000. The only exception is a word device that
+    // holds both bytes, it gets a single word access instead.
+
+    // Find the region a CPU access goes to and check its permissions
+    fn checked_region(&self, address: u16, access: Access) -> Result<usize, Fault> {
+        // Find address in region
+        let index = self
+            .regions
+            .iter()
+            .position(|region| region.contains(address))
+            .ok_or(Fault::Unmapped { address, access })?;
+
+        // Check permissions
+        if !self.regions[index].allows(access, address, self.supervisor) {
+            return Err(Fault::Protection { address, access });
+        }
+
+        Ok(index)
+    }
+
+    // Byte order of 16-bit accesses at address
+    pub fn endian(&self, address: u16) -> Endian {
+        self.regions
+            .iter()
+            .find(|region| region.contains(address))
+            .and_then(|region| region.endian)
+            .unwrap_or(self.endian)
+    }
+
+    // Count the wait states of an access to a region
+    fn wait(&self, index: usize) {
+        let wait_states = self.regions[index].wait_states;
+        self.wait_cycles.set(self.wait_cycles.get() + wait_states);
+    }
+
+    // Check if a 16-bit access at address can be one word access
+    fn is_word_access(&self, index: usize, address: u16) -> bool {
+        let region = &self.regions[index];
+        region.device.bus_width == BusWidth::Word
+            && address != 0xFFFF
+            && region.contains(address + 1)
+    }
+
+    // Read a byte for the given access
+    pub fn read_uint_8(&self, address: u16, access: Access) -> Result<u8, Fault> {
+        let index = self.checked_region(address, access)?;
+        let region = &self.regions[index];
+        self.wait(index);
+        Ok(region.device.get_byte(region.device_address(address)))
+    }
+
+    // Read bytes for the given access
+    pub fn read_uint_16(&self, address: u16, access: Access) -> Result<u16, Fault> {
+        let index = self.checked_region(address, access)?;
+        let region = &self.regions[index];
+        let endian = region.endian.unwrap_or(self.endian);
+
+        // Read word
+        if self.is_word_access(index, address) {
+            self.wait(index);
+            return Ok(region
+                .device
+                .get_word(region.device_address(address), endian));
+        }
 
         // Read bytes
         let bytes = [
-            region.device.get_byte(final_address as usize),
-            region.device.get_byte((final_address + 1) as usize),
+            self.read_uint_8(address, access)?,
+            self.read_uint_8(address.wrapping_add(1), access)?,
         ];
-        u16::from_be_bytes(bytes)
+        Ok(endian.join(bytes))
     }
 
     // Read a byte
-    pub fn get_uint_8(&self, address: u16) -> u8 {
-        let region = self.find_region(address);
-
-        // Remap the address if needed
-        let final_address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+    pub fn get_uint_8(&self, address: u16) -> Result<u8, Fault> {
+        self.read_uint_8(address, Access::Read)
+    }
 
-        // Read byte
-        region.device.get_byte(final_address as usize)
+    // Read bytes
+    pub fn get_uint_16(&self, address: u16) -> Result<u16, Fault> {
+        self.read_uint_16(address, Access::Read)
     }
 
-    // Write bytes
-    pub fn set_uint_16(&mut self, address: u16, value: u16) {
-        let region = self.mut_find_region(address);
+    // Read bytes for the MMU, skipping the mode and permission checks of the
+    // region so page tables can live in supervisor-only memory
+    pub fn privileged_uint_16(&self, address: u16) -> Result<u16, Fault> {
+        let next = address.wrapping_add(1);
+        for address in [address, next] {
+            if !self.is_mapped(address) {
+                return Err(Fault::Unmapped {
+                    address,
+                    access: Access::Read,
+                });
+            }
+        }
+        let bytes = [self.get_byte(address), self.get_byte(next)];
+        Ok(self.endian(address).join(bytes))
+    }
 
-        // Remap the address if needed
-        let final_address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+    // Fetch an instruction byte
+    pub fn fetch_uint_8(&self, address: u16) -> Result<u8, Fault> {
+        self.read_uint_8(address, Access::Execute)
+    }
 
-        // Write bytes
-        let bytes = value.to_be_bytes();
-        region.device.set_byte(bytes[0], final_address as usize);
-        region
-            .device
-            .set_byte(bytes[1], (final_address + 1) as usize);
+    // Fetch instruction bytes
+    pub fn fetch_uint_16(&self, address: u16) -> Result<u16, Fault> {
+        self.read_uint_16(address, Access::Execute)
     }
 
     // Write a byte
-    pub fn set_uint_8(&mut self, address: u16, value: u8) {
-        let region = self.mut_find_region(address);
+    pub fn set_uint_8(&mut self, address: u16, value: u8) -> Result<(), Fault> {
+        let index = self.checked_region(address, Access::Write)?;
+        self.wait(index);
+        let region = &mut self.regions[index];
+        let final_address = region.device_address(address);
+        region.device.set_byte(value, final_address);
+        Ok(())
+    }
 
-        // Remap the address if needed
-        let final_address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
+    // Write bytes
+    pub fn set_uint_16(&mut self, address: u16, value: u16) -> Result<(), Fault> {
+        let index = self.checked_region(address, Access::Write)?;
+        let endian = self.regions[index].endian.unwrap_or(self.endian);
 
-        // Write byte
-        region.device.set_byte(value, final_address as usize);
+        // Write word
+        if self.is_word_access(index, address) {
+            self.wait(index);
+            let region = &mut self.regions[index];
+            let final_address = region.device_address(address);
+            region.device.set_word(value, final_address, endian);
+            return Ok(());
+        }
+
+        // Write bytes, check both before writing so a fault leaves memory untouched
+        self.checked_region(address.wrapping_add(1), Access::Write)?;
+        let bytes = endian.split(value);
+        self.set_uint_8(address, bytes[0])?;
+        self.set_uint_8(address.wrapping_add(1), bytes[1])
     }
 
-    // Print/read bytes in given address range
+    // Print/read bytes in given address range, unmapped bytes show as ----
     pub fn view_memory(&self, address: u16, size: usize) {
-        let region = self.find_region(address);
-
-        // Remap the address if needed
-        let final_address = if region.remap {
-            address - region.start
-        } else {
-            address
-        };
-
         // Print and read bytes
         print!("0x{:04X}: ", address);
         for i in 0..size {
-            print!(
-                "0x{:02X} ",
-                region.device.get_byte(final_address as usize + i)
+            let address = address.wrapping_add(i as u16);
+            match self.is_mapped(address) {
+                true => print!("0x{:02X} ", self.get_byte(address)),
+                false => print!("---- "),
+            }
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceType;
+
+    // Memory of 256 bytes in each page given
+    fn machine(pages: &[u16]) -> DeviceMapper {
+        let mut mm = DeviceMapper::new();
+        for page in pages {
+            mm.map(
+                Device::new(0x0100, DeviceType::Memory),
+                page << 8,
+                page << 8 | 0x00FF,
+                true,
             );
         }
-        println!("");
+        mm
+    }
+
+    #[test]
+    fn words_pass_the_end_of_a_region() {
+        let mut mm = machine(&[0x00, 0x01]);
+        mm.set_uint_16(0x00FF, 0x1234).unwrap();
+        assert_eq!([mm.get_byte(0x00FF), mm.get_byte(0x0100)], [0x12, 0x34]);
+        assert_eq!(mm.get_uint_16(0x00FF), Ok(0x1234));
+    }
+
+    #[test]
+    fn words_wrap_around_at_the_end_of_memory() {
+        let mut mm = machine(&[0x00, 0xFF]);
+        mm.set_uint_16(0xFFFF, 0xABCD).unwrap();
+        assert_eq!([mm.get_byte(0xFFFF), mm.get_byte(0x0000)], [0xAB, 0xCD]);
+        assert_eq!(mm.get_uint_16(0xFFFF), Ok(0xABCD));
+    }
+
+    #[test]
+    fn words_half_in_unmapped_memory_fault_without_writing() {
+        let mut mm = machine(&[0x00]);
+        assert_eq!(
+            mm.set_uint_16(0x00FF, 0x1234),
+            Err(Fault::Unmapped {
+                address: 0x0100,
+                access: Access::Write
+            })
+        );
+        assert_eq!(mm.get_byte(0x00FF), 0x00);
+        assert_eq!(
+            mm.get_uint_16(0xFFFF),
+            Err(Fault::Unmapped {
+                address: 0xFFFF,
+                access: Access::Read
+            })
+        );
+    }
+
+    #[test]
+    fn regions_override_the_byte_order() {
+        let mut mm = machine(&[0x01]);
+        mm.set_endian(Endian::Big);
+        mm.map(
+            Device::new(0x0100, DeviceType::Memory),
+            0x0000,
+            0x00FF,
+            true,
+        )
+        .set_endian(Endian::Little);
+
+        mm.set_uint_16(0x0010, 0x1234).unwrap();
+        mm.set_uint_16(0x0110, 0x1234).unwrap();
+        assert_eq!([mm.get_byte(0x0010), mm.get_byte(0x0011)], [0x34, 0x12]);
+        assert_eq!([mm.get_byte(0x0110), mm.get_byte(0x0111)], [0x12, 0x34]);
+        assert_eq!(mm.get_uint_16(0x0010), Ok(0x1234));
+
+        // A word across regions is in the byte order of its first byte
+        mm.set_uint_16(0x00FF, 0xABCD).unwrap();
+        assert_eq!([mm.get_byte(0x00FF), mm.get_byte(0x0100)], [0xCD, 0xAB]);
+        assert_eq!(mm.endian(0x00FF), Endian::Little);
+        assert_eq!(mm.get_uint_16(0x00FF), Ok(0xABCD));
+    }
+
+    #[test]
+    fn word_devices_take_one_access() {
+        let mut mm = DeviceMapper::new();
+        mm.map(
+            Device::new(0x0100, DeviceType::Memory).with_bus_width(BusWidth::Word),
+            0x0000,
+            0x00FF,
+            true,
+        )
+        .set_wait_states(3);
+        mm.map(
+            Device::new(0x0100, DeviceType::Memory),
+            0x0100,
+            0x01FF,
+            true,
+        )
+        .set_wait_states(3);
+
+        mm.set_uint_16(0x0010, 0x1234).unwrap();
+        assert_eq!(mm.take_wait_cycles(), 3);
+        assert_eq!(mm.get_uint_16(0x0010), Ok(0x1234));
+        assert_eq!(mm.take_wait_cycles(), 3);
+
+        // Byte devices, and words leaving a word device, take two
+        assert_eq!(mm.get_uint_16(0x0110), Ok(0x0000));
+        assert_eq!(mm.take_wait_cycles(), 6);
+        assert_eq!(mm.get_uint_16(0x00FF), Ok(0x0000));
+        assert_eq!(mm.take_wait_cycles(), 6);
     }
 }
@@ -0,0 +1,88 @@
+// Entries of the text formats
+//
+// Object files, memory maps, symbol files and machine descriptions share one
+// layout: one entry per line, fields split by whitespace, `;` starts a
+// comment. Numbers are `$` hex or decimal.
+
+// Entry on a line
+pub struct Entry<'a> {
+    // Line number, from 1
+    pub line: usize,
+    // Line without its comment
+    pub text: &'a str,
+    pub fields: Vec<&'a str>,
+}
+
+// Entry implementation
+impl<'a> Entry<'a> {
+    // Text from a field to the end of the line, for a last field that may
+    // hold spaces like a file name
+    pub fn rest(&self, field: usize) -> &'a str {
+        let mut text = self.text;
+        for _ in 0..field {
+            text = text.trim_start();
+            text = &text[text.find(char::is_whitespace).unwrap_or(text.len())..];
+        }
+        text.trim()
+    }
+
+    // Prefix a message with the line number
+    pub fn error(&self, message: &str) -> String {
+        format!("{}: {}", self.line, message)
+    }
+
+    // Error for an entry the format doesn't know
+    pub fn invalid(&self) -> String {
+        self.error(&format!("invalid entry \"{}\"", self.text))
+    }
+}
+
+// Entries of a text, skipping empty lines and comments
+pub fn entries(text: &str) -> impl Iterator<Item = Entry<'_>> {
+    text.lines().enumerate().filter_map(|(number, line)| {
+        let text = line.split(';').next().unwrap().trim();
+        (!text.is_empty()).then(|| Entry {
+            line: number + 1,
+            text,
+            fields: text.split_whitespace().collect(),
+        })
+    })
+}
+
+// Parse a `$` hex or decimal number
+pub fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix('$') {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Parse a `$` hex or decimal number of 16 bits
+pub fn parse_u16(text: &str) -> Option<u16> {
+    parse_number(text).and_then(|number| u16::try_from(number).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_skip_comments_and_keep_line_numbers() {
+        let text = "; header\n\nregion ram $0000 255 ; low memory\n  place text ram\n";
+        let entries: Vec<Entry> = entries(text).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].fields, ["region", "ram", "$0000", "255"]);
+        assert_eq!(entries[0].rest(2), "$0000 255");
+        assert_eq!(entries[1].error("unknown region"), "4: unknown region");
+        assert_eq!(entries[1].invalid(), "4: invalid entry \"place text ram\"");
+    }
+
+    #[test]
+    fn numbers_are_hex_or_decimal() {
+        assert_eq!(parse_number("$1F"), Some(0x1F));
+        assert_eq!(parse_number("31"), Some(31));
+        assert_eq!(parse_number("1F"), None);
+        assert_eq!(parse_u16("$FFFF"), Some(0xFFFF));
+        assert_eq!(parse_u16("$10000"), None);
+    }
+}
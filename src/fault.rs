@@ -0,0 +1,124 @@
+use std::fmt;
+
+// Memory access kinds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+// Faults raised while executing an instruction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fault {
+    // Address not mapped to any region
+    Unmapped { address: u16, access: Access },
+    // Access not allowed on the region
+    Protection { address: u16, access: Access },
+    // Virtual address not mapped or access not allowed by the page table
+    Page { address: u16, access: Access },
+    // Supervisor instruction, or write to the given supervisor register, in user mode
+    Privilege { register: Option<u8> },
+    // Push below the stack limit (write) or pop above the stack base (read)
+    Stack { address: u16, access: Access },
+    // SYSCALL without a handler, only stops the CPU
+    Syscall { number: u16 },
+    // Opcode of no instruction
+    Instruction { opcode: u8 },
+}
+
+// Fault implementation
+impl Fault {
+    // Exception number of the fault, used as index in the exception vector table
+    pub fn code(&self) -> u16 {
+        match self {
+            Fault::Unmapped { .. } => 0x00,
+            Fault::Protection { .. } => 0x01,
+            Fault::Page { .. } => 0x02,
+            Fault::Privilege { .. } => 0x03,
+            Fault::Syscall { .. } => 0x04,
+            Fault::Stack { .. } => 0x05,
+            Fault::Instruction { .. } => 0x06,
+        }
+    }
+
+    // Address that caused the fault, for privilege faults the register index
+    // or 0xFFFF for an instruction, the number of a syscall or the opcode of
+    // an unknown instruction
+    pub fn address(&self) -> u16 {
+        match self {
+            Fault::Unmapped { address, .. } => *address,
+            Fault::Protection { address, .. } => *address,
+            Fault::Page { address, .. } => *address,
+            Fault::Stack { address, .. } => *address,
+            Fault::Privilege { register } => register.map_or(0xFFFF, |register| register as u16),
+            Fault::Syscall { number } => *number,
+            Fault::Instruction { opcode } => *opcode as u16,
+        }
+    }
+
+    // Access that caused the fault
+    pub fn access(&self) -> Access {
+        match self {
+            Fault::Unmapped { access, .. } => *access,
+            Fault::Protection { access, .. } => *access,
+            Fault::Page { access, .. } => *access,
+            Fault::Stack { access, .. } => *access,
+            Fault::Privilege { register: Some(_) } => Access::Write,
+            Fault::Privilege { register: None } => Access::Execute,
+            Fault::Syscall { .. } | Fault::Instruction { .. } => Access::Execute,
+        }
+    }
+}
+
+// Print faults
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::Unmapped { address, access } => {
+                write!(
+                    f,
+                    "Bus error: {:?} of unmapped address 0x{:04X}",
+                    access, address
+                )
+            }
+            Fault::Protection { address, access } => {
+                write!(
+                    f,
+                    "Protection fault: {:?} of address 0x{:04X}",
+                    access, address
+                )
+            }
+            Fault::Page { address, access } => {
+                write!(
+                    f,
+                    "Page fault: {:?} of virtual address 0x{:04X}",
+                    access, address
+                )
+            }
+            Fault::Privilege {
+                register: Some(register),
+            } => {
+                write!(
+                    f,
+                    "Privilege fault: write to register {} in user mode",
+                    register
+                )
+            }
+            Fault::Privilege { register: None } => {
+                write!(f, "Privilege fault: supervisor instruction in user mode")
+            }
+            Fault::Stack {
+                address,
+                access: Access::Write,
+            } => write!(f, "Stack overflow: push at 0x{:04X}", address),
+            Fault::Stack { address, .. } => {
+                write!(f, "Stack underflow: pop at 0x{:04X}", address)
+            }
+            Fault::Syscall { number } => write!(f, "Syscall 0x{:04X} not handled", number),
+            Fault::Instruction { opcode } => {
+                write!(f, "Illegal instruction: opcode 0x{:02X}", opcode)
+            }
+        }
+    }
+}
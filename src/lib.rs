@@ -0,0 +1,51 @@
+// 16-bit virtual machine
+//
+// The VM of the LowLevelJavaScript series and its toolchain as a library.
+// Build a machine, watch it through hooks and run it:
+//
+//     use six_teen_bit_vm::{MachineBuilder, MachineConfig, StopReason};
+//
+//     let mut cpu = MachineBuilder::new(MachineConfig::default_machine())
+//         .with_program(image)
+//         .build()?;
+//     cpu.on_step(|cpu| println!("ip: {:04X}", cpu.get_register("ip")));
+//     cpu.on_memory_access(|access| println!("{:?}", access));
+//     cpu.on_halt(|cpu| println!("acc: {:04X}", cpu.get_register("acc")));
+//     match cpu.run() {
+//         StopReason::Halted => (),
+//         reason => return Err(reason.to_string()),
+//     }
+//
+// Runs stop with a StopReason. `run_for` limits the number of instructions,
+// breakpoints stop before an address and a StopHandle stops the CPU from
+// another thread; running again continues where it stopped. Hooks are Send,
+// so a CPU can run on a worker thread. With
+// semihosting enabled guests can exit with a status, print on the host and
+// read its files and time, see `semihost`.
+//
+// Opcodes and register numbers for writing machine code by hand are in
+// `opcodes`, the assembler, linker and compiler turn source into images.
+
+pub mod assembler;
+pub mod compiler;
+pub mod cpu;
+pub mod device;
+pub mod device_mapper;
+mod entries;
+pub mod fault;
+pub mod linker;
+pub mod listing;
+pub mod loader;
+pub mod machine;
+pub mod mmu;
+pub mod object;
+pub mod opcodes;
+pub mod profiler;
+pub mod semihost;
+pub mod symbols;
+
+pub use cpu::{MemoryAccess, StopHandle, StopReason, CPU};
+pub use device::{BusWidth, Device, DeviceType, Endian, SharedStream, Stream};
+pub use device_mapper::{DeviceMapper, Permissions, Region};
+pub use fault::{Access, Fault};
+pub use machine::{MachineBuilder, MachineConfig};
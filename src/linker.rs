@@ -0,0 +1,437 @@
+use crate::device::Endian;
+use crate::entries::{entries, parse_u16};
+use crate::machine::MachineConfig;
+use crate::object::{Object, Target};
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+use std::fs;
+
+// Memory maps
+//
+// Tell the linker which address ranges hold memory and which sections go
+// where. One entry per line, numbers are `$` hex or decimal, `;` starts a
+// comment:
+//
+//     region <name> <start> <end>     memory from start to end, inclusive
+//     place <section> <region>        put sections with this name in the region
+//
+// Without a map file the regions are the memory of the machine the program
+// runs on, see MemoryMap::from_machine. Sections without a place entry go in
+// the first region. Sections are laid out one after the other in the order
+// of the objects on the command line, around the sections with a fixed
+// address.
+
+// Region of memory sections can be placed in
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapRegion {
+    pub name: String,
+    pub start: u16,
+    pub end: u16,
+}
+
+// MemoryMap class
+#[derive(Clone, Debug)]
+pub struct MemoryMap {
+    regions: Vec<MapRegion>,
+    placements: HashMap<String, String>,
+}
+
+// Default MemoryMap
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// MemoryMap implementation
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self {
+            regions: Vec::new(),
+            placements: HashMap::new(),
+        }
+    }
+
+    // Memory of the default machine, without the monitor's variables and ROM
+    // so programs can be loaded from the monitor
+    pub fn default_machine() -> Self {
+        let machine = MachineConfig::default_machine();
+        Self::machine_map(&machine, &[(0x2F00, 0x2FFF), (0xF000, 0xFEFF)]).unwrap()
+    }
+
+    // Memory of a machine, the program device without the devices mapped over
+    // it and the stack. Regions are named after the device, the parts after
+    // the first get a number.
+    pub fn from_machine(machine: &MachineConfig) -> Result<Self, String> {
+        Self::machine_map(machine, &[])
+    }
+
+    // Memory of a machine without the reserved address ranges
+    fn machine_map(machine: &MachineConfig, reserved: &[(u16, u16)]) -> Result<Self, String> {
+        let Some(index) = machine.devices.iter().position(|device| device.program) else {
+            return Err(String::from("machine has no program device"));
+        };
+        let program = &machine.devices[index];
+        let mut map = Self::new();
+        map.add_region(&program.name, program.start, program.end);
+        for device in machine.devices[index + 1..].iter() {
+            map.reserve(device.start, device.end);
+        }
+        let (base, limit) = machine.stack;
+        map.reserve(limit, base.saturating_add(1));
+        for (start, end) in reserved.iter() {
+            map.reserve(*start, *end);
+        }
+        for (number, region) in map.regions.iter_mut().enumerate().skip(1) {
+            region.name = format!("{}{}", program.name, number + 1);
+        }
+
+        Ok(map)
+    }
+
+    // Load a memory map file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        Self::parse(&text).map_err(|error| format!("{}:{}", path, error))
+    }
+
+    // Parse the text of a memory map file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = Self::new();
+        for entry in entries(text) {
+            let error = |message: &str| entry.error(message);
+            let number = |text: &str| parse_u16(text).ok_or_else(|| error("invalid number"));
+            match entry.fields[..] {
+                ["region", name, start, end] => {
+                    let (start, end) = (number(start)?, number(end)?);
+                    if end < start {
+                        return Err(error("region ends before it starts"));
+                    }
+                    map.add_region(name, start, end);
+                }
+                ["place", section, region] => {
+                    if !map.regions.iter().any(|other| other.name == region) {
+                        return Err(error(&format!("unknown region {}", region)));
+                    }
+                    map.place(section, region);
+                }
+                _ => return Err(entry.invalid()),
+            }
+        }
+
+        Ok(map)
+    }
+
+    // Add a region
+    pub fn add_region(&mut self, name: &str, start: u16, end: u16) {
+        self.regions.push(MapRegion {
+            name: name.to_string(),
+            start,
+            end,
+        });
+    }
+
+    // Remove an address range from the regions, splitting those it is inside
+    pub fn reserve(&mut self, start: u16, end: u16) {
+        let mut regions = Vec::new();
+        for region in self.regions.drain(..) {
+            if end < region.start || start > region.end {
+                regions.push(region);
+                continue;
+            }
+            if start > region.start {
+                regions.push(MapRegion {
+                    end: start - 1,
+                    ..region.clone()
+                });
+            }
+            if end < region.end {
+                regions.push(MapRegion {
+                    start: end + 1,
+                    ..region
+                });
+            }
+        }
+        self.regions = regions;
+    }
+
+    // Put sections with a name in a region
+    pub fn place(&mut self, section: &str, region: &str) {
+        self.placements
+            .insert(section.to_string(), region.to_string());
+    }
+
+    // Index of the region a section goes in
+    fn region_of(&self, section: &str) -> Result<usize, String> {
+        match self.placements.get(section) {
+            Some(region) => Ok(self
+                .regions
+                .iter()
+                .position(|other| other.name == *region)
+                .unwrap()),
+            None if self.regions.is_empty() => Err("memory map has no regions".to_string()),
+            None => Ok(0),
+        }
+    }
+}
+
+// Linked program, bytes from address 0 up to the last placed byte
+pub struct Image {
+    pub bytes: Vec<u8>,
+    pub symbols: Symbols,
+}
+
+// Link objects into an image
+pub fn link(objects: &[Object], map: &MemoryMap) -> Result<Image, String> {
+    // Relocated fields are written in the byte order of the objects
+    let endian = objects.first().map_or(Endian::Big, |object| object.endian);
+    if objects.iter().any(|object| object.endian != endian) {
+        return Err(String::from("objects have different byte orders"));
+    }
+
+    // Place sections with a fixed address
+    let mut placed: Vec<(u32, u32)> = Vec::new();
+    let mut bases: Vec<HashMap<&str, u16>> = vec![HashMap::new(); objects.len()];
+    for (object, object_bases) in objects.iter().zip(bases.iter_mut()) {
+        for section in object.sections.iter() {
+            let Some(address) = section.address else {
+                continue;
+            };
+            let (start, end) = (address as u32, address as u32 + section.bytes.len() as u32);
+            if end > 0x10000 {
+                return Err(format!(
+                    "section {} runs past the end of memory",
+                    section.name
+                ));
+            }
+            if placed.iter().any(|other| start < other.1 && end > other.0) {
+                return Err(format!(
+                    "section {} at ${:04X} overlaps another section",
+                    section.name, address
+                ));
+            }
+            placed.push((start, end));
+            object_bases.insert(section.name.as_str(), address);
+        }
+    }
+
+    // Place the other sections in the first aligned gap of their region
+    let mut cursors: Vec<u32> = map
+        .regions
+        .iter()
+        .map(|region| region.start as u32)
+        .collect();
+    for (object, object_bases) in objects.iter().zip(bases.iter_mut()) {
+        for section in object
+            .sections
+            .iter()
+            .filter(|section| section.address.is_none())
+        {
+            let index = map.region_of(&section.name)?;
+            let region = &map.regions[index];
+            let size = section.bytes.len() as u32;
+            let alignment = section.alignment as u32;
+            let mut base = cursors[index].next_multiple_of(alignment);
+            while let Some(other) = placed
+                .iter()
+                .find(|other| base < other.1 && base + size > other.0)
+            {
+                base = other.1.next_multiple_of(alignment);
+            }
+            if base + size > region.end as u32 + 1 {
+                return Err(format!(
+                    "section {} doesn't fit in region {}",
+                    section.name, region.name
+                ));
+            }
+            cursors[index] = base + size;
+            placed.push((base, base + size));
+            object_bases.insert(section.name.as_str(), base as u16);
+        }
+    }
+
+    // Global symbols
+    let mut globals: HashMap<&str, u16> = HashMap::new();
+    for (object, object_bases) in objects.iter().zip(bases.iter()) {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            let address = section_base(object_bases, &symbol.section)?.wrapping_add(symbol.offset);
+            if globals.insert(&symbol.name, address).is_some() {
+                return Err(format!("symbol {} is defined more than once", symbol.name));
+            }
+        }
+    }
+
+    // Copy sections
+    let size = placed
+        .iter()
+        .map(|range| range.1 as usize)
+        .max()
+        .unwrap_or(0);
+    let mut bytes = vec![0; size];
+    for (object, object_bases) in objects.iter().zip(bases.iter()) {
+        for section in object.sections.iter() {
+            let base = section_base(object_bases, &section.name)? as usize;
+            bytes[base..base + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+    }
+
+    // Apply relocations
+    for (object, object_bases) in objects.iter().zip(bases.iter()) {
+        for relocation in object.relocations.iter() {
+            let target = match &relocation.target {
+                Target::Section(name) => *object_bases
+                    .get(name.as_str())
+                    .ok_or_else(|| format!("relocation to unknown section {}", name))?,
+                Target::Symbol(name) => *globals
+                    .get(name.as_str())
+                    .ok_or_else(|| format!("undefined symbol {}", name))?,
+            };
+            object.check_range(&relocation.section, relocation.offset, 2)?;
+            let base = section_base(object_bases, &relocation.section)?;
+            let address = base as usize + relocation.offset as usize;
+            let value = target.wrapping_add(relocation.addend);
+            bytes[address..address + 2].copy_from_slice(&endian.split(value));
+        }
+    }
+
+    // Symbols, source lines and data regions
+    let mut symbols = Symbols::new();
+    for (object, object_bases) in objects.iter().zip(bases.iter()) {
+        for symbol in object.symbols.iter() {
+            let address = section_base(object_bases, &symbol.section)?.wrapping_add(symbol.offset);
+            symbols.add_label(&symbol.name, address, symbol.size);
+        }
+        for line in object.lines.iter() {
+            let address = section_base(object_bases, &line.section)?.wrapping_add(line.offset);
+            symbols.add_line(address, &line.file, line.line);
+        }
+        for data in object.data.iter() {
+            let address = section_base(object_bases, &data.section)?.wrapping_add(data.offset);
+            symbols.add_data(&data.name, address, data.size);
+        }
+    }
+
+    Ok(Image { bytes, symbols })
+}
+
+// Address a section of an object was placed at
+fn section_base(bases: &HashMap<&str, u16>, section: &str) -> Result<u16, String> {
+    bases
+        .get(section)
+        .copied()
+        .ok_or_else(|| format!("unknown section {}", section))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Relocation, Symbol};
+    use std::path::Path;
+
+    // Object with a 2-byte text section
+    fn object() -> Object {
+        let mut object = Object::new();
+        object.section_mut("text").bytes = vec![0x00, 0x00];
+        object
+    }
+
+    #[test]
+    fn relocation_past_the_section_is_an_error() {
+        let mut object = object();
+        object.relocations.push(Relocation {
+            section: String::from("text"),
+            offset: 0x0001,
+            target: Target::Section(String::from("text")),
+            addend: 0x0000,
+        });
+        let map = MemoryMap::default_machine();
+        assert_eq!(
+            link(&[object], &map).err().unwrap(),
+            "offset $0001 is past the end of section text"
+        );
+    }
+
+    #[test]
+    fn symbol_in_unknown_section_is_an_error() {
+        let mut object = object();
+        object.symbols.push(Symbol {
+            name: String::from("foo"),
+            section: String::from("nosuch"),
+            offset: 0x0000,
+            size: 0x0000,
+            global: false,
+        });
+        let map = MemoryMap::default_machine();
+        assert_eq!(
+            link(&[object], &map).err().unwrap(),
+            "unknown section nosuch"
+        );
+    }
+
+    #[test]
+    fn relocations_get_the_section_address() {
+        let mut object = object();
+        object.section_mut("text").address = Some(0x0300);
+        object.relocations.push(Relocation {
+            section: String::from("text"),
+            offset: 0x0000,
+            target: Target::Section(String::from("text")),
+            addend: 0x0001,
+        });
+        let image = link(&[object.clone()], &MemoryMap::default_machine()).unwrap();
+        assert_eq!(image.bytes[0x0300..], [0x03, 0x01]);
+
+        object.endian = Endian::Little;
+        let image = link(&[object], &MemoryMap::default_machine()).unwrap();
+        assert_eq!(image.bytes[0x0300..], [0x01, 0x03]);
+    }
+
+    #[test]
+    fn objects_share_a_byte_order() {
+        let mut little = object();
+        little.endian = Endian::Little;
+        let map = MemoryMap::default_machine();
+        assert_eq!(
+            link(&[object(), little], &map).err().unwrap(),
+            "objects have different byte orders"
+        );
+    }
+
+    // Names and address ranges of the regions of a map
+    fn regions(map: &MemoryMap) -> Vec<(&str, u16, u16)> {
+        map.regions
+            .iter()
+            .map(|region| (region.name.as_str(), region.start, region.end))
+            .collect()
+    }
+
+    #[test]
+    fn default_map_leaves_out_devices_the_stack_and_the_monitor() {
+        assert_eq!(
+            regions(&MemoryMap::default_machine()),
+            [("ram", 0x0000, 0x2EFF), ("ram2", 0x3104, 0xEFFF)]
+        );
+    }
+
+    #[test]
+    fn maps_follow_the_program_device_of_the_machine() {
+        let machine = MachineConfig::parse(
+            "device rom rom $0000 $7FFF
+             device main memory $8000 $FFFF remap program
+             device io serial $9000 $9001 remap
+             stack $FFFE $F000",
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(
+            regions(&MemoryMap::from_machine(&machine).unwrap()),
+            [("main", 0x8000, 0x8FFF), ("main2", 0x9002, 0xEFFF)]
+        );
+
+        let machine = MachineConfig::parse("device ram memory $0000 $FFFF", Path::new(""));
+        assert_eq!(
+            MemoryMap::from_machine(&machine.unwrap()).err().unwrap(),
+            "machine has no program device"
+        );
+    }
+}
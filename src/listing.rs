@@ -0,0 +1,257 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+
+// Listings
+//
+// Written by the assembler for reading, every source line with the address
+// and bytes it assembled to, followed by a cross reference of the names:
+//
+//     ; file demo.asm, section text
+//        12 $0000 10 FF FF 02           mov $FFFF, r1
+//        13 $0004 10 0008' 02           mov !points, r1
+//        20 $0008 48 65 6C 6C 6F    message: .asciz "Hello"
+//           $000D 00
+//
+//     ; cross reference
+//     ; name    kind   value      defined      used
+//       message label  text+$0008 demo.asm:20  demo.asm:31,40
+//
+// Addresses are offsets in the section unless it has a fixed address. Words
+// marked with `'` are relocated by the linker, they hold the offset into the
+// section or the addend until then.
+
+// Bytes shown per row
+const ROW_BYTES: usize = 5;
+
+// Rows shown per line, longer data ends with "..."
+const MAX_ROWS: usize = 4;
+
+// Source line and what it assembled to
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListingLine {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+    pub section: String,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    // Offsets into bytes of the relocated words
+    pub relocated: Vec<usize>,
+}
+
+// Name in the cross reference
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub kind: &'static str,
+    pub value: String,
+    pub defined: (String, usize),
+    pub uses: Vec<(String, usize)>,
+}
+
+// Listing class
+#[derive(Clone, Debug, Default)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+    pub references: Vec<Reference>,
+}
+
+// Listing implementation
+impl Listing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Write the listing
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|error| format!("{}: {}", path, error))
+    }
+}
+
+// Write the listing
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Source lines, with a header where the file or section changes
+        let mut current: Option<(&str, &str)> = None;
+        for line in self.lines.iter() {
+            if current != Some((&line.file, &line.section)) {
+                if current.is_some() {
+                    writeln!(f)?;
+                }
+                writeln!(f, "; file {}, section {}", line.file, line.section)?;
+                current = Some((&line.file, &line.section));
+            }
+
+            let rows = rows(line);
+            if rows.is_empty() {
+                let text = format!("{:>5} {:5} {:17} {}", line.line, "", "", line.text);
+                writeln!(f, "{}", text.trim_end())?;
+                continue;
+            }
+            for (index, (offset, row)) in rows.iter().enumerate() {
+                let address = format!("${:04X}", line.address.wrapping_add(*offset as u16));
+                match index {
+                    0 => {
+                        let text = format!("{:>5} {} {:17} {}", line.line, address, row, line.text);
+                        writeln!(f, "{}", text.trim_end())?
+                    }
+                    _ if index == MAX_ROWS - 1 && rows.len() > MAX_ROWS => {
+                        writeln!(f, "{:5} {} ...", "", address)?;
+                        break;
+                    }
+                    _ => writeln!(f, "{:5} {} {}", "", address, row)?,
+                }
+            }
+        }
+
+        // Cross reference
+        if self.references.is_empty() {
+            return Ok(());
+        }
+        let defined: Vec<String> = self
+            .references
+            .iter()
+            .map(|reference| format!("{}:{}", reference.defined.0, reference.defined.1))
+            .collect();
+        let width =
+            |column: fn(&Reference) -> usize| self.references.iter().map(column).max().unwrap_or(0);
+        let name_width = width(|reference| reference.name.len()).max(4);
+        let value_width = width(|reference| reference.value.len()).max(5);
+        let defined_width = defined.iter().map(String::len).max().unwrap_or(0);
+        writeln!(f)?;
+        writeln!(f, "; cross reference")?;
+        writeln!(
+            f,
+            "; {:name_width$} {:6} {:value_width$} {:defined_width$} used",
+            "name", "kind", "value", "defined"
+        )?;
+        for (reference, defined) in self.references.iter().zip(defined.iter()) {
+            let line = format!(
+                "  {:name_width$} {:6} {:value_width$} {:defined_width$} {}",
+                reference.name,
+                reference.kind,
+                reference.value,
+                defined,
+                uses(&reference.uses),
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
+// Format uses as "file:line,line file:line"
+fn uses(uses: &[(String, usize)]) -> String {
+    let mut groups: Vec<String> = Vec::new();
+    let mut file: Option<&str> = None;
+    for (use_file, line) in uses.iter() {
+        match groups.last_mut() {
+            Some(group) if file == Some(use_file.as_str()) => group.push_str(&format!(",{}", line)),
+            _ => groups.push(format!("{}:{}", use_file, line)),
+        }
+        file = Some(use_file);
+    }
+
+    groups.join(" ")
+}
+
+// Split the bytes of a line in rows, a relocated word is never split
+fn rows(line: &ListingLine) -> Vec<(usize, String)> {
+    let mut rows: Vec<(usize, String)> = Vec::new();
+    let mut row_start = 0;
+    let mut offset = 0;
+    while offset < line.bytes.len() {
+        let (size, text) = match line.relocated.contains(&offset) {
+            true => (
+                2,
+                format!("{:02X}{:02X}'", line.bytes[offset], line.bytes[offset + 1]),
+            ),
+            false => (1, format!("{:02X}", line.bytes[offset])),
+        };
+
+        // Start a new row when the field doesn't fit
+        match rows.last_mut() {
+            Some(row) if offset + size - row_start <= ROW_BYTES => {
+                row.1.push(' ');
+                row.1.push_str(&text);
+            }
+            _ => {
+                row_start = offset;
+                rows.push((offset, text));
+            }
+        }
+        offset += size;
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+
+    fn listing(source: &str) -> String {
+        let (_, listing) = Assembler::assemble_listing(source, "demo.asm").unwrap();
+        listing.to_string()
+    }
+
+    #[test]
+    fn lines_show_their_bytes_and_names_their_uses() {
+        let source = [
+            ".define COUNT 3",
+            "start:",
+            "    mov $FFFF, r1",
+            "    mov !message, r1",
+            "    mov COUNT, r2",
+            "    jeq $0000, !start",
+            "message: .asciz \"Hello, world\"",
+        ];
+        let expected = [
+            "; file demo.asm, section text",
+            "    1                         .define COUNT 3",
+            "    2                         start:",
+            "    3 $0000 10 FF FF 02           mov $FFFF, r1",
+            "    4 $0004 10 0011' 02           mov !message, r1",
+            "    5 $0008 10 00 03 03           mov COUNT, r2",
+            "    6 $000C 43 00 00 0000'        jeq $0000, !start",
+            "    7 $0011 48 65 6C 6C 6F    message: .asciz \"Hello, world\"",
+            "      $0016 2C 20 77 6F 72",
+            "      $001B 6C 64 00",
+            "",
+            "; cross reference",
+            "; name    kind   value      defined    used",
+            "  COUNT   define $0003      demo.asm:1 demo.asm:5",
+            "  message label  text+$0011 demo.asm:7 demo.asm:4",
+            "  start   label  text+$0000 demo.asm:2 demo.asm:6",
+        ];
+        assert_eq!(listing(&source.join("\n")), expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn long_data_is_cut_and_fixed_sections_show_addresses() {
+        let text = listing(
+            ".extern print\n.section data\n.org $4000\ntable: .fill 30, $AA\n\
+             .section text\n    cal !print\n    mov !table, r1\n    cal !print",
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        let table = lines
+            .iter()
+            .position(|line| line.contains("table:"))
+            .unwrap();
+        assert_eq!(
+            lines[table..table + 4],
+            [
+                "    4 $4000 AA AA AA AA AA    table: .fill 30, $AA",
+                "      $4005 AA AA AA AA AA",
+                "      $400A AA AA AA AA AA",
+                "      $400F ...",
+            ]
+        );
+        assert!(lines.contains(&"    6 $0000 53 0000'              cal !print"));
+
+        // Uses on several lines of a file are joined
+        assert!(lines.contains(&"  print extern ?     demo.asm:1 demo.asm:6,8"));
+        assert!(lines.contains(&"  table label  $4000 demo.asm:4 demo.asm:7"));
+    }
+}
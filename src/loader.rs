@@ -0,0 +1,303 @@
+use crate::device_mapper::DeviceMapper;
+use std::fs;
+use std::path::Path;
+
+// Loader
+//
+// Writes program files made of records to memory through a DeviceMapper:
+//
+//     :0B0300001A4830111A49301259000051      Intel HEX, .hex and .ihex
+//     S10E03001A4830111A4930125900004D       Motorola S-record, .srec, .s19,
+//                                            .s28, .s37 and .mot
+//
+// Every record carries its address, so one file can fill code at $0000 and
+// data at $0F00 without the bytes in between. Records are checked before
+// they are written, a bad checksum or a byte outside the mapped regions is
+// an error naming the line. Intel HEX segment and linear addresses and
+// 24- or 32-bit S-record addresses work as long as they stay below $10000.
+// The start address of a start record is returned.
+
+// Program file formats
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+// Format implementation
+impl Format {
+    // Format of a file by its extension, else by its first character
+    pub fn detect(path: &str, bytes: &[u8]) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("hex" | "ihex") => return Format::IntelHex,
+            Some("srec" | "s19" | "s28" | "s37" | "mot") => return Format::SRecord,
+            Some("bin") => return Format::Binary,
+            _ => (),
+        }
+
+        match bytes.first() {
+            Some(b':') => Format::IntelHex,
+            Some(b'S') => Format::SRecord,
+            _ => Format::Binary,
+        }
+    }
+}
+
+// Load a file of records to memory, returns the start address if it has one
+pub fn load_file(mm: &mut DeviceMapper, path: &str) -> Result<Option<u16>, String> {
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    let text = String::from_utf8_lossy(&bytes);
+    match Format::detect(path, &bytes) {
+        Format::IntelHex => load_intel_hex(mm, &text, path),
+        Format::SRecord => load_srecord(mm, &text, path),
+        Format::Binary => Err(format!("{}: not an Intel HEX or S-record file", path)),
+    }
+}
+
+// Load Intel HEX records
+pub fn load_intel_hex(
+    mm: &mut DeviceMapper,
+    text: &str,
+    file: &str,
+) -> Result<Option<u16>, String> {
+    let mut base: u32 = 0;
+    let mut start = None;
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", file, number + 1, message);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // Length, address, type, data and checksum
+        let Some(record) = line.strip_prefix(':') else {
+            return Err(error(String::from("record doesn't start with ':'")));
+        };
+        let bytes = hex_bytes(record).map_err(error)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(error(String::from("record length doesn't match its data")));
+        }
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != 0 {
+            let checksum = bytes[bytes.len() - 1];
+            let expected = checksum.wrapping_sub(sum);
+            return Err(error(format!(
+                "checksum is ${:02X}, should be ${:02X}",
+                checksum, expected
+            )));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            // Data
+            0x00 => write(mm, base + address, data).map_err(error)?,
+
+            // End of file
+            0x01 => break,
+
+            // Extended segment address, extended linear address
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+
+            // Start segment address, start linear address
+            0x03 if data.len() == 4 => {
+                let segment = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let offset = u16::from_be_bytes([data[2], data[3]]) as u32;
+                start = Some(start_address((segment << 4) + offset).map_err(error)?);
+            }
+            0x05 if data.len() == 4 => {
+                let address = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                start = Some(start_address(address).map_err(error)?);
+            }
+
+            record_type => {
+                return Err(error(format!("bad record type ${:02X}", record_type)));
+            }
+        }
+    }
+
+    Ok(start)
+}
+
+// Load Motorola S-records
+pub fn load_srecord(mm: &mut DeviceMapper, text: &str, file: &str) -> Result<Option<u16>, String> {
+    let mut start = None;
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", file, number + 1, message);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // Type, then count, address, data and checksum
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(error(String::from("record doesn't start with 'S'")));
+        }
+        let record_type = chars.next().unwrap_or(' ');
+        let bytes = hex_bytes(chars.as_str()).map_err(error)?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(error(String::from("record count doesn't match its data")));
+        }
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != 0xFF {
+            let checksum = bytes[bytes.len() - 1];
+            let expected = !(sum.wrapping_sub(checksum));
+            return Err(error(format!(
+                "checksum is ${:02X}, should be ${:02X}",
+                checksum, expected
+            )));
+        }
+
+        // Address size by type
+        let address_size = match record_type {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(error(format!("bad record type S{}", record_type))),
+        };
+        if bytes.len() < address_size + 2 {
+            return Err(error(String::from("record is too short for its address")));
+        }
+        let address = bytes[1..address_size + 1]
+            .iter()
+            .fold(0u32, |address, byte| address << 8 | *byte as u32);
+        let data = &bytes[address_size + 1..bytes.len() - 1];
+
+        match record_type {
+            // Header and record count
+            '0' | '5' | '6' => (),
+
+            // Data
+            '1' | '2' | '3' => write(mm, address, data).map_err(error)?,
+
+            // Start address, ends the file
+            _ => {
+                start = Some(start_address(address).map_err(error)?);
+                break;
+            }
+        }
+    }
+
+    Ok(start)
+}
+
+// Parse pairs of hex digits
+fn hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) || !text.chars().all(|char| char.is_ascii_hexdigit()) {
+        return Err(String::from("record isn't pairs of hex digits"));
+    }
+
+    Ok((0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap())
+        .collect())
+}
+
+// Write data at an address, after checking every byte lands in a region
+fn write(mm: &mut DeviceMapper, address: u32, data: &[u8]) -> Result<(), String> {
+    for offset in 0..data.len() as u32 {
+        let byte_address = address + offset;
+        if byte_address > 0xFFFF {
+            return Err(format!(
+                "address ${:X} is past the end of memory",
+                byte_address
+            ));
+        }
+        if !mm.is_mapped(byte_address as u16) {
+            return Err(format!("address ${:04X} isn't mapped", byte_address));
+        }
+    }
+
+    for (offset, byte) in data.iter().enumerate() {
+        mm.set_byte(*byte, (address + offset as u32) as u16);
+    }
+    Ok(())
+}
+
+// Check a start address fits in 16 bits
+fn start_address(address: u32) -> Result<u16, String> {
+    u16::try_from(address)
+        .map_err(|_| format!("start address ${:X} is past the end of memory", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{Device, DeviceType};
+
+    // Memory at $0000-$0FFF and $2000-$2FFF, nothing in between
+    fn machine() -> DeviceMapper {
+        let mut mm = DeviceMapper::new();
+        mm.map(
+            Device::new(0x1000, DeviceType::Memory),
+            0x0000,
+            0x0FFF,
+            false,
+        );
+        mm.map(
+            Device::new(0x1000, DeviceType::Memory),
+            0x2000,
+            0x2FFF,
+            true,
+        );
+        mm
+    }
+
+    // Bytes of memory from an address
+    fn bytes(mm: &DeviceMapper, address: u16, length: u16) -> Vec<u8> {
+        (address..address + length)
+            .map(|address| mm.get_uint_8(address).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn intel_hex_fills_discontiguous_regions() {
+        let mut mm = machine();
+        let text = ":03000000010203F7\n:02200000AABB79\n:0400000500000100F6\n:00000001FF\n";
+        assert_eq!(load_intel_hex(&mut mm, text, "a.hex"), Ok(Some(0x0100)));
+        assert_eq!(bytes(&mm, 0x0000, 3), [0x01, 0x02, 0x03]);
+        assert_eq!(bytes(&mm, 0x2000, 2), [0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn srecords_fill_discontiguous_regions() {
+        let mut mm = machine();
+        let text = "S1060000010203F3\nS1052000AABB75\nS9030100FB\n";
+        assert_eq!(load_srecord(&mut mm, text, "a.s19"), Ok(Some(0x0100)));
+        assert_eq!(bytes(&mm, 0x0000, 3), [0x01, 0x02, 0x03]);
+        assert_eq!(bytes(&mm, 0x2000, 2), [0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn bad_checksums_are_errors() {
+        let mut mm = machine();
+        assert_eq!(
+            load_intel_hex(&mut mm, ":02200000AABB79\n:03000000010203F8\n", "a.hex"),
+            Err(String::from("a.hex:2: checksum is $F8, should be $F7"))
+        );
+        assert_eq!(
+            load_srecord(&mut mm, "S1052000AABB75\nS1060000010203F4\n", "a.s19"),
+            Err(String::from("a.s19:2: checksum is $F4, should be $F3"))
+        );
+    }
+
+    #[test]
+    fn unmapped_addresses_are_errors_and_write_nothing() {
+        let mut mm = machine();
+        assert_eq!(
+            load_intel_hex(&mut mm, ":02100000AABB89\n", "a.hex"),
+            Err(String::from("a.hex:1: address $1000 isn't mapped"))
+        );
+        assert_eq!(
+            load_srecord(&mut mm, "S1050FFFAABB87\n", "a.s19"),
+            Err(String::from("a.s19:1: address $1000 isn't mapped"))
+        );
+        assert_eq!(bytes(&mm, 0x0FFF, 1), [0x00]);
+    }
+}
@@ -0,0 +1,524 @@
+use crate::cpu::CPU;
+use crate::device::{BusWidth, Device, DeviceType, Endian, SharedStream};
+use crate::device_mapper::{DeviceMapper, Permissions};
+use crate::entries::{entries, parse_number, parse_u16};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Machine descriptions
+//
+// Describe the devices of a machine, so board variants don't need a new
+// build. One entry per line, numbers are `$` hex or decimal, `;` starts a
+// comment:
+//
+//     device <name> <type> <start> <end> [options]   map a device from start
+//                                                    to end, inclusive
+//     stack <base> <limit>                           initial stack, $FFFE
+//                                                    and $FF00 by default
+//     endian big|little                              byte order, big by
+//                                                    default
+//
+// Types are memory, rom, stdout, timer, keyboard and serial. Where devices
+// overlap the one further down the file wins. Options of a device:
+//
+//     size <n>             bytes in the device, end - start + 1 by default
+//     remap                address the device from 0 at start
+//     permissions <rwx>    what the CPU may do, like "r-x" or "rw-"
+//     supervisor           only allow accesses in supervisor mode
+//     wait <n>             extra cycles every access takes
+//     endian big|little    byte order of the region
+//     bus byte|word        give 16-bit accesses to the device as one word
+//     banks <n>            show banks of n bytes, see Device::with_banks, the
+//                          device then spans at least n + 1 bytes for the
+//                          bank register and its size may pass 64K,
+//                          needs remap
+//     image <file>         initial contents, relative to the description
+//     program              raw binary programs are loaded into the device
+
+// Description of the default machine
+pub const DEFAULT_MACHINE: &str = include_str!("../machines/default.cfg");
+
+// Device in a machine description
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub device_type: String,
+    pub start: u16,
+    pub end: u16,
+    pub size: usize,
+    pub remap: bool,
+    pub permissions: Permissions,
+    pub supervisor_only: bool,
+    pub wait_states: u64,
+    pub endian: Option<Endian>,
+    pub bus_width: BusWidth,
+    pub bank_size: usize,
+    pub image: Option<PathBuf>,
+    pub program: bool,
+}
+
+// MachineConfig class
+#[derive(Clone, Debug)]
+pub struct MachineConfig {
+    pub devices: Vec<DeviceConfig>,
+    pub stack: (u16, u16),
+    pub endian: Endian,
+}
+
+// Default MachineConfig
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// MachineConfig implementation
+impl MachineConfig {
+    pub fn new() -> Self {
+        Self {
+            devices: Vec::new(),
+            stack: (0xFFFE, 0xFF00),
+            endian: Endian::Big,
+        }
+    }
+
+    // Description of the default machine
+    pub fn default_machine() -> Self {
+        Self::parse(DEFAULT_MACHINE, Path::new("")).unwrap()
+    }
+
+    // Device an address goes to, the last one mapped over it
+    pub fn device_at(&self, address: u16) -> Option<&DeviceConfig> {
+        self.devices
+            .iter()
+            .rev()
+            .find(|device| (device.start..=device.end).contains(&address))
+    }
+
+    // Load a machine description file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        Self::parse(&text, directory).map_err(|error| format!("{}:{}", path, error))
+    }
+
+    // Parse the text of a machine description, images are relative to directory
+    pub fn parse(text: &str, directory: &Path) -> Result<Self, String> {
+        let mut machine = Self::new();
+        for entry in entries(text) {
+            let error = |message: &str| entry.error(message);
+            let address = |text: &str| {
+                parse_u16(text).ok_or_else(|| error(&format!("invalid address {}", text)))
+            };
+            match entry.fields[..] {
+                ["device", name, device_type, start, end, ..] => {
+                    if self::device_type(device_type).is_none() {
+                        return Err(error(&format!("unknown device type {}", device_type)));
+                    }
+                    if machine.devices.iter().any(|other| other.name == name) {
+                        return Err(error(&format!("device {} is already defined", name)));
+                    }
+                    let (start, end) = (address(start)?, address(end)?);
+                    if end < start {
+                        return Err(error("device ends before it starts"));
+                    }
+                    let mut device = DeviceConfig {
+                        name: name.to_string(),
+                        device_type: device_type.to_string(),
+                        start,
+                        end,
+                        size: (end - start) as usize + 1,
+                        remap: false,
+                        permissions: Permissions::ALL,
+                        supervisor_only: false,
+                        wait_states: 0,
+                        endian: None,
+                        bus_width: BusWidth::Byte,
+                        bank_size: 0,
+                        image: None,
+                        program: false,
+                    };
+                    parse_options(&mut device, &entry.fields[5..], directory)
+                        .map_err(|message| error(&message))?;
+                    machine.devices.push(device);
+                }
+                ["stack", base, limit] => {
+                    let (base, limit) = (address(base)?, address(limit)?);
+                    if base < limit {
+                        return Err(error("stack base is below its limit"));
+                    }
+                    machine.stack = (base, limit);
+                }
+                ["endian", endian] => {
+                    machine.endian = parse_endian(endian).map_err(|message| error(&message))?;
+                }
+                _ => return Err(entry.invalid()),
+            }
+        }
+
+        Ok(machine)
+    }
+
+    // Create the devices and map them, a raw binary program goes in the program
+    // device and serial output is dropped without a stream for it
+    pub fn build(
+        &self,
+        program: Option<&[u8]>,
+        keyboard: &SharedStream,
+        serial: &SharedStream,
+        serial_output: Option<&SharedStream>,
+    ) -> Result<DeviceMapper, String> {
+        let mut mm = DeviceMapper::new();
+        mm.set_endian(self.endian);
+        if program.is_some() && !self.devices.iter().any(|device| device.program) {
+            return Err(String::from("machine has no program device"));
+        }
+
+        for config in self.devices.iter() {
+            // Initial contents
+            let mut contents = match (&config.image, program) {
+                (_, Some(program)) if config.program => program.to_vec(),
+                (Some(path), _) => {
+                    fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?
+                }
+                _ => Vec::new(),
+            };
+            if contents.len() > config.size {
+                return Err(format!("image doesn't fit in device {}", config.name));
+            }
+            if config.program && contents.len() > (config.end - config.start) as usize + 1 {
+                return Err(format!("program doesn't fit in device {}", config.name));
+            }
+            contents.resize(config.size, 0);
+
+            // Create device
+            let device_type = device_type(&config.device_type).unwrap();
+            let mut device =
+                Device::from_image(contents, device_type).with_bus_width(config.bus_width);
+            if config.bank_size != 0 {
+                device = device.with_banks(config.bank_size);
+            }
+            match config.device_type.as_str() {
+                "keyboard" => device = device.with_input(keyboard.clone()),
+                "serial" => {
+                    device = device.with_input(serial.clone());
+                    if let Some(serial_output) = serial_output {
+                        device = device.with_output(serial_output.clone());
+                    }
+                }
+                _ => (),
+            }
+
+            // Map device
+            let region = mm.map(device, config.start, config.end, config.remap);
+            region
+                .set_permissions(config.permissions)
+                .set_supervisor_only(config.supervisor_only)
+                .set_wait_states(config.wait_states);
+            if let Some(endian) = config.endian {
+                region.set_endian(endian);
+            }
+        }
+
+        Ok(mm)
+    }
+}
+
+// Builds a CPU around a machine description:
+//
+//     let cpu = MachineBuilder::new(MachineConfig::default_machine())
+//         .with_program(image)
+//         .with_device(Device::new(0x0002, DeviceType::Timer), 0x3200, 0x3201, true)
+//         .build()?;
+pub struct MachineBuilder {
+    config: MachineConfig,
+    program: Option<Vec<u8>>,
+    keyboard: SharedStream,
+    serial: SharedStream,
+    serial_output: Option<SharedStream>,
+    devices: Vec<(Device, u16, u16, bool)>,
+    entry: Option<u16>,
+}
+
+// MachineBuilder implementation
+impl MachineBuilder {
+    pub fn new(config: MachineConfig) -> Self {
+        Self {
+            config,
+            program: None,
+            keyboard: SharedStream::default(),
+            serial: SharedStream::default(),
+            serial_output: None,
+            devices: Vec::new(),
+            entry: None,
+        }
+    }
+
+    // Load a raw binary program into the program device
+    pub fn with_program(mut self, program: Vec<u8>) -> Self {
+        self.program = Some(program);
+        self
+    }
+
+    // Feed keyboard devices from a stream
+    pub fn with_keyboard(mut self, keyboard: SharedStream) -> Self {
+        self.keyboard = keyboard;
+        self
+    }
+
+    // Feed serial devices from a stream
+    pub fn with_serial(mut self, serial: SharedStream) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    // Send the output of serial devices to a stream
+    pub fn with_serial_output(mut self, serial_output: SharedStream) -> Self {
+        self.serial_output = Some(serial_output);
+        self
+    }
+
+    // Map a device of the host over the devices of the description
+    pub fn with_device(mut self, device: Device, start: u16, end: u16, remap: bool) -> Self {
+        self.devices.push((device, start, end, remap));
+        self
+    }
+
+    // Start executing at an address instead of 0
+    pub fn with_entry(mut self, address: u16) -> Self {
+        self.entry = Some(address);
+        self
+    }
+
+    // Build the machine and a CPU running it
+    pub fn build(self) -> Result<CPU, String> {
+        let mut mm = self.config.build(
+            self.program.as_deref(),
+            &self.keyboard,
+            &self.serial,
+            self.serial_output.as_ref(),
+        )?;
+        for (device, start, end, remap) in self.devices {
+            mm.map(device, start, end, remap);
+        }
+
+        let mut cpu = CPU::new(mm);
+        cpu.set_stack(self.config.stack.0, self.config.stack.1);
+        if let Some(entry) = self.entry {
+            cpu.set_ip(entry);
+        }
+        Ok(cpu)
+    }
+}
+
+// Parse the options of a device
+fn parse_options(
+    device: &mut DeviceConfig,
+    options: &[&str],
+    directory: &Path,
+) -> Result<(), String> {
+    let mut index = 0;
+    while index < options.len() {
+        let option = options[index];
+        let value = options.get(index + 1).copied();
+        let number = || {
+            value
+                .and_then(parse_number)
+                .ok_or_else(|| format!("option {} needs a number", option))
+        };
+        index += 2;
+        match option {
+            "size" => device.size = number()? as usize,
+            "permissions" => {
+                device.permissions = value
+                    .and_then(parse_permissions)
+                    .ok_or_else(|| String::from("permissions are like \"rwx\" or \"r--\""))?;
+            }
+            "wait" => device.wait_states = number()? as u64,
+            "endian" => {
+                device.endian = Some(parse_endian(value.unwrap_or(""))?);
+            }
+            "bus" => {
+                device.bus_width = match value {
+                    Some("byte") => BusWidth::Byte,
+                    Some("word") => BusWidth::Word,
+                    _ => return Err(String::from("bus is byte or word")),
+                }
+            }
+            "banks" => device.bank_size = number()? as usize,
+            "image" => {
+                let path = value.ok_or_else(|| String::from("option image needs a file"))?;
+                device.image = Some(directory.join(path));
+            }
+
+            // Flags
+            "remap" | "supervisor" | "program" => {
+                index -= 1;
+                match option {
+                    "remap" => device.remap = true,
+                    "supervisor" => device.supervisor_only = true,
+                    _ => device.program = true,
+                }
+            }
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+
+    // Timers hold a 16-bit counter
+    if device.device_type == "timer" && device.size < 2 {
+        return Err(String::from("timer needs 2 bytes for its counter"));
+    }
+
+    // Banked devices show one bank and the bank register, others show all of it
+    let window = (device.end - device.start) as usize + 1;
+    if device.bank_size == 0 {
+        if device.size > 0x10000 {
+            return Err(String::from("device is larger than memory"));
+        }
+    } else if !device.remap {
+        return Err(String::from("banked device needs remap"));
+    } else if device.bank_size + 1 > window {
+        return Err(String::from(
+            "device is smaller than its bank and bank register",
+        ));
+    } else if device.size > device.bank_size * 0x100 {
+        return Err(String::from(
+            "device has more banks than the bank register selects",
+        ));
+    }
+
+    Ok(())
+}
+
+// Device type by name
+fn device_type(name: &str) -> Option<DeviceType> {
+    match name {
+        "memory" => Some(DeviceType::Memory),
+        "rom" => Some(DeviceType::Rom),
+        "stdout" => Some(DeviceType::Stdout),
+        "timer" => Some(DeviceType::Timer),
+        "keyboard" => Some(DeviceType::Keyboard),
+        "serial" => Some(DeviceType::Serial),
+        _ => None,
+    }
+}
+
+// Parse permissions like "r-x"
+fn parse_permissions(text: &str) -> Option<Permissions> {
+    match text.as_bytes() {
+        [read @ (b'r' | b'-'), write @ (b'w' | b'-'), execute @ (b'x' | b'-')] => {
+            Some(Permissions {
+                read: *read == b'r',
+                write: *write == b'w',
+                execute: *execute == b'x',
+            })
+        }
+        _ => None,
+    }
+}
+
+// Parse a byte order
+fn parse_endian(text: &str) -> Result<Endian, String> {
+    match text {
+        "big" => Ok(Endian::Big),
+        "little" => Ok(Endian::Little),
+        _ => Err(String::from("endian is big or little")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a machine description
+    fn build(text: &str) -> Result<DeviceMapper, String> {
+        let streams = SharedStream::default();
+        MachineConfig::parse(text, Path::new(""))?.build(None, &streams, &streams, None)
+    }
+
+    #[test]
+    fn banked_devices_may_pass_64k() {
+        let mut mm = build("device bank memory $8000 $C000 size $40000 banks $4000 remap").unwrap();
+
+        // Write the last byte of bank $0F and read it back after switching away
+        mm.set_byte(0x0F, 0xC000);
+        mm.set_byte(0x42, 0xBFFF);
+        mm.set_byte(0x00, 0xC000);
+        assert_eq!(mm.get_byte(0xBFFF), 0x00);
+        mm.set_byte(0x0F, 0xC000);
+        assert_eq!(mm.get_byte(0xBFFF), 0x42);
+    }
+
+    #[test]
+    fn later_devices_win_an_address() {
+        let machine = MachineConfig::default_machine();
+        let name = |address| {
+            machine
+                .device_at(address)
+                .map(|device| device.name.as_str())
+        };
+        assert_eq!(name(0x2FFF), Some("ram"));
+        assert_eq!(name(0x3101), Some("keyboard"));
+        assert_eq!(name(0xFF00), Some("stack"));
+
+        let machine = MachineConfig::parse("device rom rom $8000 $FFFF", Path::new(""));
+        assert!(machine.unwrap().device_at(0x7FFF).is_none());
+    }
+
+    #[test]
+    fn timers_count_in_the_byte_order_of_their_region() {
+        for (text, expected) in [
+            (
+                "endian little\ndevice t timer $8000 $8001 remap",
+                [0x02, 0x01],
+            ),
+            (
+                "endian little\ndevice t timer $8000 $8001 remap endian big",
+                [0x01, 0x02],
+            ),
+        ] {
+            let mut mm = build(text).unwrap();
+            mm.tick(0x0102);
+            assert_eq!(mm.get_uint_16(0x8000), Ok(0x0102));
+            assert_eq!([mm.get_byte(0x8000), mm.get_byte(0x8001)], expected);
+        }
+    }
+
+    #[test]
+    fn devices_may_fill_memory() {
+        let mm = build("device ram memory $0000 $FFFF remap").unwrap();
+        assert!(mm.is_mapped(0x0000) && mm.is_mapped(0xFFFF));
+    }
+
+    #[test]
+    fn device_sizes_are_checked() {
+        assert_eq!(
+            build("device ram memory $0000 $00FF size $10001")
+                .err()
+                .unwrap(),
+            "1: device is larger than memory"
+        );
+        assert_eq!(
+            build("device bank memory $8000 $BFFF size $40000 banks $4000 remap")
+                .err()
+                .unwrap(),
+            "1: device is smaller than its bank and bank register"
+        );
+        assert_eq!(
+            build("device bank memory $8000 $8010 size $1010 banks $0010 remap")
+                .err()
+                .unwrap(),
+            "1: device has more banks than the bank register selects"
+        );
+        assert_eq!(
+            build("device bank memory $8000 $C000 size $40000 banks $4000")
+                .err()
+                .unwrap(),
+            "1: banked device needs remap"
+        );
+        assert_eq!(
+            build("device t timer $8000 $8000 remap").err().unwrap(),
+            "1: timer needs 2 bytes for its counter"
+        );
+    }
+}
@@ -1,270 +1,475 @@
-#![allow(dead_code)]
-mod cpu;
-mod device;
-mod device_mapper;
-
-use cpu::*;
-use device::*;
-use device_mapper::DeviceMapper;
-
-const IP: u8 = 0;
-const ACC: u8 = 1;
-const R1: u8 = 2;
-const R2: u8 = 3;
-const R3: u8 = 4;
-const R4: u8 = 5;
-const R5: u8 = 6;
-const R6: u8 = 7;
-const R7: u8 = 8;
-const R8: u8 = 9;
-const SP: u8 = 10;
-const FP: u8 = 11;
-
-const DEBUG: bool = true;
+use six_teen_bit_vm::assembler::Assembler;
+use six_teen_bit_vm::linker::{self, MemoryMap};
+use six_teen_bit_vm::loader::{self, Format};
+use six_teen_bit_vm::object::Object;
+use six_teen_bit_vm::symbols::Symbols;
+use six_teen_bit_vm::{compiler, Device, DeviceType, Endian, MachineBuilder, MachineConfig};
+use six_teen_bit_vm::{SharedStream, StopReason, Stream, CPU};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::{env, process, thread};
+
+// Source of the monitor
+const MONITOR: &str = include_str!("../asm/monitor.asm");
+
+// Address of the monitor ROM, up to the stack
+const MONITOR_ROM: u16 = 0xF000;
+
+// Devices and memory the monitor uses, with the address it expects them at
+const MONITOR_DEVICES: [(&str, &str, u16); 5] = [
+    ("screen", "stdout", 0x3000),
+    ("keyboard", "keyboard", 0x3100),
+    ("serial port", "serial", 0x3102),
+    ("variables", "memory", 0x2F00),
+    ("stack", "memory", 0xFF00),
+];
+
+// Command line usage
+const USAGE: &str = "usage:
+    six-teen-bit-vm [--rom <image>] [--machine <file>] [--serial <file>]
+                    [--serial-out <file>] [--debug]
+                                 boot the monitor
+    six-teen-bit-vm run <image> [--machine <file>] [--symbols <file>]
+                        [--serial <file>] [--serial-out <file>] [--debug]
+                        [--profile] [--semihost]
+                                 image is a binary, Intel HEX or S-record file,
+                                 --semihost lets it call the host and exit with
+                                 a status
+    six-teen-bit-vm asm <source> [-o <object>] [-l <listing>]
+                        [--endian <big|little>]
+                                 --endian is the byte order of the machine
+    six-teen-bit-vm link <object>... [-m <map>] [--machine <file>] [-o <image>]
+                                 without a map the image goes in the memory of
+                                 the machine
+    six-teen-bit-vm cc <source> [-S] [-o <output>] [--machine <file>]
+                       [--endian <big|little>]";
 
 fn main() {
-    // Create memory devices
-    let memory = Device::new(0xFFFF, DeviceType::Memory);
-    let stack = Device::new(0x00FF, DeviceType::Memory);
+    // Run command
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        None => monitor(&args),
+        Some(arg) if arg.starts_with('-') => monitor(&args),
+        Some("run") => run(&args[1..]),
+        Some("asm") => assemble(&args[1..]),
+        Some("link") => link(&args[1..]),
+        Some("cc") => compile(&args[1..]),
+        Some(command) => Err(format!("unknown command \"{}\"\n{}", command, USAGE)),
+    };
+
+    // Report errors
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+// Machine description given with --machine, or the default machine
+fn machine(options: &HashMap<String, String>) -> Result<MachineConfig, String> {
+    match options.get("--machine") {
+        Some(path) => MachineConfig::load(path),
+        None => Ok(MachineConfig::default_machine()),
+    }
+}
+
+// Memory map given with -m, the memory of the machine given with --machine or
+// of the default machine
+fn memory_map(options: &HashMap<String, String>) -> Result<MemoryMap, String> {
+    match (options.get("-m"), options.contains_key("--machine")) {
+        (Some(path), _) => MemoryMap::load(path),
+        (None, true) => MemoryMap::from_machine(&machine(options)?),
+        (None, false) => Ok(MemoryMap::default_machine()),
+    }
+}
+
+// Byte order given with --endian, big by default
+fn endian(options: &HashMap<String, String>) -> Result<Endian, String> {
+    match options.get("--endian").map(String::as_str) {
+        None | Some("big") => Ok(Endian::Big),
+        Some("little") => Ok(Endian::Little),
+        Some(_) => Err(String::from("endian is big or little")),
+    }
+}
+
+// Keyboard input read from stdin, which steps the CPU when debugging instead
+fn keyboard(debug: bool) -> SharedStream {
+    let keyboard = SharedStream::default();
+    if !debug {
+        let input = keyboard.clone();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                let Ok(byte) = byte else {
+                    break;
+                };
+                input.lock().unwrap().bytes.push_back(byte);
+            }
+            input.lock().unwrap().closed = true;
+        });
+    }
+    keyboard
+}
+
+// Serial input read from a file, none without one
+fn serial(path: Option<&String>) -> Result<SharedStream, String> {
+    let mut stream = Stream::default();
+    if let Some(path) = path {
+        let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+        stream.bytes = bytes.into();
+    }
+    stream.closed = true;
+    Ok(Arc::new(Mutex::new(stream)))
+}
+
+// Serial output and the thread writing it to a file
+type SerialOutput = (SharedStream, JoinHandle<io::Result<()>>);
+
+// Serial output written to a file while the machine runs, dropped without one
+fn serial_output(path: Option<&String>) -> Result<Option<SerialOutput>, String> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let mut file = File::create(path).map_err(|error| format!("{}: {}", path, error))?;
+    let output = SharedStream::default();
+    let stream = output.clone();
+    let writer = thread::spawn(move || loop {
+        let (bytes, closed) = {
+            let mut stream = stream.lock().unwrap();
+            let bytes: Vec<u8> = stream.bytes.drain(..).collect();
+            (bytes, stream.closed)
+        };
+        file.write_all(&bytes)?;
+        if closed {
+            return file.flush();
+        }
+        thread::sleep(Duration::from_millis(10));
+    });
+    Ok(Some((output, writer)))
+}
 
-    // Create screen device
-    let screen = Device::new(0x00FF, DeviceType::Stdout);
+// Close serial output and wait for the rest of it to be written
+fn close_serial_output(serial_output: Option<SerialOutput>) -> Result<(), String> {
+    let Some((output, writer)) = serial_output else {
+        return Ok(());
+    };
+    output.lock().unwrap().closed = true;
+    writer.join().unwrap().map_err(|error| error.to_string())
+}
 
-    // Create memory mapper
-    let mut mm = DeviceMapper::new();
+// Boot the monitor, or a ROM image given with --rom
+fn monitor(args: &[String]) -> Result<(), String> {
+    let (paths, options) = parse_options(
+        args,
+        &["--debug"],
+        &["--rom", "--serial", "--serial-out", "--machine"],
+    )?;
+    if !paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    // Assemble and link the monitor in the byte order of the machine, the
+    // image holds it from the ROM address. A ROM image given with --rom may
+    // use other devices.
+    let machine = machine(&options)?;
+    let mut symbols = Symbols::new();
+    let mut rom = match options.get("--rom") {
+        Some(path) => fs::read(path).map_err(|error| format!("{}: {}", path, error))?,
+        None => {
+            check_monitor_devices(&machine)?;
+            let (object, _) = Assembler::new()
+                .with_endian(machine.endian)
+                .assemble_source(MONITOR, "asm/monitor.asm")?;
+            let image = linker::link(&[object], &MemoryMap::new())?;
+            symbols = image.symbols;
+            image.bytes[MONITOR_ROM as usize..].to_vec()
+        }
+    };
+    let size = 0xFF00 - MONITOR_ROM as usize;
+    if rom.len() > size {
+        return Err(format!("ROM doesn't fit in ${:04X} bytes", size));
+    }
+    rom.resize(size, 0);
+
+    // Create virtual machine, with the ROM over the machine
+    let debug = options.contains_key("--debug");
+    let rom = Device::from_image(rom, DeviceType::Rom);
+    let serial_output = serial_output(options.get("--serial-out"))?;
+    let mut builder = MachineBuilder::new(machine)
+        .with_keyboard(keyboard(debug))
+        .with_serial(serial(options.get("--serial"))?)
+        .with_device(rom, MONITOR_ROM, 0xFEFF, true)
+        .with_entry(MONITOR_ROM);
+    if let Some((output, _)) = &serial_output {
+        builder = builder.with_serial_output(output.clone());
+    }
+    let mut cpu = builder.build()?;
+    cpu.load_symbols(symbols);
+
+    // Run the monitor
+    let reason = execute(&mut cpu, debug);
+    close_serial_output(serial_output)?;
+    finish(reason)
+}
 
-    // Map memory devices to memory mapper
-    mm.map(memory, 0x0000, 0xFF00, true);
-    mm.map(stack, 0xFF00, 0xFFFF, true);
-    mm.map(screen, 0x3000, 0x30FF, true);
+// Check the machine has the devices the monitor uses where it expects them,
+// devices must start at their address and memory must be writable up to the
+// end of the page
+fn check_monitor_devices(machine: &MachineConfig) -> Result<(), String> {
+    for (name, device_type, address) in MONITOR_DEVICES {
+        let found = |address| {
+            machine.device_at(address).filter(|device| {
+                device.device_type == device_type
+                    && match device_type {
+                        "memory" => device.permissions.write,
+                        _ => device.start == address,
+                    }
+            })
+        };
+        let end = match device_type {
+            "memory" => address | 0x00FF,
+            _ => address,
+        };
+        if found(address).is_none() || found(end).is_none() {
+            let expected = match device_type {
+                "memory" => String::from("in writable memory"),
+                _ => format!("as a {} device", device_type),
+            };
+            return Err(format!(
+                "the monitor needs the {} at ${:04X} {}",
+                name, address, expected
+            ));
+        }
+    }
+
+    Ok(())
+}
 
-    // Load program to memory
-    hardcode(&mut mm);
+// Run a linked image
+fn run(args: &[String]) -> Result<(), String> {
+    let (paths, options) = parse_options(
+        args,
+        &["--debug", "--profile", "--semihost"],
+        &["--symbols", "--serial", "--serial-out", "--machine"],
+    )?;
+    let [path] = &paths[..] else {
+        return Err(USAGE.to_string());
+    };
+
+    // Create virtual machine with a binary image in its program device
+    let image = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    let format = Format::detect(path, &image);
+    let debug = options.contains_key("--debug");
+    let serial_output = serial_output(options.get("--serial-out"))?;
+    let mut builder = MachineBuilder::new(machine(&options)?)
+        .with_keyboard(keyboard(debug))
+        .with_serial(serial(options.get("--serial"))?);
+    if format == Format::Binary {
+        builder = builder.with_program(image);
+    }
+    if let Some((output, _)) = &serial_output {
+        builder = builder.with_serial_output(output.clone());
+    }
+    let mut cpu = builder
+        .build()
+        .map_err(|error| format!("{}: {}", path, error))?;
+
+    // Write records to the machine, starting at their start address
+    if format != Format::Binary {
+        if let Some(start) = loader::load_file(cpu.device_mapper_mut(), path)? {
+            cpu.set_ip(start);
+        }
+    }
+
+    // Load symbols, next to the image when not given
+    let symbols_path = match options.get("--symbols") {
+        Some(symbols_path) => Some(symbols_path.clone()),
+        None => Some(with_extension(path, "sym")).filter(|path| Path::new(path).exists()),
+    };
+    if let Some(symbols_path) = symbols_path {
+        cpu.load_symbols(Symbols::load(&symbols_path)?);
+    }
+    if options.contains_key("--semihost") {
+        cpu.enable_semihosting();
+    }
+
+    // Run the program, with the profiler if asked
+    let profile = options.contains_key("--profile");
+    if profile {
+        cpu.enable_profiler();
+    }
+    let reason = execute(&mut cpu, debug);
+    close_serial_output(serial_output)?;
+    if !profile {
+        return finish(reason);
+    }
+
+    // Write profile, a report and collapsed stacks for flamegraphs
+    let profiler = cpu.profiler().unwrap();
+    let report_path = with_extension(path, "profile.txt");
+    let mut report = File::create(&report_path).map_err(|error| error.to_string())?;
+    profiler
+        .report(cpu.symbols(), &mut report)
+        .map_err(|error| error.to_string())?;
+    let collapsed_path = with_extension(path, "folded");
+    let mut collapsed = File::create(&collapsed_path).map_err(|error| error.to_string())?;
+    profiler
+        .write_collapsed(cpu.symbols(), &mut collapsed)
+        .map_err(|error| error.to_string())?;
+    println!("Profile written to {} and {}", report_path, collapsed_path);
+    finish(reason)
+}
 
-    // Create virtual machine
-    let mut cpu = CPU::new(mm);
+// Run the CPU, or step it when debugging, printing its state and waiting for
+// enter after every instruction
+fn execute(cpu: &mut CPU, debug: bool) -> StopReason {
+    if !debug {
+        return cpu.run();
+    }
+
+    loop {
+        let reason = cpu.run_for(1);
+        cpu.debug();
+        let device_mapper = cpu.device_mapper();
+        device_mapper.view_memory(cpu.get_register("ip"), 16);
+        device_mapper.view_memory(0xFFFF - 16, 16);
+        device_mapper.view_memory(0x0F00, 16);
+        println!();
+        if reason != StopReason::Budget {
+            return reason;
+        }
+
+        // Wait for input
+        io::stdin().read_line(&mut String::new()).unwrap();
+    }
+}
+
+// Succeed when the program halted, exit with the status the guest exited with
+fn finish(reason: StopReason) -> Result<(), String> {
+    match reason {
+        StopReason::Halted | StopReason::Exit(0) => Ok(()),
+        StopReason::Exit(status) => {
+            io::stdout().flush().map_err(|error| error.to_string())?;
+            process::exit(status.min(255) as i32)
+        }
+        reason => Err(reason.to_string()),
+    }
+}
+
+// Assemble a source file to an object file
+fn assemble(args: &[String]) -> Result<(), String> {
+    let (paths, options) = parse_options(args, &[], &["-o", "-l", "--endian"])?;
+    let [path] = &paths[..] else {
+        return Err(USAGE.to_string());
+    };
+
+    let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let (object, listing) = Assembler::new()
+        .with_endian(endian(&options)?)
+        .assemble_source(&source, path)?;
+    let output = options
+        .get("-o")
+        .cloned()
+        .unwrap_or_else(|| with_extension(path, "obj"));
+    object.save(&output)?;
+
+    // Write listing
+    match options.get("-l") {
+        Some(listing_path) => listing.save(listing_path),
+        None => Ok(()),
+    }
+}
+
+// Link object files to an image and its symbol file
+fn link(args: &[String]) -> Result<(), String> {
+    let (paths, options) = parse_options(args, &[], &["-m", "-o", "--machine"])?;
+    if paths.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    // Load objects and memory map
+    let objects = paths
+        .iter()
+        .map(|path| Object::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let map = memory_map(&options)?;
+
+    // Write image and symbols
+    let image = linker::link(&objects, &map)?;
+    let output = options
+        .get("-o")
+        .cloned()
+        .unwrap_or_else(|| String::from("a.bin"));
+    fs::write(&output, &image.bytes).map_err(|error| format!("{}: {}", output, error))?;
+    image.symbols.save(&with_extension(&output, "sym"))
+}
+
+// Compile a source file to an image, or to assembler source with -S
+fn compile(args: &[String]) -> Result<(), String> {
+    let (paths, options) = parse_options(args, &["-S"], &["-o", "--endian", "--machine"])?;
+    let [path] = &paths[..] else {
+        return Err(USAGE.to_string());
+    };
+
+    // Write assembler source
+    let source = compiler::compile_file(path)?;
+    let assembly_path = with_extension(path, "asm");
+    if options.contains_key("-S") {
+        let output = options.get("-o").unwrap_or(&assembly_path);
+        return fs::write(output, source).map_err(|error| format!("{}: {}", output, error));
+    }
+
+    // Assemble and link for the machine, lines refer to the assembler source
+    let endian = match options.contains_key("--endian") {
+        true => endian(&options)?,
+        false => machine(&options)?.endian,
+    };
+    let (object, _) = Assembler::new()
+        .with_endian(endian)
+        .assemble_source(&source, &assembly_path)?;
+    let image = linker::link(&[object], &memory_map(&options)?)?;
+    let output = options
+        .get("-o")
+        .cloned()
+        .unwrap_or_else(|| with_extension(path, "bin"));
+    fs::write(&output, &image.bytes).map_err(|error| format!("{}: {}", output, error))?;
+    image.symbols.save(&with_extension(&output, "sym"))
+}
 
-    // Run the program
-    cpu.run(DEBUG);
+// Split arguments in paths and options, flags get an empty value
+fn parse_options(
+    args: &[String],
+    flags: &[&str],
+    options: &[&str],
+) -> Result<(Vec<String>, HashMap<String, String>), String> {
+    let mut paths = Vec::new();
+    let mut values = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if flags.contains(&arg.as_str()) {
+            values.insert(arg.clone(), String::new());
+        } else if options.contains(&arg.as_str()) {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("option {} needs a value", arg))?;
+            values.insert(arg.clone(), value.clone());
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option {}\n{}", arg, USAGE));
+        } else {
+            paths.push(arg.clone());
+        }
+    }
+
+    Ok((paths, values))
 }
 
-// Hardcode a program to memory
-fn hardcode(mm: &mut DeviceMapper) {
-    let program_address = 0;
-
-    // Move 0xFFFF in register 1
-    mm.set_byte(MOV_LIT_REG, program_address + 0);
-    mm.set_byte(0xFF, program_address + 1);
-    mm.set_byte(0xFF, program_address + 2); // 0xFFFF
-    mm.set_byte(R1, program_address + 3);
-
-    // Move 0xEEEE in register 2
-    mm.set_byte(MOV_LIT_REG, program_address + 4);
-    mm.set_byte(0xEE, program_address + 5);
-    mm.set_byte(0xEE, program_address + 6); // 0xEEEE
-    mm.set_byte(R2, program_address + 7);
-
-    // Move 0xDDDD in memory address 0x0F00
-    mm.set_byte(MOV_LIT_MEM, program_address + 8);
-    mm.set_byte(0xDD, program_address + 9);
-    mm.set_byte(0xDD, program_address + 10); //0xDDDD
-    mm.set_byte(0x0F, program_address + 11);
-    mm.set_byte(0x00, program_address + 12); // 0x0F00
-
-    // Move 0xCCCC in memory address 0x0F02
-    mm.set_byte(MOV_LIT_MEM, program_address + 13);
-    mm.set_byte(0xCC, program_address + 14);
-    mm.set_byte(0xCC, program_address + 15); //0xCCCC
-    mm.set_byte(0x0F, program_address + 16);
-    mm.set_byte(0x02, program_address + 17); // 0x0F02
-
-    // Move 0x0002 in register 3
-    mm.set_byte(MOV_LIT_REG, program_address + 18);
-    mm.set_byte(0x00, program_address + 19);
-    mm.set_byte(0x02, program_address + 20); // 0x0002
-    mm.set_byte(R3, program_address + 21);
-
-    // Move 0x0F00 with offset from register 3 memory address to register 4
-    mm.set_byte(MOV_LIT_OFF_REG, program_address + 22);
-    mm.set_byte(0x0F, program_address + 23);
-    mm.set_byte(0x00, program_address + 24);
-    mm.set_byte(R3, program_address + 25);
-    mm.set_byte(R4, program_address + 26);
-
-    // Move 0x0000 with offset from register 3 memory address to register 7
-    mm.set_byte(MOV_LIT_OFF_REG, program_address + 27);
-    mm.set_byte(0x00, program_address + 28);
-    mm.set_byte(0x00, program_address + 29);
-    mm.set_byte(R3, program_address + 30);
-    mm.set_byte(R7, program_address + 31);
-
-    // Move register 1 in register 5
-    mm.set_byte(MOV_REG_REG, program_address + 32);
-    mm.set_byte(R1, program_address + 33);
-    mm.set_byte(R5, program_address + 34);
-
-    // Move register 2 in register 6
-    mm.set_byte(MOV_REG_REG, program_address + 35);
-    mm.set_byte(R2, program_address + 36);
-    mm.set_byte(R6, program_address + 37);
-
-    // Move register 1 in memory address 0x0F04
-    mm.set_byte(MOV_REG_MEM, program_address + 38);
-    mm.set_byte(R1, program_address + 39);
-    mm.set_byte(0x0F, program_address + 40);
-    mm.set_byte(0x04, program_address + 41);
-
-    // Move register 2 in memory address 0x0F06
-    mm.set_byte(MOV_REG_MEM, program_address + 42);
-    mm.set_byte(R2, program_address + 43);
-    mm.set_byte(0x0F, program_address + 44);
-    mm.set_byte(0x06, program_address + 45);
-
-    // Move value on memory address in register 4 in register 6
-    mm.set_byte(MOV_REG_PTR_REG, program_address + 46);
-    mm.set_byte(R4, program_address + 47);
-    mm.set_byte(R6, program_address + 48);
-
-    // Move value on memory address in register 3 in register 5
-    mm.set_byte(MOV_REG_PTR_REG, program_address + 49);
-    mm.set_byte(R3, program_address + 50);
-    mm.set_byte(R5, program_address + 51);
-
-    // Move memory address 0x0F00 in register 8
-    mm.set_byte(MOV_MEM_REG, program_address + 52);
-    mm.set_byte(0x0F, program_address + 53);
-    mm.set_byte(0x00, program_address + 54);
-    mm.set_byte(R8, program_address + 55);
-
-    // Move memory address 0x0F01 in register 7
-    mm.set_byte(MOV_MEM_REG, program_address + 56);
-    mm.set_byte(0x0F, program_address + 57);
-    mm.set_byte(0x01, program_address + 58);
-    mm.set_byte(R7, program_address + 59);
-
-    // Reset registers by MOV_LIT_REG 0x0000 in RX
-    mm.set_byte(MOV_LIT_REG, program_address + 60);
-    mm.set_byte(0x00, program_address + 61);
-    mm.set_byte(0x00, program_address + 62);
-    mm.set_byte(R1, program_address + 63);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 64);
-    mm.set_byte(0x00, program_address + 65);
-    mm.set_byte(0x00, program_address + 66);
-    mm.set_byte(R2, program_address + 67);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 68);
-    mm.set_byte(0x00, program_address + 69);
-    mm.set_byte(0x00, program_address + 70);
-    mm.set_byte(R3, program_address + 71);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 72);
-    mm.set_byte(0x00, program_address + 73);
-    mm.set_byte(0x00, program_address + 74);
-    mm.set_byte(R4, program_address + 75);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 76);
-    mm.set_byte(0x00, program_address + 77);
-    mm.set_byte(0x00, program_address + 78);
-    mm.set_byte(R5, program_address + 79);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 80);
-    mm.set_byte(0x00, program_address + 81);
-    mm.set_byte(0x00, program_address + 82);
-    mm.set_byte(R6, program_address + 83);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 84);
-    mm.set_byte(0x00, program_address + 85);
-    mm.set_byte(0x00, program_address + 86);
-    mm.set_byte(R7, program_address + 87);
-
-    mm.set_byte(MOV_LIT_REG, program_address + 88);
-    mm.set_byte(0x00, program_address + 89);
-    mm.set_byte(0x00, program_address + 90);
-    mm.set_byte(R8, program_address + 91);
-
-    // Move 0x0008 in register 1
-    mm.set_byte(MOV_LIT_REG, program_address + 92);
-    mm.set_byte(0x00, program_address + 93);
-    mm.set_byte(0x08, program_address + 94);
-    mm.set_byte(R1, program_address + 95);
-
-    // Move 0x0008 in register 2
-    mm.set_byte(MOV_LIT_REG, program_address + 96);
-    mm.set_byte(0x00, program_address + 97);
-    mm.set_byte(0x08, program_address + 98);
-    mm.set_byte(R2, program_address + 99);
-
-    // Add register 1 to register 2
-    mm.set_byte(ADD_REG_REG, program_address + 100);
-    mm.set_byte(R1, program_address + 101);
-    mm.set_byte(R2, program_address + 102);
-
-    // Add register 0x00FF to register 2
-    mm.set_byte(ADD_LIT_REG, program_address + 103);
-    mm.set_byte(0x00, program_address + 104);
-    mm.set_byte(0xFF, program_address + 105);
-    mm.set_byte(R2, program_address + 106);
-
-    // Subtract 0x0001 from register 2
-    mm.set_byte(SUB_LIT_REG, program_address + 107);
-    mm.set_byte(0x00, program_address + 108);
-    mm.set_byte(0x01, program_address + 109);
-    mm.set_byte(R2, program_address + 110);
-
-    // Subtract register 1 from register 2
-    mm.set_byte(SUB_REG_REG, program_address + 111);
-    mm.set_byte(R1, program_address + 112);
-    mm.set_byte(R2, program_address + 113);
-
-    // Subtract Register 2 from 0x0005
-    mm.set_byte(SUB_LIT_REG, program_address + 114);
-    mm.set_byte(0x00, program_address + 115);
-    mm.set_byte(0x05, program_address + 116);
-    mm.set_byte(R2, program_address + 117);
-
-    // Multiply 0x0002 with register 2
-    mm.set_byte(MUL_LIT_REG, program_address + 118);
-    mm.set_byte(0x00, program_address + 119);
-    mm.set_byte(0x02, program_address + 120);
-    mm.set_byte(R2, program_address + 121);
-
-    // Multiply register 2 by register 1
-    mm.set_byte(MUL_REG_REG, program_address + 122);
-    mm.set_byte(R2, program_address + 123);
-    mm.set_byte(R1, program_address + 124);
-
-    // Increment register 2
-    mm.set_byte(INC_REG, program_address + 125);
-    mm.set_byte(R2, program_address + 126);
-
-    // Decrement register 2
-    mm.set_byte(DEC_REG, program_address + 127);
-    mm.set_byte(R2, program_address + 128);
-
-    // Left shift register 2 by 1
-    mm.set_byte(LSH_REG_LIT, program_address + 129);
-    mm.set_byte(R2, program_address + 130);
-    mm.set_byte(0x00, program_address + 131);
-    mm.set_byte(0x01, program_address + 132);
-
-    // Left shift register 2 by 1
-    mm.set_byte(LSH_REG_LIT, program_address + 133);
-    mm.set_byte(R2, program_address + 134);
-    mm.set_byte(0x00, program_address + 135);
-    mm.set_byte(0x01, program_address + 136);
-
-    // Move 0x0002 in register 3
-    mm.set_byte(MOV_LIT_REG, program_address + 137);
-    mm.set_byte(0x00, program_address + 138);
-    mm.set_byte(0x02, program_address + 139);
-    mm.set_byte(R3, program_address + 140);
-
-    // Right shift register 2 by 2
-    mm.set_byte(RSH_REG_REG, program_address + 141);
-    mm.set_byte(R2, program_address + 142);
-    mm.set_byte(R3, program_address + 143);
-
-    // Halt
-    mm.set_byte(HLT, program_address + 144);
+// Replace the extension of a path
+fn with_extension(path: &str, extension: &str) -> String {
+    Path::new(path)
+        .with_extension(extension)
+        .to_string_lossy()
+        .into_owned()
 }
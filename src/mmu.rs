@@ -0,0 +1,165 @@
+use crate::device_mapper::DeviceMapper;
+use crate::fault::{Access, Fault};
+
+// The 64K address space is split in 256 pages of 256 bytes. A page table
+// holds one 16-bit entry per virtual page, the high byte is the physical
+// page and the low byte holds the flags below.
+pub const PAGE_SIZE: u16 = 0x0100;
+pub const PAGE_COUNT: u16 = 0x0100;
+
+// Page table entry flags
+pub const PAGE_PRESENT: u16 = 0x01;
+pub const PAGE_WRITE: u16 = 0x02;
+pub const PAGE_EXECUTE: u16 = 0x04;
+pub const PAGE_USER: u16 = 0x08;
+
+// MMU class
+#[allow(clippy::upper_case_acronyms)]
+pub struct MMU {
+    enabled: bool,
+    page_table: u16,
+}
+
+// Default MMU
+impl Default for MMU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// MMU implementation
+impl MMU {
+    // Start with identity mapping
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            page_table: 0x0000,
+        }
+    }
+
+    // Translate virtual addresses through the page table at address
+    pub fn load_page_table(&mut self, address: u16) {
+        self.page_table = address;
+        self.enabled = true;
+    }
+
+    // Go back to identity mapping
+    pub fn unload_page_table(&mut self) {
+        self.enabled = false;
+    }
+
+    // Check if addresses are translated
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Page table base register
+    pub fn page_table(&self) -> u16 {
+        self.page_table
+    }
+
+    // Translate a virtual address to a physical address
+    pub fn translate(
+        &self,
+        device_mapper: &DeviceMapper,
+        address: u16,
+        access: Access,
+    ) -> Result<u16, Fault> {
+        // Identity mapping
+        if !self.enabled {
+            return Ok(address);
+        }
+
+        // Read page table entry, the table itself lives in physical memory and
+        // is read in any mode
+        let page = address / PAGE_SIZE;
+        let entry_address = self.page_table.wrapping_add(page * 2);
+        let entry = device_mapper.privileged_uint_16(entry_address)?;
+
+        // Check flags
+        let allowed = match access {
+            Access::Read => true,
+            Access::Write => entry & PAGE_WRITE != 0,
+            Access::Execute => entry & PAGE_EXECUTE != 0,
+        };
+        let user_allowed = device_mapper.is_supervisor() || entry & PAGE_USER != 0;
+        if entry & PAGE_PRESENT == 0 || !allowed || !user_allowed {
+            return Err(Fault::Page { address, access });
+        }
+
+        // Physical address
+        let frame = entry >> 8;
+        Ok(frame * PAGE_SIZE + address % PAGE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{Device, DeviceType};
+
+    // Memory at $0000-$7FFF and a supervisor-only page table at $8000
+    fn machine() -> DeviceMapper {
+        let mut mm = DeviceMapper::new();
+        mm.map(
+            Device::new(0x8000, DeviceType::Memory),
+            0x0000,
+            0x7FFF,
+            false,
+        );
+        mm.map(
+            Device::new(0x0200, DeviceType::Memory),
+            0x8000,
+            0x81FF,
+            true,
+        )
+        .set_supervisor_only(true);
+
+        // Virtual page $01 is physical page $02, for user mode code
+        let entry = 0x0200 | PAGE_PRESENT | PAGE_EXECUTE | PAGE_USER;
+        mm.set_byte((entry >> 8) as u8, 0x8002);
+        mm.set_byte(entry as u8, 0x8003);
+        mm
+    }
+
+    #[test]
+    fn user_mode_walks_supervisor_only_page_table() {
+        let mut mm = machine();
+        mm.set_supervisor(false);
+        let mut mmu = MMU::new();
+        mmu.load_page_table(0x8000);
+
+        assert_eq!(mmu.translate(&mm, 0x0142, Access::Execute), Ok(0x0242));
+        assert_eq!(
+            mmu.translate(&mm, 0x0142, Access::Write),
+            Err(Fault::Page {
+                address: 0x0142,
+                access: Access::Write
+            })
+        );
+
+        // The table itself stays protected from user mode accesses
+        assert_eq!(
+            mm.get_uint_8(0x8002),
+            Err(Fault::Protection {
+                address: 0x8002,
+                access: Access::Read
+            })
+        );
+    }
+
+    #[test]
+    fn unmapped_page_table_faults() {
+        let mm = machine();
+        let mut mmu = MMU::new();
+        mmu.load_page_table(0xF000);
+
+        assert_eq!(
+            mmu.translate(&mm, 0x0142, Access::Read),
+            Err(Fault::Unmapped {
+                address: 0xF002,
+                access: Access::Read
+            })
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use crate::device::Endian;
+use crate::entries::{entries, parse_u16};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+
+// Object files
+//
+// Written by the assembler and combined by the linker. Code and data live in
+// named sections that start at offset 0, the linker decides where they go
+// unless the section has a fixed address.
+// Every 16-bit field holding an address is listed as a relocation, so it can
+// be patched once the address is known. One entry per line, numbers are `$`
+// hex or decimal, `;` starts a comment:
+//
+//     endian <big|little>
+//     section <name> <size> <alignment> [address]
+//     bytes <section> <offset> <byte>...
+//     symbol <global|local> <name> <section> <offset> <size>
+//     import <name>
+//     reloc <section> <offset> <section|symbol> <name> <addend>
+//     line <section> <offset> <file>:<line>
+//     data <name> <section> <offset> <size>
+//
+// The location of a line entry is the rest of the line, so file names may
+// hold spaces.
+// Words, relocated fields too, are in the byte order of the memory the
+// program runs from, big-endian unless the object has an endian entry.
+
+// Section
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub alignment: u16,
+    pub address: Option<u16>,
+}
+
+// Symbol defined in a section
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: String,
+    pub offset: u16,
+    pub size: u16,
+    pub global: bool,
+}
+
+// What a relocation points at
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    // Start of a section of the same object
+    Section(String),
+    // Global symbol of any object
+    Symbol(String),
+}
+
+// 16-bit field to patch with the address of the target plus the addend
+#[derive(Clone, Debug, PartialEq)]
+pub struct Relocation {
+    pub section: String,
+    pub offset: u16,
+    pub target: Target,
+    pub addend: u16,
+}
+
+// First byte of a source line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub section: String,
+    pub offset: u16,
+    pub file: String,
+    pub line: usize,
+}
+
+// Bytes that are data, not code
+#[derive(Clone, Debug, PartialEq)]
+pub struct Data {
+    pub name: String,
+    pub section: String,
+    pub offset: u16,
+    pub size: u16,
+}
+
+// Object class
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub endian: Endian,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    pub lines: Vec<Line>,
+    pub data: Vec<Data>,
+}
+
+// Default Object
+impl Default for Object {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Object implementation
+impl Object {
+    pub fn new() -> Self {
+        Self {
+            endian: Endian::Big,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+            imports: Vec::new(),
+            relocations: Vec::new(),
+            lines: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    // Load an object file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        Self::parse(&text).map_err(|error| format!("{}:{}", path, error))
+    }
+
+    // Write the object file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    // Find a section by name
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    // Find a section by name, creating it when missing
+    pub fn section_mut(&mut self, name: &str) -> &mut Section {
+        let index = match self
+            .sections
+            .iter()
+            .position(|section| section.name == name)
+        {
+            Some(index) => index,
+            None => {
+                self.sections.push(Section {
+                    name: name.to_string(),
+                    bytes: Vec::new(),
+                    alignment: 1,
+                    address: None,
+                });
+                self.sections.len() - 1
+            }
+        };
+        &mut self.sections[index]
+    }
+
+    // Check that size bytes from offset are inside a section
+    pub fn check_range(&self, section: &str, offset: u16, size: u16) -> Result<(), String> {
+        let Some(section) = self.section(section) else {
+            return Err(format!("unknown section {}", section));
+        };
+        if offset as usize + size as usize > section.bytes.len() {
+            return Err(format!(
+                "offset ${:04X} is past the end of section {}",
+                offset, section.name
+            ));
+        }
+        Ok(())
+    }
+
+    // Parse the text of an object file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut object = Self::new();
+        for entry in entries(text) {
+            let error = |message: &str| entry.error(message);
+            let number = |text: &str| parse_u16(text).ok_or_else(|| error("invalid number"));
+            match entry.fields[..] {
+                ["endian", "big"] => object.endian = Endian::Big,
+                ["endian", "little"] => object.endian = Endian::Little,
+                ["section", name, size, alignment, ref address @ ..] if address.len() < 2 => {
+                    let bytes = vec![0; number(size)? as usize];
+                    let alignment = number(alignment)?;
+                    if !alignment.is_power_of_two() {
+                        return Err(error("alignment must be a power of two"));
+                    }
+                    let address = match address.first() {
+                        Some(address) => Some(number(address)?),
+                        None => None,
+                    };
+                    let section = object.section_mut(name);
+                    section.bytes = bytes;
+                    section.alignment = alignment;
+                    section.address = address;
+                }
+                ["bytes", section, offset, ref bytes @ ..] => {
+                    let offset = number(offset)? as usize;
+                    let section = object
+                        .sections
+                        .iter_mut()
+                        .find(|other| other.name == section)
+                        .ok_or_else(|| error("unknown section"))?;
+                    if offset + bytes.len() > section.bytes.len() {
+                        return Err(error("bytes past the end of the section"));
+                    }
+                    for (index, byte) in bytes.iter().enumerate() {
+                        section.bytes[offset + index] =
+                            u8::from_str_radix(byte, 16).map_err(|_| error("invalid byte"))?;
+                    }
+                }
+                ["symbol", scope @ ("global" | "local"), name, section, offset, size] => {
+                    let (offset, size) = (number(offset)?, number(size)?);
+                    object
+                        .check_range(section, offset, 0)
+                        .map_err(|message| error(&message))?;
+                    object.symbols.push(Symbol {
+                        name: name.to_string(),
+                        section: section.to_string(),
+                        offset,
+                        size,
+                        global: scope == "global",
+                    })
+                }
+                ["import", name] => object.imports.push(name.to_string()),
+                ["reloc", section, offset, kind @ ("section" | "symbol"), name, addend] => {
+                    let target = match kind {
+                        "section" => Target::Section(name.to_string()),
+                        _ => Target::Symbol(name.to_string()),
+                    };
+                    let offset = number(offset)?;
+                    object
+                        .check_range(section, offset, 2)
+                        .map_err(|message| error(&message))?;
+                    object.relocations.push(Relocation {
+                        section: section.to_string(),
+                        offset,
+                        target,
+                        addend: number(addend)?,
+                    });
+                }
+                ["line", section, offset, _, ..] => {
+                    let (file, line) = entry
+                        .rest(3)
+                        .rsplit_once(':')
+                        .ok_or_else(|| error("expected file:line"))?;
+                    let offset = number(offset)?;
+                    object
+                        .check_range(section, offset, 0)
+                        .map_err(|message| error(&message))?;
+                    object.lines.push(Line {
+                        section: section.to_string(),
+                        offset,
+                        file: file.to_string(),
+                        line: line.parse().map_err(|_| error("invalid line number"))?,
+                    });
+                }
+                ["data", name, section, offset, size] => {
+                    let (offset, size) = (number(offset)?, number(size)?);
+                    object
+                        .check_range(section, offset, size)
+                        .map_err(|message| error(&message))?;
+                    object.data.push(Data {
+                        name: name.to_string(),
+                        section: section.to_string(),
+                        offset,
+                        size,
+                    })
+                }
+                _ => return Err(entry.invalid()),
+            }
+        }
+
+        Ok(object)
+    }
+}
+
+// Write the object file format
+impl Display for Object {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.endian == Endian::Little {
+            writeln!(f, "endian little")?;
+        }
+        for section in self.sections.iter() {
+            write!(
+                f,
+                "section {} ${:04X} ${:04X}",
+                section.name,
+                section.bytes.len(),
+                section.alignment
+            )?;
+            match section.address {
+                Some(address) => writeln!(f, " ${:04X}", address)?,
+                None => writeln!(f)?,
+            }
+            for (index, chunk) in section.bytes.chunks(16).enumerate() {
+                // Sections are zeroed, leave out empty chunks
+                if chunk.iter().all(|byte| *byte == 0) {
+                    continue;
+                }
+
+                let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+                writeln!(
+                    f,
+                    "bytes {} ${:04X} {}",
+                    section.name,
+                    index * 16,
+                    bytes.join(" ")
+                )?;
+            }
+        }
+        for symbol in self.symbols.iter() {
+            let scope = if symbol.global { "global" } else { "local" };
+            writeln!(
+                f,
+                "symbol {} {} {} ${:04X} ${:04X}",
+                scope, symbol.name, symbol.section, symbol.offset, symbol.size
+            )?;
+        }
+        for import in self.imports.iter() {
+            writeln!(f, "import {}", import)?;
+        }
+        for relocation in self.relocations.iter() {
+            let (kind, name) = match &relocation.target {
+                Target::Section(name) => ("section", name),
+                Target::Symbol(name) => ("symbol", name),
+            };
+            writeln!(
+                f,
+                "reloc {} ${:04X} {} {} ${:04X}",
+                relocation.section, relocation.offset, kind, name, relocation.addend
+            )?;
+        }
+        for line in self.lines.iter() {
+            writeln!(
+                f,
+                "line {} ${:04X} {}:{}",
+                line.section, line.offset, line.file, line.line
+            )?;
+        }
+
+        for data in self.data.iter() {
+            writeln!(
+                f,
+                "data {} {} ${:04X} ${:04X}",
+                data.name, data.section, data.offset, data.size
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_must_name_a_section() {
+        let text = "section text $0002 $0001\nsymbol local foo nosuch $0000 $0000\n";
+        assert_eq!(
+            Object::parse(text).unwrap_err(),
+            "2: unknown section nosuch"
+        );
+    }
+
+    #[test]
+    fn relocations_must_fit_in_their_section() {
+        let text = "section text $0002 $0001\nreloc text $0001 section text $0000\n";
+        assert_eq!(
+            Object::parse(text).unwrap_err(),
+            "2: offset $0001 is past the end of section text"
+        );
+
+        let text = "section text $0002 $0001\nreloc text $0000 section text $0000\n";
+        assert!(Object::parse(text).is_ok());
+    }
+
+    #[test]
+    fn written_objects_parse_back() {
+        let mut object = Object::new();
+        object.endian = Endian::Little;
+        object.section_mut("text").bytes = vec![0x10, 0x00, 0x00, 0x02];
+        object.symbols.push(Symbol {
+            name: String::from("start"),
+            section: String::from("text"),
+            offset: 0x0000,
+            size: 0x0004,
+            global: true,
+        });
+        object.relocations.push(Relocation {
+            section: String::from("text"),
+            offset: 0x0001,
+            target: Target::Section(String::from("text")),
+            addend: 0x0004,
+        });
+        object.lines.push(Line {
+            section: String::from("text"),
+            offset: 0x0000,
+            file: String::from("my dir/x.asm"),
+            line: 2,
+        });
+
+        let parsed = Object::parse(&object.to_string()).unwrap();
+        assert_eq!(parsed.endian, Endian::Little);
+        assert_eq!(parsed.sections, object.sections);
+        assert_eq!(parsed.symbols, object.symbols);
+        assert_eq!(parsed.relocations, object.relocations);
+        assert_eq!(parsed.lines, object.lines);
+    }
+}
@@ -0,0 +1,127 @@
+// Opcodes
+//
+// Every instruction starts with its opcode byte, followed by its operands.
+// Registers are encoded by their number below, literals and addresses are
+// 16-bit words in the byte order of the memory they are in.
+
+// Registers by operand number
+pub const IP: u8 = 0x00;
+pub const ACC: u8 = 0x01;
+pub const R1: u8 = 0x02;
+pub const R2: u8 = 0x03;
+pub const R3: u8 = 0x04;
+pub const R4: u8 = 0x05;
+pub const R5: u8 = 0x06;
+pub const R6: u8 = 0x07;
+pub const R7: u8 = 0x08;
+pub const R8: u8 = 0x09;
+pub const SP: u8 = 0x0A;
+pub const FP: u8 = 0x0B;
+pub const SB: u8 = 0x0C;
+pub const SL: u8 = 0x0D;
+
+// Instructions for the CPU
+
+// Move instructions
+pub const MOV_LIT_REG: u8 = 0x10;
+pub const MOV_REG_REG: u8 = 0x11;
+pub const MOV_REG_MEM: u8 = 0x12;
+pub const MOV_MEM_REG: u8 = 0x13;
+pub const MOV_LIT_MEM: u8 = 0x14;
+pub const MOV_REG_PTR_REG: u8 = 0x15;
+pub const MOV_LIT_OFF_REG: u8 = 0x16;
+
+// Byte move instructions, loads zero-extend (MOV8) or sign-extend (MOV8S)
+pub const MOV8_REG_MEM: u8 = 0x17;
+pub const MOV8_MEM_REG: u8 = 0x18;
+pub const MOV8S_MEM_REG: u8 = 0x19;
+pub const MOV8_LIT_MEM: u8 = 0x1A;
+pub const MOV8_REG_PTR_REG: u8 = 0x1B;
+pub const MOV8S_REG_PTR_REG: u8 = 0x1C;
+pub const MOV8_REG_REG_PTR: u8 = 0x1D;
+
+// Addressed move instructions, the memory operand is an addressing mode byte
+// followed by a 16-bit offset for ADR_OFFSET
+pub const MOV_ADR_REG: u8 = 0x1E;
+pub const MOV_REG_ADR: u8 = 0x1F;
+
+// Addressing modes, bits 7-6 of the mode byte
+pub const ADR_INDIRECT: u8 = 0x00; // [reg]
+pub const ADR_OFFSET: u8 = 0x40; // [reg + offset], also fp relative locals and arguments
+pub const ADR_POST_INC: u8 = 0x80; // [reg], then reg += size
+pub const ADR_PRE_DEC: u8 = 0xC0; // reg -= size, then [reg]
+
+// Addressing mode size flags, bits 5-4 of the mode byte, the base register is in bits 3-0
+pub const ADR_BYTE: u8 = 0x20;
+pub const ADR_SIGNED: u8 = 0x10;
+
+// Arithmetic instructions
+pub const ADD_REG_REG: u8 = 0x20;
+pub const ADD_LIT_REG: u8 = 0x21;
+pub const SUB_LIT_REG: u8 = 0x22;
+pub const SUB_REG_LIT: u8 = 0x23;
+pub const SUB_REG_REG: u8 = 0x24;
+pub const INC_REG: u8 = 0x25;
+pub const DEC_REG: u8 = 0x26;
+pub const MUL_LIT_REG: u8 = 0x27;
+pub const MUL_REG_REG: u8 = 0x28;
+
+// Binary manipulation instructions
+pub const LSH_REG_LIT: u8 = 0x30;
+pub const LSH_REG_REG: u8 = 0x31;
+pub const RSH_REG_LIT: u8 = 0x32;
+pub const RSH_REG_REG: u8 = 0x33;
+pub const AND_REG_LIT: u8 = 0x34;
+pub const AND_REG_REG: u8 = 0x35;
+pub const OR_REG_LIT: u8 = 0x36;
+pub const OR_REG_REG: u8 = 0x37;
+pub const XOR_REG_LIT: u8 = 0x38;
+pub const XOR_REG_REG: u8 = 0x39;
+pub const NOT: u8 = 0x3A;
+pub const ROL_REG_LIT: u8 = 0x3B;
+pub const ROL_REG_REG: u8 = 0x3C;
+pub const ROR_REG_LIT: u8 = 0x3D;
+pub const ROR_REG_REG: u8 = 0x3E;
+
+// Branching instructions
+pub const JNE_REG: u8 = 0x40;
+pub const JNE_LIT: u8 = 0x41;
+pub const JEQ_REG: u8 = 0x42;
+pub const JEQ_LIT: u8 = 0x43;
+pub const JLT_REG: u8 = 0x44;
+pub const JLT_LIT: u8 = 0x45;
+pub const JGT_REG: u8 = 0x46;
+pub const JGT_LIT: u8 = 0x47;
+pub const JLE_REG: u8 = 0x48;
+pub const JLE_LIT: u8 = 0x49;
+pub const JGE_REG: u8 = 0x4A;
+pub const JGE_LIT: u8 = 0x4B;
+
+// Miscellaneous instructions
+pub const PSH_LIT: u8 = 0x50;
+pub const PSH_REG: u8 = 0x51;
+pub const POP: u8 = 0x52;
+pub const CAL_LIT: u8 = 0x53;
+pub const CAL_REG: u8 = 0x54;
+pub const RET: u8 = 0x55;
+pub const HLT: u8 = 0x56;
+pub const JSR_LIT: u8 = 0x57;
+pub const JSR_REG: u8 = 0x58;
+pub const RTS_LIT: u8 = 0x59;
+
+// System instructions
+pub const LPT_LIT: u8 = 0x60;
+pub const LPT_REG: u8 = 0x61;
+pub const UPT: u8 = 0x62;
+pub const SYSCALL: u8 = 0x63;
+pub const SYSRET: u8 = 0x64;
+
+// Bit instructions
+pub const TST_REG_LIT: u8 = 0x70;
+pub const TST_REG_REG: u8 = 0x71;
+pub const SET_REG_LIT: u8 = 0x72;
+pub const SET_REG_REG: u8 = 0x73;
+pub const CLR_REG_LIT: u8 = 0x74;
+pub const CLR_REG_REG: u8 = 0x75;
+pub const CNT_REG: u8 = 0x76;
+pub const SWP_REG: u8 = 0x77;
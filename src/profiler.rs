@@ -0,0 +1,287 @@
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Executions and cycles counted for one address or function
+#[derive(Clone, Copy, Default)]
+pub struct Counts {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+// Profiler class
+//
+// Counts every instruction the CPU runs. Time is attributed to functions by
+// following calls (CAL, JSR, SYSCALL and exceptions) and returns (RET, RTS
+// and SYSRET) on a shadow call stack. Functions are named by their label
+// when symbols are given, by their entry address otherwise.
+pub struct Profiler {
+    addresses: HashMap<u16, Counts>,
+    functions: HashMap<u16, Counts>,
+    stacks: HashMap<Vec<u16>, u64>,
+    call_stack: Vec<u16>,
+    total_cycles: u64,
+}
+
+// Default Profiler
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Profiler implementation
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            addresses: HashMap::new(),
+            functions: HashMap::new(),
+            stacks: HashMap::new(),
+            call_stack: Vec::new(),
+            total_cycles: 0,
+        }
+    }
+
+    // Count an instruction at address that took cycles
+    pub fn record(&mut self, address: u16, cycles: u64) {
+        // The program entry is the root function
+        if self.call_stack.is_empty() {
+            self.enter(address);
+        }
+
+        // Count address
+        let counts = self.addresses.entry(address).or_default();
+        counts.executions += 1;
+        counts.cycles += cycles;
+
+        // Count self time of the current function
+        let function = *self.call_stack.last().unwrap();
+        self.functions.entry(function).or_default().cycles += cycles;
+
+        // Count the whole call stack
+        *self.stacks.entry(self.call_stack.clone()).or_default() += cycles;
+        self.total_cycles += cycles;
+    }
+
+    // A function at address was called
+    pub fn enter(&mut self, address: u16) {
+        self.call_stack.push(address);
+        self.functions.entry(address).or_default().executions += 1;
+    }
+
+    // The current function returned
+    pub fn leave(&mut self) {
+        // Never pop the root, a return from it is a return to unknown code
+        if self.call_stack.len() > 1 {
+            self.call_stack.pop();
+        }
+    }
+
+    // Name of a function
+    fn function_name(&self, address: u16, symbols: Option<&Symbols>) -> String {
+        match symbols.and_then(|symbols| symbols.label(address)) {
+            Some((label, 0)) => label.name.clone(),
+            Some((label, offset)) => format!("{}+{}", label.name, offset),
+            None => format!("0x{:04X}", address),
+        }
+    }
+
+    // Cycles spent in a function and everything it called
+    fn total_cycles(&self, function: u16) -> u64 {
+        self.stacks
+            .iter()
+            .filter(|(stack, _)| stack.contains(&function))
+            .map(|(_, cycles)| cycles)
+            .sum()
+    }
+
+    // Percentage of all cycles
+    fn percentage(&self, cycles: u64) -> f64 {
+        if self.total_cycles == 0 {
+            return 0.0;
+        }
+
+        cycles as f64 * 100.0 / self.total_cycles as f64
+    }
+
+    // Write a report of the hottest functions and addresses
+    pub fn report(&self, symbols: Option<&Symbols>, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "Total cycles: {}", self.total_cycles)?;
+        writeln!(out)?;
+
+        // Functions, sorted by self cycles
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        writeln!(
+            out,
+            "{:<24} {:>10} {:>12} {:>7} {:>12} {:>7}",
+            "Function", "Calls", "Self", "%", "Total", "%"
+        )?;
+        for (function, counts) in functions {
+            let total = self.total_cycles(*function);
+            writeln!(
+                out,
+                "{:<24} {:>10} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                self.function_name(*function, symbols),
+                counts.executions,
+                counts.cycles,
+                self.percentage(counts.cycles),
+                total,
+                self.percentage(total)
+            )?;
+        }
+        writeln!(out)?;
+
+        // Addresses, sorted by cycles
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        writeln!(
+            out,
+            "{:<24} {:>10} {:>12} {:>7}",
+            "Address", "Executions", "Cycles", "%"
+        )?;
+        for (address, counts) in addresses {
+            writeln!(
+                out,
+                "{:<24} {:>10} {:>12} {:>6.2}%",
+                symbols.map_or(format!("0x{:04X}", address), |symbols| {
+                    symbols.format_address(*address)
+                }),
+                counts.executions,
+                counts.cycles,
+                self.percentage(counts.cycles)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Write the call stacks in collapsed format, one "root;caller;callee cycles"
+    // line per stack, as read by flamegraph tools
+    pub fn write_collapsed(
+        &self,
+        symbols: Option<&Symbols>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, cycles) in stacks {
+            let names: Vec<String> = stack
+                .iter()
+                .map(|function| self.function_name(*function, symbols))
+                .collect();
+            writeln!(out, "{} {}", names.join(";"), cycles)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{StopReason, CPU};
+    use crate::machine::{MachineBuilder, MachineConfig};
+    use crate::opcodes::*;
+
+    // Profile main calling f with CAL, g with JSR and handler with SYSCALL
+    fn profile() -> CPU {
+        let mut image = vec![0; 0x100A];
+        for (address, code) in [
+            (0x0000, &[PSH_LIT, 0x00, 0x00, CAL_LIT, 0x00, 0x40][..]),
+            (0x0006, &[JSR_LIT, 0x00, 0x60, SYSCALL, 0x00, 0x01, HLT]),
+            (0x0040, &[RET]),
+            (0x0060, &[RTS_LIT, 0x00, 0x00]),
+            (0x0080, &[SYSRET]),
+            (0x1008, &[0x00, 0x80]),
+        ] {
+            image[address..address + code.len()].copy_from_slice(code);
+        }
+        let mut cpu = MachineBuilder::new(MachineConfig::default_machine())
+            .with_program(image)
+            .build()
+            .unwrap();
+        cpu.enable_profiler();
+        assert_eq!(cpu.run(), StopReason::Halted);
+        cpu
+    }
+
+    fn symbols() -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, address) in [("main", 0x0000), ("f", 0x0040), ("g", 0x0060)] {
+            symbols.add_label(name, address, 0);
+        }
+        symbols.add_label("handler", 0x0080, 0);
+        symbols
+    }
+
+    #[test]
+    fn calls_are_attributed_to_their_function() {
+        let cpu = profile();
+        let mut out = Vec::new();
+        let profiler = cpu.profiler().unwrap();
+        profiler
+            .write_collapsed(Some(&symbols()), &mut out)
+            .unwrap();
+
+        // PSH, CAL, JSR, SYSCALL and HLT stay in main
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main 55\nmain;f 20\nmain;g 6\nmain;handler 24\n"
+        );
+    }
+
+    #[test]
+    fn stacks_without_symbols_use_addresses() {
+        let cpu = profile();
+        let mut out = Vec::new();
+        cpu.profiler()
+            .unwrap()
+            .write_collapsed(None, &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0x0000 55\n0x0000;0x0040 20\n0x0000;0x0060 6\n0x0000;0x0080 24\n"
+        );
+    }
+
+    #[test]
+    fn reports_list_functions_and_addresses() {
+        let cpu = profile();
+        let mut out = Vec::new();
+        let profiler = cpu.profiler().unwrap();
+        profiler.report(Some(&symbols()), &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Total cycles: 105");
+
+        // Hottest function first, main includes what it called
+        assert!(lines[3].starts_with("main "));
+        assert!(lines[3].ends_with("1           55  52.38%          105 100.00%"));
+        assert!(lines[4].starts_with("handler "));
+        assert!(lines[4].ends_with("1           24  22.86%           24  22.86%"));
+        assert!(lines[6].starts_with("g "));
+
+        // SYSCALL at main+9 is the hottest address
+        assert!(lines[8].starts_with("Address "));
+        assert!(lines[9].starts_with("0x0009 <main+9> "));
+        assert!(lines[9].ends_with("1           24  22.86%"));
+    }
+
+    #[test]
+    fn returns_never_pop_the_root() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x0000, 2);
+        profiler.enter(0x0100);
+        profiler.record(0x0100, 3);
+        profiler.leave();
+        profiler.leave();
+        profiler.record(0x0002, 4);
+        let mut out = Vec::new();
+        profiler.write_collapsed(None, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0x0000 6\n0x0000;0x0100 3\n"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Semihosting
+//
+// Lets guest programs use the host, so test programs can report pass or fail
+// to CI. Once enabled with CPU::enable_semihosting, a SYSCALL with one of
+// the numbers below is handled by the host instead of the exception handler.
+// Arguments are in r1 to r4, results in acc:
+//
+//     $FF00 exit       r1 status, the run stops with StopReason::Exit
+//     $FF01 print      r1 address of a 0-terminated string, printed on stderr
+//     $FF02 read       r1 address of a 0-terminated path, r2 buffer, r3 size,
+//                      r4 offset in the file, acc bytes read or $FFFF on error
+//     $FF03 time       acc and r1 the low and high word of the seconds since
+//                      the Unix epoch
+//
+// Other numbers go to the exception handler like any SYSCALL. Paths are
+// relative to the working directory of the host.
+
+// Numbers of the host calls
+pub const SEMIHOST_EXIT: u16 = 0xFF00;
+pub const SEMIHOST_PRINT: u16 = 0xFF01;
+pub const SEMIHOST_READ: u16 = 0xFF02;
+pub const SEMIHOST_TIME: u16 = 0xFF03;
+
+// Result of a failed host call
+pub const SEMIHOST_ERROR: u16 = 0xFFFF;
+
+// Check if a SYSCALL number is a host call
+pub fn is_host_call(number: u16) -> bool {
+    (SEMIHOST_EXIT..=SEMIHOST_TIME).contains(&number)
+}
+
+// Read up to size bytes of a file, from an offset
+pub fn read_file(path: &str, offset: u16, size: u16) -> Option<Vec<u8>> {
+    let mut file = File::open(path).ok()?;
+    file.seek(SeekFrom::Start(offset as u64)).ok()?;
+    let mut bytes = Vec::new();
+    file.take(size as u64).read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+// Seconds since the Unix epoch, wrapping after 2106
+pub fn time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{StopReason, CPU};
+    use crate::machine::{MachineBuilder, MachineConfig};
+    use crate::opcodes::*;
+    use std::fs;
+
+    // CPU of the default machine with semihosting, running a program with
+    // data at $0200
+    fn cpu(program: &[u8], data: &[u8]) -> CPU {
+        let mut image = program.to_vec();
+        image.resize(image.len().max(0x0200 + data.len()), 0);
+        image[0x0200..0x0200 + data.len()].copy_from_slice(data);
+        let mut cpu = MachineBuilder::new(MachineConfig::default_machine())
+            .with_program(image)
+            .build()
+            .unwrap();
+        cpu.enable_semihosting();
+        cpu
+    }
+
+    #[test]
+    fn exit_stops_with_the_status() {
+        let mut cpu = cpu(
+            &[MOV_LIT_REG, 0x00, 0x2A, R1, SYSCALL, 0xFF, 0x00, HLT],
+            &[],
+        );
+        assert_eq!(cpu.run(), StopReason::Exit(42));
+    }
+
+    #[test]
+    fn print_continues_after_the_call() {
+        let mut cpu = cpu(
+            &[MOV_LIT_REG, 0x02, 0x00, R1, SYSCALL, 0xFF, 0x01, HLT],
+            b"semihost print test\n\0",
+        );
+        assert_eq!(cpu.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn read_copies_a_file_to_memory() {
+        let path = std::env::temp_dir().join(format!("semihost-{}.txt", std::process::id()));
+        fs::write(&path, "hello").unwrap();
+        let mut data = path.to_string_lossy().into_owned().into_bytes();
+        data.push(0);
+
+        let program = [
+            &[MOV_LIT_REG, 0x02, 0x00, R1][..],
+            &[MOV_LIT_REG, 0x03, 0x00, R2],
+            &[MOV_LIT_REG, 0x00, 0x10, R3],
+            &[MOV_LIT_REG, 0x00, 0x01, R4],
+            &[SYSCALL, 0xFF, 0x02],
+            &[HLT],
+        ]
+        .concat();
+        let (mut cpu, mut missing) = (
+            cpu(&program, &data),
+            cpu(&program, b"/nonexistent/semihost\0"),
+        );
+        assert_eq!(cpu.run(), StopReason::Halted);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cpu.get_register("acc"), 4);
+        let bytes: Vec<u8> = (0x0300..0x0305)
+            .map(|address| cpu.device_mapper().get_byte(address))
+            .collect();
+        assert_eq!(bytes, b"ello\0");
+
+        // Missing files are an error
+        assert_eq!(missing.run(), StopReason::Halted);
+        assert_eq!(missing.get_register("acc"), SEMIHOST_ERROR);
+    }
+
+    #[test]
+    fn time_is_the_host_time() {
+        let mut cpu = cpu(&[SYSCALL, 0xFF, 0x03, HLT], &[]);
+        assert_eq!(cpu.run(), StopReason::Halted);
+        let guest = (cpu.get_register("r1") as u32) << 16 | cpu.get_register("acc") as u32;
+        assert!(time().wrapping_sub(guest) < 10);
+    }
+
+    #[test]
+    fn other_numbers_go_to_the_syscall_handler() {
+        let mut image = vec![SYSCALL, 0xFF, 0x10, HLT];
+        image.resize(0x0100, 0);
+        image.extend_from_slice(&[MOV_ADR_REG, ADR_OFFSET | FP, 0x00, 24, R1, HLT]);
+        image.resize(0x1008, 0);
+        image.extend_from_slice(&[0x01, 0x00]);
+        let mut cpu = cpu(&image, &[]);
+        assert_eq!(cpu.run(), StopReason::Halted);
+        assert_eq!(cpu.get_register("r1"), 0xFF10);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::entries::{entries, parse_u16};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+
+// Symbol files
+//
+// Written next to assembled programs so addresses can be mapped back to
+// labels and source lines. One entry per line, numbers are `$` hex or
+// decimal, `;` starts a comment:
+//
+//     label <name> <address> <size>     size 0 means up to the next label
+//     line <address> <file>:<line>      first address of a source line
+//     data <name> <address> <size>      bytes that are data, not code
+//
+// The location of a line entry is the rest of the line, so file names may
+// hold spaces.
+
+// Label
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub address: u16,
+    pub size: u16,
+}
+
+// Source line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub file: String,
+    pub line: usize,
+}
+
+// Data region
+#[derive(Clone, Debug, PartialEq)]
+pub struct Data {
+    pub name: String,
+    pub address: u16,
+    pub size: u16,
+}
+
+// Symbols class
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    labels: Vec<Label>,
+    lines: Vec<Line>,
+    data: Vec<Data>,
+}
+
+// Symbols implementation
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Load a symbol file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        Self::parse(&text).map_err(|error| format!("{}:{}", path, error))
+    }
+
+    // Parse the text of a symbol file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+        for entry in entries(text) {
+            let error = |message: &str| entry.error(message);
+            match entry.fields[..] {
+                ["label", name, address, size] => symbols.add_label(
+                    name,
+                    parse_u16(address).ok_or_else(|| error("invalid address"))?,
+                    parse_u16(size).ok_or_else(|| error("invalid size"))?,
+                ),
+                ["line", address, _, ..] => {
+                    let (file, line) = entry
+                        .rest(2)
+                        .rsplit_once(':')
+                        .ok_or_else(|| error("expected file:line"))?;
+                    symbols.add_line(
+                        parse_u16(address).ok_or_else(|| error("invalid address"))?,
+                        file,
+                        line.parse().map_err(|_| error("invalid line number"))?,
+                    );
+                }
+                ["data", name, address, size] => symbols.add_data(
+                    name,
+                    parse_u16(address).ok_or_else(|| error("invalid address"))?,
+                    parse_u16(size).ok_or_else(|| error("invalid size"))?,
+                ),
+                _ => return Err(entry.invalid()),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    // Write the symbol file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    // Add a label
+    pub fn add_label(&mut self, name: &str, address: u16, size: u16) {
+        let index = self
+            .labels
+            .partition_point(|label| label.address <= address);
+        self.labels.insert(
+            index,
+            Label {
+                name: name.to_string(),
+                address,
+                size,
+            },
+        );
+    }
+
+    // Add the first address of a source line
+    pub fn add_line(&mut self, address: u16, file: &str, line: usize) {
+        let index = self.lines.partition_point(|line| line.address <= address);
+        self.lines.insert(
+            index,
+            Line {
+                address,
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+
+    // Add a data region
+    pub fn add_data(&mut self, name: &str, address: u16, size: u16) {
+        let index = self.data.partition_point(|data| data.address <= address);
+        self.data.insert(
+            index,
+            Data {
+                name: name.to_string(),
+                address,
+                size,
+            },
+        );
+    }
+
+    // Labels sorted by address
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    // Find a label by name
+    pub fn find_label(&self, name: &str) -> Option<&Label> {
+        self.labels.iter().find(|label| label.name == name)
+    }
+
+    // Find the label an address belongs to and the offset into it
+    pub fn label(&self, address: u16) -> Option<(&Label, u16)> {
+        let index = self
+            .labels
+            .partition_point(|label| label.address <= address);
+        let label = self.labels[..index].last()?;
+        let offset = address - label.address;
+        if label.size != 0 && offset >= label.size {
+            return None;
+        }
+
+        Some((label, offset))
+    }
+
+    // Find the source line an address belongs to
+    pub fn line(&self, address: u16) -> Option<&Line> {
+        let index = self.lines.partition_point(|line| line.address <= address);
+        self.lines[..index].last()
+    }
+
+    // Find the data region an address is in
+    pub fn data(&self, address: u16) -> Option<&Data> {
+        self.data
+            .iter()
+            .find(|data| address >= data.address && address - data.address < data.size)
+    }
+
+    // Format an address as "0x0064 <main+12>"
+    pub fn format_address(&self, address: u16) -> String {
+        match self.label(address) {
+            Some((label, 0)) => format!("0x{:04X} <{}>", address, label.name),
+            Some((label, offset)) => format!("0x{:04X} <{}+{}>", address, label.name, offset),
+            None => format!("0x{:04X}", address),
+        }
+    }
+}
+
+// Write the symbol file format
+impl Display for Symbols {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for label in self.labels.iter() {
+            writeln!(
+                f,
+                "label {} ${:04X} ${:04X}",
+                label.name, label.address, label.size
+            )?;
+        }
+        for data in self.data.iter() {
+            writeln!(
+                f,
+                "data {} ${:04X} ${:04X}",
+                data.name, data.address, data.size
+            )?;
+        }
+        for line in self.lines.iter() {
+            writeln!(f, "line ${:04X} {}:{}", line.address, line.file, line.line)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_files_read_back_what_they_write() {
+        let mut symbols = Symbols::new();
+        symbols.add_label("main", 0x0010, 0);
+        symbols.add_data("table", 0x0100, 8);
+        symbols.add_line(0x0010, "my dir/p.asm", 3);
+
+        let parsed = Symbols::parse(&symbols.to_string()).unwrap();
+        assert_eq!(parsed.labels(), symbols.labels());
+        assert_eq!(parsed.data(0x0104), symbols.data(0x0104));
+        assert_eq!(
+            parsed.line(0x0010),
+            Some(&Line {
+                address: 0x0010,
+                file: String::from("my dir/p.asm"),
+                line: 3
+            })
+        );
+    }
+}